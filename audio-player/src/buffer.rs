@@ -1,114 +1,189 @@
-use std::borrow::Cow;
-
-use symphonia::core::{
-    audio::{AudioBuffer, AudioBufferRef, Signal},
-    conv::IntoSample,
-    sample::Sample,
-};
-
-pub(super) enum SampleBuffer<'b> {
-    Buf(SampleBuf),
-    BufRef(&'b SampleBuf),
-    Symphonia(AudioBufferRef<'b>),
-}
-
-/// planar format
-pub(super) struct SampleBuf {
-    // TODO: support other types
-    buffer: Vec<Vec<f64>>,
-}
-
-impl SampleBuf {
-    pub(super) fn new() -> Self {
-        Self { buffer: vec![] }
-    }
-
-    pub(super) fn with_capacity(channels: usize, samples_per_channel: usize) -> Self {
-        Self {
-            buffer: (0..channels)
-                .map(|_| Vec::with_capacity(samples_per_channel))
-                .collect(),
-        }
-    }
-
-    pub(super) fn with_buffer(buffer: Vec<Vec<f64>>) -> Self {
-        Self { buffer }
-    }
-
-    pub(super) fn resize(&mut self, channels: usize, samples_per_channel: usize) {
-        self.buffer.truncate(channels);
-        self.buffer.iter_mut().for_each(|b| {
-            b.resize(samples_per_channel, 0.0);
-        });
-    }
-
-    pub(super) fn channels(&self) -> usize {
-        self.buffer.len()
-    }
-
-    pub(super) fn frames(&self) -> usize {
-        match self.buffer.get(0) {
-            Some(b) => b.len(),
-            None => 0,
-        }
-    }
-
-    pub(super) fn channel_samples(&self) -> impl Iterator<Item = &[f64]> + '_ {
-        self.buffer.iter().map(|b| b.as_slice())
-    }
-
-    pub(super) fn channel_samples_mut(&mut self) -> impl Iterator<Item = &mut [f64]> + '_ {
-        self.buffer.iter_mut().map(|b| b.as_mut())
-    }
-
-    pub(super) fn samples(&self, channel: usize) -> Option<&[f64]> {
-        self.buffer.get(channel).map(|b| b.as_slice())
-    }
-
-    pub(super) fn samples_mut(&mut self, channel: usize) -> Option<&mut [f64]> {
-        self.buffer.get_mut(channel).map(|b| b.as_mut())
-    }
-
-    pub(super) fn interleaved(&self) -> impl Iterator<Item = f64> + '_ {
-        (0..self.frames()).flat_map(|f| self.buffer.iter().map(move |b| b[f]))
-    }
-}
-
-impl AsRef<[Vec<f64>]> for SampleBuf {
-    fn as_ref(&self) -> &[Vec<f64>] {
-        &self.buffer
-    }
-}
-
-impl AsMut<[Vec<f64>]> for SampleBuf {
-    fn as_mut(&mut self) -> &mut [Vec<f64>] {
-        &mut self.buffer
-    }
-}
-
-impl<T: Sample + IntoSample<f64>> From<Cow<'_, AudioBuffer<T>>> for SampleBuf {
-    /// This will clone
-    fn from(buffer: Cow<'_, AudioBuffer<T>>) -> Self {
-        let mut s = Self::with_capacity(buffer.spec().channels.count(), buffer.frames());
-        for (c, channel) in s.buffer.iter_mut().enumerate() {
-            channel.extend(buffer.chan(c).iter().map(|&s| s.into_sample()))
-        }
-        s
-    }
-}
-
-pub(super) trait AsSlice<Item> {
-    fn as_slice(&self) -> &[Item];
-}
-
-impl<T> AsSlice<T> for &[T] {
-    fn as_slice(&self) -> &[T] {
-        self
-    }
-}
-
-impl<T> AsSlice<T> for Vec<T> {
-    fn as_slice(&self) -> &[T] {
-        self
-    }
-}
\ No newline at end of file
+use std::borrow::Cow;
+
+use symphonia::core::{
+    audio::{AudioBuffer, AudioBufferRef, Signal},
+    conv::IntoSample,
+    sample::Sample,
+};
+
+pub(super) enum SampleBuffer<'b> {
+    Buf(SampleBuf),
+    Symphonia(AudioBufferRef<'b>),
+}
+
+impl SampleBuffer<'_> {
+    pub(super) fn frames(&self) -> usize {
+        match self {
+            SampleBuffer::Buf(buffer) => buffer.frames(),
+            SampleBuffer::Symphonia(buffer) => buffer.frames(),
+        }
+    }
+}
+
+/// planar format, one `Vec` of samples per channel
+#[derive(Clone, Default)]
+pub struct SampleBuf {
+    // TODO: support other types
+    buffer: Vec<Vec<f64>>,
+}
+
+impl SampleBuf {
+    pub fn new() -> Self {
+        Self { buffer: vec![] }
+    }
+
+    pub(crate) fn with_capacity(channels: usize, samples_per_channel: usize) -> Self {
+        Self {
+            buffer: (0..channels)
+                .map(|_| Vec::with_capacity(samples_per_channel))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn with_buffer(buffer: Vec<Vec<f64>>) -> Self {
+        Self { buffer }
+    }
+
+    pub(crate) fn resize(&mut self, channels: usize, samples_per_channel: usize) {
+        self.buffer.truncate(channels);
+        self.buffer.iter_mut().for_each(|b| {
+            b.resize(samples_per_channel, 0.0);
+        });
+    }
+
+    /// Drop `frames` frames from the start of every channel
+    pub(super) fn drain_front(&mut self, frames: usize) {
+        self.buffer.iter_mut().for_each(|b| {
+            b.drain(..frames.min(b.len()));
+        });
+    }
+
+    /// Add the frames of `other` to the end, it must have the same number of channels
+    pub(super) fn append(&mut self, other: &SampleBuf) {
+        self.buffer
+            .iter_mut()
+            .zip(&other.buffer)
+            .for_each(|(b, o)| b.extend_from_slice(o));
+    }
+
+    /// Move the frames from `frames` on into a new buffer
+    pub(super) fn split_off(&mut self, frames: usize) -> SampleBuf {
+        Self {
+            buffer: self
+                .buffer
+                .iter_mut()
+                .map(|b| b.split_off(frames.min(b.len())))
+                .collect(),
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn frames(&self) -> usize {
+        match self.buffer.get(0) {
+            Some(b) => b.len(),
+            None => 0,
+        }
+    }
+
+    pub fn channel_samples(&self) -> impl Iterator<Item = &[f64]> + '_ {
+        self.buffer.iter().map(|b| b.as_slice())
+    }
+
+    pub fn channel_samples_mut(&mut self) -> impl Iterator<Item = &mut [f64]> + '_ {
+        self.buffer.iter_mut().map(|b| b.as_mut())
+    }
+
+    pub fn samples(&self, channel: usize) -> Option<&[f64]> {
+        self.buffer.get(channel).map(|b| b.as_slice())
+    }
+
+    pub fn samples_mut(&mut self, channel: usize) -> Option<&mut [f64]> {
+        self.buffer.get_mut(channel).map(|b| b.as_mut())
+    }
+
+    /// Mutable access to whole channels, callers must keep them the same length
+    pub(crate) fn buffer_mut(&mut self) -> &mut [Vec<f64>] {
+        &mut self.buffer
+    }
+
+    /// The channels mixed into each of `channels` channels, mono goes to every channel and
+    /// extra channels to `channel % channels`. Channels that are missing get none
+    pub(super) fn channel_mix(&self, channels: usize) -> Vec<Vec<&[f64]>> {
+        (0..channels)
+            .map(|c| match self.channels() {
+                1 => self.samples(0).into_iter().collect(),
+                n => (c..n)
+                    .step_by(channels)
+                    .filter_map(|c| self.samples(c))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    pub fn interleaved(&self) -> impl Iterator<Item = f64> + '_ {
+        (0..self.frames()).flat_map(|f| self.buffer.iter().map(move |b| b[f]))
+    }
+}
+
+impl AsRef<[Vec<f64>]> for SampleBuf {
+    fn as_ref(&self) -> &[Vec<f64>] {
+        &self.buffer
+    }
+}
+
+impl<T: Sample + IntoSample<f64>> From<Cow<'_, AudioBuffer<T>>> for SampleBuf {
+    /// This will clone
+    fn from(buffer: Cow<'_, AudioBuffer<T>>) -> Self {
+        let mut s = Self::with_capacity(buffer.spec().channels.count(), buffer.frames());
+        for (c, channel) in s.buffer.iter_mut().enumerate() {
+            channel.extend(buffer.chan(c).iter().map(|&s| s.into_sample()))
+        }
+        s
+    }
+}
+
+impl From<SampleBuffer<'_>> for SampleBuf {
+    fn from(buffer: SampleBuffer<'_>) -> Self {
+        match buffer {
+            SampleBuffer::Buf(buffer) => buffer,
+            SampleBuffer::Symphonia(buffer) => buffer.into(),
+        }
+    }
+}
+
+impl From<AudioBufferRef<'_>> for SampleBuf {
+    /// This will clone
+    fn from(buffer: AudioBufferRef<'_>) -> Self {
+        match buffer {
+            AudioBufferRef::U8(buffer) => buffer.into(),
+            AudioBufferRef::U16(buffer) => buffer.into(),
+            AudioBufferRef::U24(buffer) => buffer.into(),
+            AudioBufferRef::U32(buffer) => buffer.into(),
+            AudioBufferRef::S8(buffer) => buffer.into(),
+            AudioBufferRef::S16(buffer) => buffer.into(),
+            AudioBufferRef::S24(buffer) => buffer.into(),
+            AudioBufferRef::S32(buffer) => buffer.into(),
+            AudioBufferRef::F32(buffer) => buffer.into(),
+            AudioBufferRef::F64(buffer) => buffer.into(),
+        }
+    }
+}
+
+pub(super) trait AsSlice<Item> {
+    fn as_slice(&self) -> &[Item];
+}
+
+impl<T> AsSlice<T> for &[T] {
+    fn as_slice(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T> AsSlice<T> for Vec<T> {
+    fn as_slice(&self) -> &[T] {
+        self
+    }
+}
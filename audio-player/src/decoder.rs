@@ -1,148 +1,546 @@
-use std::{fs::File, path::Path, time::Duration};
-
-use symphonia::core::{
-    codecs::{CodecParameters, Decoder, DecoderOptions},
-    formats::{FormatOptions, FormatReader, Packet, SeekMode, SeekTo},
-    io::MediaSourceStream,
-    meta::MetadataOptions,
-    probe::Hint,
-    units::TimeStamp,
-};
-use tracing::info;
-
-use crate::{buffer::SampleBuffer, Track, TrackDetails};
-
-#[derive(Debug, thiserror::Error)]
-pub(super) enum DecoderError {
-    #[error("TrackUnavailable")]
-    TrackUnavailable,
-    #[error("IO Error {0}")]
-    IO(#[from] std::io::Error),
-    #[error("SymphoniaError {0}")]
-    Symphonia(#[from] symphonia::core::errors::Error),
-    #[error("Failed to Calculate Progress")]
-    ProgressUnavailable,
-}
-
-pub(super) fn decode<P: AsRef<Path>>(path: &P) -> Result<Track, DecoderError> {
-    let mss = MediaSourceStream::new(Box::new(File::open(path.as_ref())?), Default::default());
-    let mut hint = Hint::new();
-    if let Some(ext) = path.as_ref().extension() {
-        hint.with_extension(&ext.to_string_lossy());
-    }
-    let mut probed = symphonia::default::get_probe().format(
-        &hint,
-        mss,
-        &FormatOptions::default(),
-        &MetadataOptions::default(),
-    )?;
-
-    let details = TrackDetails::new(&mut probed);
-
-    let track = probed
-        .format
-        .default_track()
-        .ok_or(DecoderError::TrackUnavailable)?;
-    let decoder =
-        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
-    let progress = decoder.codec_params().start_ts;
-    Ok(Track {
-        decoded: DecodedTrack {
-            reader: probed.format,
-            decoder,
-            progress,
-            next_packet: None,
-        },
-        details,
-    })
-}
-
-pub(super) struct DecodedTrack {
-    reader: Box<dyn FormatReader>,
-    decoder: Box<dyn Decoder>,
-    progress: TimeStamp,
-    // buffer next_packet call to accurately determine progress after seek call
-    next_packet: Option<Packet>,
-}
-
-impl DecodedTrack {
-    pub(super) fn codec_params(&self) -> &CodecParameters {
-        self.decoder.codec_params()
-    }
-
-    pub(super) fn next(&mut self) -> Result<SampleBuffer, DecoderError> {
-        let packet = match self.next_packet.take() {
-            Some(packet) => {
-                self.next_packet = None;
-                packet
-            }
-            None => self.next_packet()?,
-        };
-        self.progress = packet.ts();
-
-        while !self.reader.metadata().is_latest() {
-            self.reader.metadata().pop();
-            if let Some(metadata) = self.reader.metadata().current() {
-                metadata.tags().iter().for_each(|tag| match tag {
-                    _ => info!("{} {:?} {}", tag.key, tag.std_key, tag.value),
-                });
-            }
-        }
-
-        // let decoded = match self.decoder.decode(&packet)? {
-        //     AudioBufferRef::U8(buffer) => buffer.into(),
-        //     AudioBufferRef::U16(buffer) => buffer.into(),
-        //     AudioBufferRef::U24(buffer) => buffer.into(),
-        //     AudioBufferRef::U32(buffer) => buffer.into(),
-        //     AudioBufferRef::S8(buffer) => buffer.into(),
-        //     AudioBufferRef::S16(buffer) => buffer.into(),
-        //     AudioBufferRef::S24(buffer) => buffer.into(),
-        //     AudioBufferRef::S32(buffer) => buffer.into(),
-        //     AudioBufferRef::F32(buffer) => buffer.into(),
-        //     AudioBufferRef::F64(buffer) => buffer.into(),
-        // };
-        // Ok(SampleBuffer::Buf(decoded))
-        let decoded = self.decoder.decode(&packet)?;
-        Ok(SampleBuffer::Symphonia(decoded))
-    }
-
-    fn next_packet(&mut self) -> Result<Packet, DecoderError> {
-        let packet = loop {
-            let packet = self.reader.next_packet()?;
-            if packet.track_id()
-                == self
-                    .reader
-                    .default_track()
-                    .ok_or(DecoderError::TrackUnavailable)?
-                    .id
-            {
-                break packet;
-            }
-        };
-        self.progress = packet.ts();
-        Ok(packet)
-    }
-
-    pub(super) fn seek(&mut self, progress: Duration) -> Result<(), DecoderError> {
-        self.reader.seek(
-            SeekMode::Accurate,
-            SeekTo::Time {
-                time: progress.into(),
-                track_id: None,
-            },
-        )?;
-        self.decoder.reset();
-        self.next_packet = Some(self.next_packet()?);
-        Ok(())
-    }
-
-    pub(super) fn progress(&self) -> Result<Duration, DecoderError> {
-        Ok(self
-            .decoder
-            .codec_params()
-            .time_base
-            .ok_or(DecoderError::ProgressUnavailable)?
-            .calc_time(self.progress)
-            .into())
-    }
-}
+use std::{fs::File, path::Path, sync::Arc, time::Duration};
+
+use symphonia::{
+    core::{
+        codecs::{CodecParameters, Decoder, DecoderOptions},
+        errors::Error,
+        formats::{util::trim_packet, FormatOptions, FormatReader, Packet, SeekMode, SeekTo},
+        io::{MediaSource, MediaSourceStream, ReadBytes, SeekBuffered},
+        meta::{MetadataLog, MetadataOptions, MetadataRevision, Value},
+        probe::{Descriptor, Instantiate, QueryDescriptor},
+        units::{TimeBase, TimeStamp},
+    },
+    default::formats::{
+        AdtsReader, AiffReader, CafReader, FlacReader, IsoMp4Reader, MkvReader, MpaReader,
+        OggReader, WavReader,
+    },
+};
+use tracing::{debug, info};
+
+use crate::{
+    buffer::{SampleBuf, SampleBuffer},
+    http::HttpBuffer,
+    StreamInfo, Track, TrackDetails, TrackSource,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub(super) enum DecoderError {
+    #[error("TrackUnavailable")]
+    TrackUnavailable,
+    #[error("IO Error {0}")]
+    IO(#[from] std::io::Error),
+    #[error("SymphoniaError {0}")]
+    Symphonia(#[from] symphonia::core::errors::Error),
+    #[error("Failed to Calculate Progress")]
+    ProgressUnavailable,
+}
+
+impl DecoderError {
+    /// Reached the end of the stream, which symphonia reports as an error
+    pub(super) fn end_of_stream(&self) -> bool {
+        match self {
+            DecoderError::Symphonia(Error::IoError(err)) => {
+                err.kind() == std::io::ErrorKind::UnexpectedEof
+            }
+            _ => false,
+        }
+    }
+
+    /// Only the last packet was bad, decoding can continue with the next
+    pub(super) fn packet_error(&self) -> bool {
+        matches!(self, DecoderError::Symphonia(Error::DecodeError(_)))
+    }
+}
+
+pub(super) fn decode<P: AsRef<Path>>(path: &P) -> Result<Track, DecoderError> {
+    let extension = path.as_ref().extension().map(|ext| ext.to_string_lossy());
+    decode_source(
+        Box::new(File::open(path.as_ref())?),
+        extension.as_deref(),
+        TrackSource::File(path.as_ref().to_path_buf()),
+    )
+}
+
+/// `hint` is the file extension or MIME type of the format, which decides between formats that
+/// start the same
+pub(super) fn decode_source(
+    source: Box<dyn MediaSource>,
+    hint: Option<&str>,
+    track_source: TrackSource,
+) -> Result<Track, DecoderError> {
+    let file_size = source.byte_len();
+    let mut probed = Probed::new(MediaSourceStream::new(source, Default::default()), hint)?;
+
+    let mut details = TrackDetails::new(&mut probed);
+    let gapless = Gapless::new(&mut probed);
+
+    let track = probed
+        .format
+        .default_track()
+        .ok_or(DecoderError::TrackUnavailable)?;
+    if let Some(gapless) = gapless {
+        if let Some(time_base) = track.codec_params.time_base {
+            details.set_duration(time_base.calc_time(gapless.frames).into());
+        }
+    }
+    let stream_info = StreamInfo::new(
+        &track.codec_params,
+        probed.container,
+        file_size,
+        // the bitrate is of what comes after metadata in front of the container
+        file_size.map(|size| size.saturating_sub(probed.offset)),
+        details.duration().copied(),
+    );
+    let decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+    let progress = decoder.codec_params().start_ts;
+    Ok(Track {
+        decoded: DecodedTrack {
+            reader: probed.format,
+            decoder,
+            progress,
+            next_packet: None,
+            gapless,
+            metadata: None,
+            bitrate: Bitrate::default(),
+            http: None,
+        },
+        source: track_source,
+        details,
+        stream_info,
+    })
+}
+
+/// [QueryDescriptor::query] of a format reader
+type ReaderQuery = fn() -> &'static [Descriptor];
+
+/// A stream opened by the probe. This does what [symphonia::core::probe::Probe::format] does
+/// to also learn which container was found, and to make use of the hint which it ignores
+pub(super) struct Probed {
+    pub(super) format: Box<dyn FormatReader>,
+    /// Metadata in front of the container, like ID3 tags
+    pub(super) metadata: Option<MetadataLog>,
+    container: Option<&'static str>,
+    /// Bytes in front of the container
+    offset: u64,
+}
+
+impl Probed {
+    /// The readers in the order [symphonia::default::get_probe] looks for their markers, with
+    /// the name of their container
+    const CONTAINERS: [(ReaderQuery, &'static str); 9] = [
+        (AdtsReader::query, "ADTS"),
+        (CafReader::query, "CAF"),
+        (FlacReader::query, "FLAC"),
+        (IsoMp4Reader::query, "MP4"),
+        (MpaReader::query, "MPEG audio"),
+        (AiffReader::query, "AIFF"),
+        (WavReader::query, "WAVE"),
+        (OggReader::query, "Ogg"),
+        (MkvReader::query, "Matroska"),
+    ];
+
+    fn new(mut mss: MediaSourceStream, hint: Option<&str>) -> Result<Self, DecoderError> {
+        let probe = symphonia::default::get_probe();
+        let mut metadata = MetadataLog::default();
+        loop {
+            match probe.next(&mut mss)? {
+                Instantiate::Format(instantiate) => {
+                    let offset = mss.pos();
+                    // the probe leaves the stream at the marker it found
+                    let mut marker = [0; 16];
+                    mss.read_buf_exact(&mut marker)?;
+                    mss.seek_buffered_rev(marker.len());
+                    let (instantiate, container) = match Self::reader(&marker, hint) {
+                        Some((Instantiate::Format(instantiate), name)) => (instantiate, Some(name)),
+                        _ => (instantiate, None),
+                    };
+                    let format = instantiate(
+                        mss,
+                        &FormatOptions {
+                            // readers that support it trim encoder delay and padding themselves
+                            enable_gapless: true,
+                            ..Default::default()
+                        },
+                    )?;
+                    let found = metadata.metadata().current().is_some();
+                    let metadata = found.then_some(metadata);
+                    return Ok(Self {
+                        format,
+                        metadata,
+                        container,
+                        offset,
+                    });
+                }
+                Instantiate::Metadata(instantiate) => {
+                    let mut reader = instantiate(&MetadataOptions::default());
+                    metadata.push(reader.read_all(&mut mss)?);
+                }
+            }
+        }
+    }
+
+    /// The reader of the format with a marker at the start of `marker` and the name of its
+    /// container. Of several such formats the one `hint` is an extension or MIME type of is
+    /// taken, otherwise the first like the probe does
+    fn reader(marker: &[u8], hint: Option<&str>) -> Option<(Instantiate, &'static str)> {
+        let found = Self::CONTAINERS
+            .iter()
+            .flat_map(|&(query, name)| query().iter().map(move |d| (d, name)))
+            .filter(|(d, _)| d.markers.iter().any(|m| marker.starts_with(m)))
+            .collect::<Vec<_>>();
+        let hinted = hint.and_then(|hint| {
+            found.iter().find(|(d, _)| {
+                d.extensions
+                    .iter()
+                    .chain(d.mime_types)
+                    .any(|e| e.eq_ignore_ascii_case(hint))
+            })
+        });
+        hinted
+            .or(found.first())
+            .map(|(descriptor, name)| (descriptor.inst, *name))
+    }
+}
+
+/// Encoder delay and padding for formats whose reader does not trim them, taken from the
+/// iTunSMPB tag written by iTunes and most AAC encoders
+#[derive(Debug, Clone, Copy)]
+struct Gapless {
+    /// Frames of encoder priming at the start, in `TimeBase` units
+    delay: u32,
+    /// Frames of actual audio, in `TimeBase` units
+    frames: u64,
+}
+
+impl Gapless {
+    fn new(probed: &mut Probed) -> Option<Self> {
+        let track = probed.format.default_track()?;
+        // the reader already handles delay and padding
+        if track.codec_params.delay.is_some() {
+            return None;
+        }
+        let sample_rate = track.codec_params.sample_rate? as u64;
+        let time_base = track.codec_params.time_base?;
+        let to_ts =
+            |frames: u64| frames * time_base.denom as u64 / (time_base.numer as u64 * sample_rate);
+
+        fn find_smpb(metadata: &MetadataRevision) -> Option<String> {
+            metadata.tags().iter().find_map(|tag| {
+                if !tag.key.to_lowercase().ends_with("itunsmpb") {
+                    return None;
+                }
+                match &tag.value {
+                    Value::String(v) => Some(v.clone()),
+                    _ => None,
+                }
+            })
+        }
+        let mut smpb = probed.format.metadata().current().and_then(find_smpb);
+        if smpb.is_none() {
+            if let Some(metadata) = probed.metadata.as_mut() {
+                smpb = metadata.metadata().current().and_then(find_smpb);
+            }
+        }
+
+        let (delay, frames) = Self::parse(&smpb?)?;
+        debug!("iTunSMPB: delay {} frames {}", delay, frames);
+        Some(Self {
+            delay: to_ts(delay) as u32,
+            frames: to_ts(frames),
+        })
+    }
+
+    /// Delay and number of frames in an iTunSMPB value, `None` if it has no frames
+    fn parse(smpb: &str) -> Option<(u64, u64)> {
+        // " 00000000 00000840 000001CA 00000000003F31F6 ..." -> delay, padding, frames
+        let mut fields = smpb.split_whitespace().skip(1);
+        let delay = u64::from_str_radix(fields.next()?, 16).ok()?;
+        let _padding = u64::from_str_radix(fields.next()?, 16).ok()?;
+        let frames = u64::from_str_radix(fields.next()?, 16).ok()?;
+        (frames != 0).then_some((delay, frames))
+    }
+}
+
+pub(super) struct DecodedTrack {
+    reader: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    progress: TimeStamp,
+    // buffer next_packet call to accurately determine progress after seek call
+    next_packet: Option<Packet>,
+    gapless: Option<Gapless>,
+    /// Latest metadata revision found in the stream and not yet taken
+    metadata: Option<MetadataRevision>,
+    bitrate: Bitrate,
+    /// Download of tracks played from a URL
+    http: Option<Arc<HttpBuffer>>,
+}
+
+/// Bitrate of the packets read in the last second or so
+#[derive(Default)]
+struct Bitrate {
+    /// Bytes of the packets read since the last measurement
+    bytes: u64,
+    /// Duration of those packets in `TimeBase` units
+    duration: u64,
+    bitrate: Option<u32>,
+}
+
+impl Bitrate {
+    /// Seconds of packets each measurement is taken over
+    const WINDOW: f64 = 1.0;
+
+    fn add(&mut self, packet: &Packet, time_base: Option<TimeBase>) {
+        let Some(time_base) = time_base else {
+            return;
+        };
+        self.bytes += packet.buf().len() as u64;
+        self.duration += packet.dur();
+        let time = time_base.calc_time(self.duration);
+        let seconds = time.seconds as f64 + time.frac;
+        if seconds >= Self::WINDOW {
+            self.bitrate = Some((self.bytes as f64 * 8.0 / seconds) as u32);
+            self.bytes = 0;
+            self.duration = 0;
+        }
+    }
+
+    /// Start measuring over, keeping the last measurement until there is a new one
+    fn restart(&mut self) {
+        self.bytes = 0;
+        self.duration = 0;
+    }
+}
+
+impl DecodedTrack {
+    pub(super) fn set_http(&mut self, http: Arc<HttpBuffer>) {
+        self.http = Some(http);
+    }
+
+    pub(super) fn http(&self) -> Option<Arc<HttpBuffer>> {
+        self.http.clone()
+    }
+
+    pub(super) fn codec_params(&self) -> &CodecParameters {
+        self.decoder.codec_params()
+    }
+
+    pub(super) fn next(&mut self) -> Result<SampleBuffer, DecoderError> {
+        let packet = match self.next_packet.take() {
+            Some(packet) => {
+                self.next_packet = None;
+                packet
+            }
+            None => self.next_packet()?,
+        };
+        self.progress = packet.ts();
+        self.bitrate
+            .add(&packet, self.decoder.codec_params().time_base);
+
+        while !self.reader.metadata().is_latest() {
+            self.reader.metadata().pop();
+            if let Some(metadata) = self.reader.metadata().current() {
+                metadata.tags().iter().for_each(|tag| match tag {
+                    _ => info!("{} {:?} {}", tag.key, tag.std_key, tag.value),
+                });
+                self.metadata = Some(metadata.clone());
+            }
+        }
+        // radio streams send the song playing next to the audio rather than in the container
+        if let Some(metadata) = self.http.as_ref().and_then(|http| http.take_metadata()) {
+            self.metadata = Some(metadata);
+        }
+
+        // let decoded = match self.decoder.decode(&packet)? {
+        //     AudioBufferRef::U8(buffer) => buffer.into(),
+        //     AudioBufferRef::U16(buffer) => buffer.into(),
+        //     AudioBufferRef::U24(buffer) => buffer.into(),
+        //     AudioBufferRef::U32(buffer) => buffer.into(),
+        //     AudioBufferRef::S8(buffer) => buffer.into(),
+        //     AudioBufferRef::S16(buffer) => buffer.into(),
+        //     AudioBufferRef::S24(buffer) => buffer.into(),
+        //     AudioBufferRef::S32(buffer) => buffer.into(),
+        //     AudioBufferRef::F32(buffer) => buffer.into(),
+        //     AudioBufferRef::F64(buffer) => buffer.into(),
+        // };
+        // Ok(SampleBuffer::Buf(decoded))
+        let decoded = self.decoder.decode(&packet)?;
+
+        // trim delay and padding if the decoder did not do it already
+        let frames = decoded.frames() as u64;
+        if packet.trim_start() == 0 && packet.trim_end() == 0 || frames <= packet.dur() {
+            return Ok(SampleBuffer::Symphonia(decoded));
+        }
+        let trim_start = (packet.trim_start() as usize).min(frames as usize);
+        let mut buffer = SampleBuf::from(decoded);
+        buffer.drain_front(trim_start);
+        buffer.resize(
+            buffer.channels(),
+            (frames as usize - trim_start).saturating_sub(packet.trim_end() as usize),
+        );
+        Ok(SampleBuffer::Buf(buffer))
+    }
+
+    /// Bits per second of the stream around the packet last decoded, in the last second or so
+    pub(super) fn bitrate(&self) -> Option<u32> {
+        self.bitrate.bitrate
+    }
+
+    /// Metadata that changed while playing, e.g. the song title of a radio stream
+    pub(super) fn take_metadata(&mut self) -> Option<MetadataRevision> {
+        self.metadata.take()
+    }
+
+    fn next_packet(&mut self) -> Result<Packet, DecoderError> {
+        let packet = loop {
+            let packet = self.reader.next_packet()?;
+            if packet.track_id()
+                == self
+                    .reader
+                    .default_track()
+                    .ok_or(DecoderError::TrackUnavailable)?
+                    .id
+            {
+                break packet;
+            }
+        };
+        let mut packet = packet;
+        if let Some(gapless) = self.gapless {
+            trim_packet(&mut packet, gapless.delay, Some(gapless.frames));
+        }
+        self.progress = packet.ts();
+        Ok(packet)
+    }
+
+    pub(super) fn seek(&mut self, progress: Duration) -> Result<(), DecoderError> {
+        let seek_to = match (self.gapless, self.decoder.codec_params().time_base) {
+            // timestamps in the stream still include the encoder delay
+            (Some(gapless), Some(time_base)) => SeekTo::TimeStamp {
+                ts: time_base.calc_timestamp(progress.into()) + gapless.delay as u64,
+                track_id: self
+                    .reader
+                    .default_track()
+                    .ok_or(DecoderError::TrackUnavailable)?
+                    .id,
+            },
+            _ => SeekTo::Time {
+                time: progress.into(),
+                track_id: None,
+            },
+        };
+        self.reader.seek(SeekMode::Accurate, seek_to)?;
+        self.decoder.reset();
+        self.bitrate.restart();
+        self.next_packet = Some(self.next_packet()?);
+        Ok(())
+    }
+
+    /// Time left until the end of the track, if the length of the track is known
+    pub(super) fn remaining(&self) -> Option<Duration> {
+        let codec_params = self.decoder.codec_params();
+        let end = match self.gapless {
+            Some(gapless) => gapless.frames,
+            None => codec_params.start_ts + codec_params.n_frames?,
+        };
+        Some(
+            codec_params
+                .time_base?
+                .calc_time(end.saturating_sub(self.progress))
+                .into(),
+        )
+    }
+
+    pub(super) fn progress(&self) -> Result<Duration, DecoderError> {
+        Ok(self
+            .decoder
+            .codec_params()
+            .time_base
+            .ok_or(DecoderError::ProgressUnavailable)?
+            .calc_time(self.progress)
+            .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::track::tests::wav;
+
+    /// ID3v2.3 tag with the iTunSMPB comment of iTunes
+    fn smpb_tag(smpb: &str) -> Vec<u8> {
+        let mut frame = vec![0];
+        frame.extend_from_slice(b"iTunSMPB\0");
+        frame.extend_from_slice(smpb.as_bytes());
+        let mut tag = b"TXXX".to_vec();
+        tag.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        tag.extend_from_slice(&[0, 0]);
+        tag.extend(frame);
+        let size = tag.len() as u32;
+        // the size of the tag is stored in 7 bits per byte
+        let size = [size >> 21, size >> 14, size >> 7, size].map(|b| (b & 0x7f) as u8);
+        [b"ID3\x03\0\0".as_slice(), &size, &tag].concat()
+    }
+
+    /// Every frame of `track`, with the first channel only
+    fn decode_all(track: &mut Track) -> Vec<f64> {
+        let mut samples = vec![];
+        loop {
+            match track.decoded.next() {
+                Ok(buffer) => {
+                    samples.extend_from_slice(SampleBuf::from(buffer).samples(0).unwrap())
+                }
+                Err(err) if err.end_of_stream() => break,
+                Err(err) => panic!("{}", err),
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn parse_smpb() {
+        let smpb = " 00000000 00000840 000001CA 00000000003F31F6 00000000 00000000";
+        assert_eq!(Gapless::parse(smpb), Some((0x840, 0x3F31F6)));
+        assert_eq!(Gapless::parse(" 00000000 00000840 000001CA"), None);
+        assert_eq!(
+            Gapless::parse(" 00000000 00000840 000001CA 0000000000000000"),
+            None
+        );
+        assert_eq!(Gapless::parse(" 00000000 00000840 000001CA 0000000X"), None);
+        assert_eq!(Gapless::parse(""), None);
+    }
+
+    #[test]
+    fn trim() {
+        // a frame counter so the trimmed frames can be told apart
+        let frames = 10000;
+        let counter = (0..frames)
+            .map(|i| i as f32 / frames as f32)
+            .collect::<Vec<_>>();
+        let (delay, padding) = (1000, 1500);
+        // in hexadecimal
+        let smpb = " 00000000 000003E8 000005DC 0000000000001D4C";
+        let bytes = [smpb_tag(smpb), wav(&counter, 1, 8000)].concat();
+        let mut track =
+            decode_source(Box::new(Cursor::new(bytes)), None, TrackSource::Bytes).unwrap();
+        assert!(track.decoded.gapless.is_some());
+        let length = frames - delay - padding;
+        let duration = Duration::from_secs_f64(length as f64 / 8000.0);
+        assert_eq!(track.details().duration(), Some(&duration));
+
+        let samples = decode_all(&mut track);
+        assert_eq!(samples.len(), length);
+        assert_eq!(samples[0], counter[delay] as f64);
+        assert_eq!(samples[length - 1], counter[delay + length - 1] as f64);
+
+        // seeking is relative to the trimmed start, it lands at the start of a packet
+        track.decoded.seek(Duration::from_millis(500)).unwrap();
+        let progress = track.decoded.progress().unwrap().as_secs_f64() * 8000.0;
+        let progress = progress.round() as usize;
+        assert!(progress <= 4000);
+        let samples = decode_all(&mut track);
+        assert_eq!(samples.len(), length - progress);
+        assert_eq!(samples[0], counter[delay + progress] as f64);
+    }
+}
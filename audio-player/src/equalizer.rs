@@ -0,0 +1,486 @@
+use std::{fmt::Display, time::Duration};
+
+use crate::{buffer::SampleBuf, filter::Biquad, processor::AudioProcessor};
+
+/// Settings of the equalizer, changed through [crate::AudioPlayerController::set_equalizer]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Equalizer {
+    /// Gain in dB applied before the bands, lower it to make room for boosts
+    pub preamp: f64,
+    /// Applied one after the other, flat without any
+    pub bands: Vec<EqualizerBand>,
+}
+
+impl Equalizer {
+    /// Center frequencies of the bands of [Equalizer::graphic], an octave apart
+    pub const GRAPHIC_FREQUENCIES: [f64; 10] = [
+        31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+    ];
+    /// Bandwidth of an octave
+    const GRAPHIC_Q: f64 = std::f64::consts::SQRT_2;
+
+    /// 10-band graphic equalizer with the gain in dB of each of [Equalizer::GRAPHIC_FREQUENCIES]
+    pub fn graphic(gains: [f64; 10]) -> Self {
+        let bands = Self::GRAPHIC_FREQUENCIES
+            .iter()
+            .zip(gains)
+            .map(|(&frequency, gain)| EqualizerBand::peaking(frequency, gain, Self::GRAPHIC_Q))
+            .collect();
+        Self { preamp: 0.0, bands }
+    }
+
+    /// Equalizer made of any number of bands
+    pub fn parametric(bands: Vec<EqualizerBand>) -> Self {
+        Self { preamp: 0.0, bands }
+    }
+
+    /// Graphic equalizer with the preamp lowered by the largest boost so it doesn't clip
+    pub fn preset(preset: EqualizerPreset) -> Self {
+        let gains = preset.gains();
+        Self {
+            preamp: -gains.iter().fold(0.0, |max: f64, &gain| max.max(gain)),
+            ..Self::graphic(gains)
+        }
+    }
+
+    fn flat(&self) -> bool {
+        self.preamp == 0.0 && self.bands.is_empty()
+    }
+}
+
+/// A filter of the [Equalizer]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EqualizerBand {
+    pub filter: FilterKind,
+    /// Center frequency of peaking filters, corner frequency of the others, in Hz
+    pub frequency: f64,
+    /// Boost or cut in dB, unused by low and high pass filters
+    pub gain: f64,
+    /// Higher is narrower, or more resonant for low and high pass filters
+    pub q: f64,
+}
+
+impl EqualizerBand {
+    /// Q of shelf and pass filters without a resonance peak
+    const FLAT_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+    pub fn peaking(frequency: f64, gain: f64, q: f64) -> Self {
+        Self {
+            filter: FilterKind::Peaking,
+            frequency,
+            gain,
+            q,
+        }
+    }
+
+    pub fn low_shelf(frequency: f64, gain: f64) -> Self {
+        Self {
+            filter: FilterKind::LowShelf,
+            frequency,
+            gain,
+            q: Self::FLAT_Q,
+        }
+    }
+
+    pub fn high_shelf(frequency: f64, gain: f64) -> Self {
+        Self {
+            filter: FilterKind::HighShelf,
+            frequency,
+            gain,
+            q: Self::FLAT_Q,
+        }
+    }
+
+    pub fn low_pass(frequency: f64) -> Self {
+        Self {
+            filter: FilterKind::LowPass,
+            frequency,
+            gain: 0.0,
+            q: Self::FLAT_Q,
+        }
+    }
+
+    pub fn high_pass(frequency: f64) -> Self {
+        Self {
+            filter: FilterKind::HighPass,
+            frequency,
+            gain: 0.0,
+            q: Self::FLAT_Q,
+        }
+    }
+
+    /// The filter at `sample_rate`, with the settings limited to what it can do
+    fn biquad(&self, sample_rate: u32) -> Biquad {
+        let rate = sample_rate as f64;
+        let frequency = self.frequency.clamp(10.0, rate * 0.49);
+        let gain = self.gain.clamp(-24.0, 24.0);
+        let q = self.q.clamp(0.1, 20.0);
+        match self.filter {
+            FilterKind::Peaking => Biquad::peaking(frequency, gain, q, rate),
+            FilterKind::LowShelf => Biquad::low_shelf(frequency, gain, q, rate),
+            FilterKind::HighShelf => Biquad::high_shelf(frequency, gain, q, rate),
+            FilterKind::LowPass => Biquad::low_pass(frequency, q, rate),
+            FilterKind::HighPass => Biquad::high_pass(frequency, q, rate),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    /// Boosts or cuts around the frequency
+    Peaking,
+    /// Boosts or cuts below the frequency
+    LowShelf,
+    /// Boosts or cuts above the frequency
+    HighShelf,
+    /// Removes what is above the frequency
+    LowPass,
+    /// Removes what is below the frequency
+    HighPass,
+}
+
+/// Gains of the graphic equalizer for common kinds of music
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EqualizerPreset {
+    Flat,
+    Rock,
+    Pop,
+    Jazz,
+    Classical,
+    Electronic,
+    Vocal,
+    BassBoost,
+    TrebleBoost,
+}
+
+impl EqualizerPreset {
+    pub const ALL: [EqualizerPreset; 9] = [
+        EqualizerPreset::Flat,
+        EqualizerPreset::Rock,
+        EqualizerPreset::Pop,
+        EqualizerPreset::Jazz,
+        EqualizerPreset::Classical,
+        EqualizerPreset::Electronic,
+        EqualizerPreset::Vocal,
+        EqualizerPreset::BassBoost,
+        EqualizerPreset::TrebleBoost,
+    ];
+
+    /// Gain in dB of each of [Equalizer::GRAPHIC_FREQUENCIES]
+    pub fn gains(&self) -> [f64; 10] {
+        match self {
+            EqualizerPreset::Flat => [0.0; 10],
+            EqualizerPreset::Rock => [5.0, 4.0, 3.0, 1.0, -1.0, -1.0, 1.0, 3.0, 4.0, 5.0],
+            EqualizerPreset::Pop => [-1.0, 1.0, 3.0, 4.0, 3.0, 1.0, -1.0, -1.0, 1.0, 2.0],
+            EqualizerPreset::Jazz => [3.0, 2.0, 1.0, 2.0, -1.0, -1.0, 0.0, 1.0, 2.0, 3.0],
+            EqualizerPreset::Classical => [4.0, 3.0, 2.0, 1.0, 0.0, 0.0, 0.0, 1.0, 2.0, 3.0],
+            EqualizerPreset::Electronic => [5.0, 4.0, 1.0, 0.0, -2.0, 1.0, 0.0, 1.0, 4.0, 5.0],
+            EqualizerPreset::Vocal => [-2.0, -2.0, -1.0, 1.0, 3.0, 4.0, 3.0, 1.0, 0.0, -1.0],
+            EqualizerPreset::BassBoost => [6.0, 5.0, 4.0, 2.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            EqualizerPreset::TrebleBoost => [0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 4.0, 5.0, 6.0],
+        }
+    }
+}
+
+impl Display for EqualizerPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            EqualizerPreset::Flat => "flat",
+            EqualizerPreset::Rock => "rock",
+            EqualizerPreset::Pop => "pop",
+            EqualizerPreset::Jazz => "jazz",
+            EqualizerPreset::Classical => "classical",
+            EqualizerPreset::Electronic => "electronic",
+            EqualizerPreset::Vocal => "vocal",
+            EqualizerPreset::BassBoost => "bass boost",
+            EqualizerPreset::TrebleBoost => "treble boost",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Applies an [Equalizer] at the output sample rate, this is the [crate::ProcessorId::EQUALIZER]
+/// processor. When the settings change the old and the new filters run side by side for a
+/// moment and are crossfaded, so the change doesn't click
+pub(super) struct EqualizerFilter {
+    settings: Equalizer,
+    sample_rate: u32,
+    /// Filters of every band per channel
+    filters: Vec<Vec<Biquad>>,
+    preamp: f64,
+    /// Filters and preamp replaced by the last change, fading out over `fade` frames
+    previous: Option<(Vec<Vec<Biquad>>, f64)>,
+    faded: usize,
+    fade: usize,
+    /// Settings changed again while fading, faded to once the fade is done
+    pending: Option<Equalizer>,
+}
+
+impl EqualizerFilter {
+    const FADE: Duration = Duration::from_millis(20);
+
+    pub(super) fn new() -> Self {
+        Self {
+            settings: Equalizer::default(),
+            // the filters are made for the sample rate of the first buffer
+            sample_rate: 0,
+            filters: vec![],
+            preamp: 1.0,
+            previous: None,
+            faded: 0,
+            fade: 0,
+            pending: None,
+        }
+    }
+
+    /// Fade over to `settings`
+    pub(super) fn set(&mut self, settings: Equalizer) {
+        if self.previous.is_some() {
+            self.pending = Some(settings);
+        } else {
+            self.fade_to(settings);
+        }
+    }
+
+    fn fade_to(&mut self, settings: Equalizer) {
+        if self.filters.is_empty() {
+            // nothing playing to fade, the filters are made for the next buffer
+            self.settings = settings;
+            return;
+        }
+        let channels = self.filters.len();
+        let mut filters = Self::filters(&settings, self.sample_rate, channels);
+        for (filters, previous) in filters.iter_mut().zip(&self.filters) {
+            for (filter, previous) in filters.iter_mut().zip(previous) {
+                filter.continue_from(previous);
+            }
+        }
+        let previous = std::mem::replace(&mut self.filters, filters);
+        let preamp = std::mem::replace(&mut self.preamp, Self::preamp(&settings));
+        self.previous = Some((previous, preamp));
+        self.faded = 0;
+        self.settings = settings;
+    }
+
+    fn filters(settings: &Equalizer, sample_rate: u32, channels: usize) -> Vec<Vec<Biquad>> {
+        let bands = settings
+            .bands
+            .iter()
+            .map(|band| band.biquad(sample_rate))
+            .collect::<Vec<_>>();
+        vec![bands; channels]
+    }
+
+    /// Frames of [EqualizerFilter::FADE], at least one even at very low sample rates as the fade
+    /// progress is divided by it
+    fn fade(sample_rate: u32) -> usize {
+        ((Self::FADE.as_secs_f64() * sample_rate as f64) as usize).max(1)
+    }
+
+    fn preamp(settings: &Equalizer) -> f64 {
+        10f64.powf(settings.preamp.clamp(-24.0, 24.0) / 20.0)
+    }
+}
+
+impl AudioProcessor for EqualizerFilter {
+    /// The filters start over when the sample rate or the number of channels changed
+    fn process(&mut self, buffer: &mut SampleBuf, sample_rate: u32) {
+        if self.sample_rate != sample_rate || self.filters.len() != buffer.channels() {
+            self.sample_rate = sample_rate;
+            self.fade = Self::fade(sample_rate);
+            if let Some(pending) = self.pending.take() {
+                self.settings = pending;
+            }
+            self.filters = Self::filters(&self.settings, sample_rate, buffer.channels());
+            self.preamp = Self::preamp(&self.settings);
+            self.previous = None;
+        }
+        if self.previous.is_none() {
+            if let Some(pending) = self.pending.take() {
+                self.fade_to(pending);
+            }
+        }
+        if self.settings.flat() && self.previous.is_none() {
+            return;
+        }
+        for (c, samples) in buffer.channel_samples_mut().enumerate() {
+            let filters = &mut self.filters[c];
+            let mut previous = self
+                .previous
+                .as_mut()
+                .map(|(filters, preamp)| (&mut filters[c], *preamp));
+            for (i, sample) in samples.iter_mut().enumerate() {
+                let input = *sample;
+                *sample = filters
+                    .iter_mut()
+                    .fold(input * self.preamp, |s, filter| filter.process(s));
+                if let Some((filters, preamp)) = previous.as_mut() {
+                    let old = filters
+                        .iter_mut()
+                        .fold(input * *preamp, |s, filter| filter.process(s));
+                    let progress = ((self.faded + i) as f64 / self.fade as f64).min(1.0);
+                    *sample = old + (*sample - old) * progress;
+                }
+            }
+        }
+        self.faded += buffer.frames();
+        if self.faded >= self.fade {
+            self.previous = None;
+        }
+    }
+
+    fn reset(&mut self) {
+        // made again for the next buffer
+        self.filters.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+
+    use super::*;
+
+    const RATE: u32 = 48000;
+    /// Frames of [EqualizerFilter::FADE] at [RATE]
+    const FADE: usize = 960;
+
+    fn preamp(db: f64) -> Equalizer {
+        Equalizer {
+            preamp: db,
+            bands: vec![],
+        }
+    }
+
+    fn gain(db: f64) -> f64 {
+        10f64.powf(db / 20.0)
+    }
+
+    /// Run `samples` through `filter` as mono buffers of `chunk` frames
+    fn process(filter: &mut EqualizerFilter, samples: &[f64], chunk: usize) -> Vec<f64> {
+        let mut output = vec![];
+        for samples in samples.chunks(chunk) {
+            let mut buffer = SampleBuf::with_buffer(vec![samples.to_vec()]);
+            filter.process(&mut buffer, RATE);
+            output.extend_from_slice(buffer.samples(0).unwrap());
+        }
+        output
+    }
+
+    fn sine(frequency: f64, frames: usize) -> Vec<f64> {
+        (0..frames)
+            .map(|i| (TAU * frequency * i as f64 / RATE as f64).sin())
+            .collect()
+    }
+
+    /// Largest difference between consecutive samples
+    fn largest_step(samples: &[f64]) -> f64 {
+        samples
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn flat_is_unchanged() {
+        let input = sine(1000.0, 4800);
+        let mut filter = EqualizerFilter::new();
+        assert_eq!(process(&mut filter, &input, 512), input);
+
+        // back to flat after a fade
+        filter.set(preamp(-6.0));
+        process(&mut filter, &input, 512);
+        filter.set(Equalizer::default());
+        process(&mut filter, &input, 512);
+        assert_eq!(process(&mut filter, &input, 512), input);
+    }
+
+    #[test]
+    fn peaking_gain_at_center() {
+        let band = EqualizerBand::peaking(1000.0, 6.0, 1.0);
+        let mut filter = EqualizerFilter::new();
+        filter.set(Equalizer::parametric(vec![band]));
+        let input = sine(1000.0, RATE as usize);
+        let output = process(&mut filter, &input, 512);
+
+        let mut biquad = Biquad::peaking(1000.0, 6.0, 1.0, RATE as f64);
+        for (&input, &output) in input.iter().zip(&output) {
+            assert!((biquad.process(input) - output).abs() < 1e-12);
+        }
+        // past the filter settling
+        let peak = output[RATE as usize / 2..]
+            .iter()
+            .fold(0.0, |peak: f64, s| peak.max(s.abs()));
+        assert!((peak / gain(6.0) - 1.0).abs() < 0.01, "{}", peak);
+    }
+
+    #[test]
+    fn fade() {
+        let mut filter = EqualizerFilter::new();
+        filter.set(preamp(-6.0));
+        let ones = vec![1.0; 2 * FADE];
+        assert!(process(&mut filter, &ones, 100)
+            .iter()
+            .all(|&s| (s - gain(-6.0)).abs() < 1e-12));
+
+        filter.set(preamp(0.0));
+        let output = process(&mut filter, &ones, 100);
+        // a straight line from the old gain to the new one, whatever the buffer size
+        for (i, &s) in output[..FADE].iter().enumerate() {
+            let progress = i as f64 / FADE as f64;
+            let expected = gain(-6.0) + (1.0 - gain(-6.0)) * progress;
+            assert!((s - expected).abs() < 1e-12, "{} {}", i, s);
+        }
+        assert!(output[FADE..].iter().all(|&s| s == 1.0));
+    }
+
+    #[test]
+    fn change_during_playback_is_smooth() {
+        let mut filter = EqualizerFilter::new();
+        let input = sine(1000.0, RATE as usize / 2);
+        let (before, after) = input.split_at(RATE as usize / 4 + 123);
+        let mut output = process(&mut filter, before, 256);
+        filter.set(Equalizer {
+            preamp: -12.0,
+            bands: vec![EqualizerBand::peaking(1000.0, 12.0, 2.0)],
+        });
+        output.extend(process(&mut filter, after, 256));
+
+        // no larger steps than the sine itself has, so nothing clicks
+        assert!(largest_step(&output) <= largest_step(&input) * 1.01);
+    }
+
+    #[test]
+    fn pending() {
+        let mut filter = EqualizerFilter::new();
+        let ones = vec![1.0; FADE];
+        process(&mut filter, &ones, 100);
+
+        filter.set(preamp(-6.0));
+        let mut output = process(&mut filter, &ones[..FADE / 2], 100);
+        // changed again halfway, it waits for the running fade
+        filter.set(preamp(-12.0));
+        output.extend(process(&mut filter, &ones, 100));
+        output.extend(process(&mut filter, &ones, 100));
+
+        assert!((output[FADE] - gain(-6.0)).abs() < 1e-12);
+        // the next fade starts with the buffer after the first one ended
+        assert!((output[FADE + 100] - gain(-6.0)).abs() > 1e-6);
+        assert!((output[2 * FADE + 100] - gain(-12.0)).abs() < 1e-12);
+        assert!(output.windows(2).all(|w| w[1] <= w[0]));
+        assert!(largest_step(&output) < (1.0 - gain(-6.0)) / FADE as f64 * 1.01);
+    }
+
+    #[test]
+    fn low_sample_rate() {
+        let mut filter = EqualizerFilter::new();
+        let mut buffer = SampleBuf::with_buffer(vec![vec![1.0; 4]]);
+        filter.process(&mut buffer, 40);
+        filter.set(preamp(-6.0));
+        let mut buffer = SampleBuf::with_buffer(vec![vec![1.0; 4]]);
+        filter.process(&mut buffer, 40);
+        let samples = buffer.samples(0).unwrap();
+        assert!(samples.iter().all(|s| s.is_finite()));
+        assert!((samples[3] - gain(-6.0)).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    http::Buffering,
+    player::AudioPlayerError,
+    queue::{QueueItem, TrackId},
+    track::TrackDetails,
+};
+
+/// Notification delivered to receivers from [crate::AudioPlayerController::subscribe]
+#[derive(Debug, Clone)]
+pub enum AudioPlayerEvent {
+    TrackStarted(QueueItem),
+    /// The track played through to its end
+    TrackEnded(TrackId),
+    Paused,
+    Resumed,
+    /// A seek was carried out, with the position playback continues from which can be off from
+    /// the requested one by up to a packet
+    Seeked(Duration),
+    /// Sent at the interval given to [crate::AudioPlayerController::subscribe]
+    Position(Duration),
+    MetadataChanged(TrackId, TrackDetails),
+    /// Playback of a track played from a URL started or stopped waiting for the download, see
+    /// [crate::AudioPlayerController::buffering]
+    Buffering(TrackId, Buffering),
+    /// Tracks were added, removed or moved, or another track became the current one
+    QueueChanged,
+    /// Playback moved to another output device, `None` being the default device
+    DeviceChanged(Option<String>),
+    /// The output device stopped working, followed by [AudioPlayerEvent::DeviceChanged] once
+    /// playback continues on the default device
+    DeviceLost(Option<String>),
+    Error(Arc<AudioPlayerError>),
+}
+
+struct Subscriber {
+    tx: Sender<AudioPlayerEvent>,
+    position_interval: Option<Duration>,
+    last_position: Option<Instant>,
+}
+
+/// Shared with the download of a track played from a URL, which emits while the player thread
+/// waits for it
+#[derive(Clone)]
+pub(super) struct Subscribers {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl Subscribers {
+    pub(super) fn new() -> Self {
+        Self {
+            subscribers: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    pub(super) fn subscribe(
+        &self,
+        position_interval: Option<Duration>,
+    ) -> Receiver<AudioPlayerEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(Subscriber {
+            tx,
+            position_interval,
+            last_position: None,
+        });
+        rx
+    }
+
+    /// Send to every subscriber, forgetting those that dropped their receiver
+    pub(super) fn emit(&self, event: AudioPlayerEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.tx.send(event.clone()).is_ok());
+    }
+
+    /// Send [AudioPlayerEvent::Position] to the subscribers whose interval has elapsed
+    pub(super) fn position(&self, position: Duration) {
+        let now = Instant::now();
+        self.subscribers.lock().unwrap().retain_mut(|subscriber| {
+            let Some(interval) = subscriber.position_interval else {
+                return true;
+            };
+            if subscriber
+                .last_position
+                .is_some_and(|last| now.duration_since(last) < interval)
+            {
+                return true;
+            }
+            subscriber.last_position = Some(now);
+            subscriber
+                .tx
+                .send(AudioPlayerEvent::Position(position))
+                .is_ok()
+        });
+    }
+}
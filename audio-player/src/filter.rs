@@ -0,0 +1,116 @@
+use std::f64::consts::TAU;
+
+/// Second order IIR filter in direct form I, the building block of the equalizer and of the
+/// K-weighting of the loudness meter
+#[derive(Debug, Clone)]
+pub(super) struct Biquad {
+    /// Coefficients normalized by `a0`
+    b: [f64; 3],
+    a: [f64; 2],
+    /// Last two inputs and outputs, newest first
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    pub(super) fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    /// Filters of the Audio EQ Cookbook by Robert Bristow-Johnson take `a0` and are normalized
+    /// here
+    fn cookbook(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self::new(
+            [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            [a[1] / a[0], a[2] / a[0]],
+        )
+    }
+
+    /// Cosine of the angular frequency and the bandwidth term `alpha`
+    fn omega(frequency: f64, q: f64, sample_rate: f64) -> (f64, f64) {
+        let w0 = TAU * frequency / sample_rate;
+        (w0.cos(), w0.sin() / (2.0 * q))
+    }
+
+    pub(super) fn peaking(frequency: f64, gain: f64, q: f64, sample_rate: f64) -> Self {
+        let a = 10f64.powf(gain / 40.0);
+        let (cos, alpha) = Self::omega(frequency, q, sample_rate);
+        Self::cookbook(
+            [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+        )
+    }
+
+    pub(super) fn low_shelf(frequency: f64, gain: f64, q: f64, sample_rate: f64) -> Self {
+        let a = 10f64.powf(gain / 40.0);
+        let (cos, alpha) = Self::omega(frequency, q, sample_rate);
+        let root = 2.0 * a.sqrt() * alpha;
+        Self::cookbook(
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + root),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - root),
+            ],
+            [
+                (a + 1.0) + (a - 1.0) * cos + root,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - root,
+            ],
+        )
+    }
+
+    pub(super) fn high_shelf(frequency: f64, gain: f64, q: f64, sample_rate: f64) -> Self {
+        let a = 10f64.powf(gain / 40.0);
+        let (cos, alpha) = Self::omega(frequency, q, sample_rate);
+        let root = 2.0 * a.sqrt() * alpha;
+        Self::cookbook(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + root),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - root),
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + root,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - root,
+            ],
+        )
+    }
+
+    pub(super) fn low_pass(frequency: f64, q: f64, sample_rate: f64) -> Self {
+        let (cos, alpha) = Self::omega(frequency, q, sample_rate);
+        Self::cookbook(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    pub(super) fn high_pass(frequency: f64, q: f64, sample_rate: f64) -> Self {
+        let (cos, alpha) = Self::omega(frequency, q, sample_rate);
+        Self::cookbook(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Take over the inputs and outputs of `other`, so replacing a filter doesn't start from
+    /// silence
+    pub(super) fn continue_from(&mut self, other: &Biquad) {
+        self.x = other.x;
+        self.y = other.y;
+    }
+
+    pub(super) fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
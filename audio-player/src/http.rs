@@ -0,0 +1,736 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use symphonia::core::{io::MediaSource, meta::MetadataRevision};
+use tracing::{debug, warn};
+use ureq::{Agent, AgentBuilder, Response};
+
+use crate::icy::{self, IcyReader, IcyUpdates};
+
+/// How far the download of a track played from a URL got, see
+/// [crate::AudioPlayerController::buffering]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Buffering {
+    /// Playback waits for the download to catch up
+    pub stalled: bool,
+    /// Bytes downloaded past [Buffering::position]
+    pub ahead: u64,
+    /// Byte of the file being decoded
+    pub position: u64,
+    /// Size of the file, `None` for live streams
+    pub length: Option<u64>,
+    /// The download reached the end of the file
+    pub complete: bool,
+}
+
+/// The URL `path` stands for, if it starts with `http://` or `https://`
+pub(super) fn url(path: &Path) -> Option<&str> {
+    let path = path.to_str()?;
+    let scheme = path.split_once("://")?.0;
+    (scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https")).then_some(path)
+}
+
+/// Bytes downloaded by the thread of a [HttpSource] and not yet dropped
+struct Download {
+    data: VecDeque<u8>,
+    /// Byte of the file `data` starts at
+    start: u64,
+    /// Byte of the file the source reads next
+    position: u64,
+    /// Byte the download has to start over from, after a seek out of `data`
+    restart: Option<u64>,
+    complete: bool,
+    error: Option<(io::ErrorKind, String)>,
+    stalled: bool,
+    /// The source was dropped, the thread ends
+    closed: bool,
+}
+
+impl Download {
+    fn end(&self) -> u64 {
+        self.start + self.data.len() as u64
+    }
+
+    fn ahead(&self) -> u64 {
+        self.end().saturating_sub(self.position)
+    }
+
+    /// Drop what is too far behind the position to be read again
+    fn trim(&mut self) {
+        let behind = (self.position.saturating_sub(self.start))
+            .saturating_sub(HttpSource::BEHIND)
+            .min(self.data.len() as u64);
+        self.data.drain(..behind as usize);
+        self.start += behind;
+    }
+}
+
+/// Called with the state of the download when playback starts or stops waiting for it
+type BufferingListener = Box<dyn Fn(Buffering) + Send>;
+
+/// Shared by a [HttpSource], its download thread and the player
+pub(super) struct HttpBuffer {
+    download: Mutex<Download>,
+    condvar: Condvar,
+    length: Option<u64>,
+    icy: IcyUpdates,
+    listener: Mutex<Option<BufferingListener>>,
+}
+
+impl HttpBuffer {
+    fn lock(&self) -> MutexGuard<'_, Download> {
+        self.download.lock().unwrap()
+    }
+
+    pub(super) fn buffering(&self) -> Buffering {
+        self.snapshot(&self.lock())
+    }
+
+    fn snapshot(&self, download: &Download) -> Buffering {
+        Buffering {
+            stalled: download.stalled,
+            ahead: download.ahead(),
+            position: download.position,
+            length: self.length,
+            complete: download.complete,
+        }
+    }
+
+    /// Call `listener` whenever [Buffering::stalled] changes, replacing the previous one
+    pub(super) fn set_listener(&self, listener: Option<BufferingListener>) {
+        *self.listener.lock().unwrap() = listener;
+    }
+
+    fn set_stalled(&self, download: &mut Download, stalled: bool) {
+        download.stalled = stalled;
+        if let Some(listener) = self.listener.lock().unwrap().as_ref() {
+            listener(self.snapshot(download));
+        }
+    }
+
+    /// The latest ICY metadata of radio streams that the source has read up to, if it changed
+    pub(super) fn take_metadata(&self) -> Option<MetadataRevision> {
+        let position = self.lock().position;
+        let mut updates = self.icy.lock().unwrap();
+        let mut latest = None;
+        while updates.front().is_some_and(|(start, _)| *start <= position) {
+            latest = updates.pop_front().map(|(_, metadata)| metadata);
+        }
+        latest.map(|metadata| metadata.revision())
+    }
+}
+
+/// A file or stream downloaded over HTTP as a [MediaSource]. A thread downloads up to
+/// [HttpSource::AHEAD] bytes ahead of where the decoder reads. Seeking further than that or
+/// back to what was dropped makes a range request, when the server accepts them. Shoutcast
+/// and Icecast metadata is taken out of radio streams
+pub(super) struct HttpSource {
+    buffer: Arc<HttpBuffer>,
+    seekable: bool,
+    /// Tags of the radio station from the response headers
+    station: MetadataRevision,
+}
+
+impl HttpSource {
+    /// Bytes downloaded ahead of the position before the download waits
+    const AHEAD: u64 = 4 * 1024 * 1024;
+    /// Bytes kept behind the position for readers that step back
+    const BEHIND: u64 = 512 * 1024;
+    /// Bytes downloaded ahead before playback resumes once the download fell behind, so it
+    /// doesn't stutter
+    const REFILL: u64 = 64 * 1024;
+    /// Bytes a seek forward waits for instead of making a range request
+    const SKIP: u64 = 256 * 1024;
+    const CHUNK: usize = 16 * 1024;
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+    const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Connect to `url` and start downloading, returns the MIME type or file extension of the
+    /// format as a hint if known
+    pub(super) fn open(url: &str) -> io::Result<(Self, Option<String>)> {
+        let agent = AgentBuilder::new()
+            .timeout_connect(Self::CONNECT_TIMEOUT)
+            .timeout_read(Self::READ_TIMEOUT)
+            .build();
+        let response = Self::request(&agent, url, 0)?;
+        // bytes of audio between the metadata blocks of radio streams
+        let interval = response
+            .header("icy-metaint")
+            .and_then(|interval| interval.trim().parse::<usize>().ok())
+            .filter(|&interval| interval > 0);
+        // without a length there is no way to tell where a range request would land, and the
+        // metadata blocks move the audio away from where it would
+        let length = response
+            .header("Content-Length")
+            .and_then(|length| length.trim().parse().ok())
+            .filter(|_| interval.is_none());
+        let seekable = length.is_some()
+            && response
+                .header("Accept-Ranges")
+                .is_some_and(|ranges| ranges.trim().eq_ignore_ascii_case("bytes"));
+        let hint = Self::hint(&response);
+        let station = icy::station(response.headers_names().into_iter().filter_map(|name| {
+            let value = response.header(&name)?.to_string();
+            Some((name, value))
+        }));
+        debug!(
+            "opened {} length {:?} seekable {} hint {:?} metadata interval {:?}",
+            url, length, seekable, hint, interval
+        );
+        let buffer = Arc::new(HttpBuffer {
+            download: Mutex::new(Download {
+                data: VecDeque::new(),
+                start: 0,
+                position: 0,
+                restart: None,
+                complete: false,
+                error: None,
+                stalled: false,
+                closed: false,
+            }),
+            condvar: Condvar::new(),
+            length,
+            icy: IcyUpdates::default(),
+            listener: Mutex::new(None),
+        });
+        let reader: Box<dyn Read + Send + Sync> = match interval {
+            Some(interval) => Box::new(IcyReader::new(
+                response.into_reader(),
+                interval,
+                buffer.icy.clone(),
+            )),
+            None => response.into_reader(),
+        };
+        let thread_buffer = buffer.clone();
+        let url = url.to_string();
+        std::thread::spawn(move || {
+            Self::download(&thread_buffer, &agent, &url, reader, seekable);
+        });
+        Ok((
+            Self {
+                buffer,
+                seekable,
+                station,
+            },
+            hint,
+        ))
+    }
+
+    pub(super) fn buffer(&self) -> Arc<HttpBuffer> {
+        self.buffer.clone()
+    }
+
+    pub(super) fn station(&self) -> &MetadataRevision {
+        &self.station
+    }
+
+    /// GET `url` from byte `offset` on, with the metadata of radio streams
+    fn request(agent: &Agent, url: &str, offset: u64) -> io::Result<Response> {
+        let mut request = agent.get(url).set("Icy-MetaData", "1");
+        if offset > 0 {
+            request = request.set("Range", &format!("bytes={}-", offset));
+        }
+        let response = request.call().map_err(|err| match err {
+            ureq::Error::Status(404, _) => io::Error::new(io::ErrorKind::NotFound, err),
+            err => io::Error::other(err),
+        })?;
+        Ok(response)
+    }
+
+    /// The MIME type the server sent, or else the extension at the end of the URL
+    fn hint(response: &Response) -> Option<String> {
+        let content_type = response.content_type().trim();
+        if response.header("Content-Type").is_some() && content_type != "application/octet-stream" {
+            return Some(content_type.to_ascii_lowercase());
+        }
+        let path = response.get_url().split(['?', '#']).next()?;
+        let name = path.rsplit('/').next()?;
+        name.rsplit_once('.')
+            .map(|(_, extension)| extension.to_ascii_lowercase())
+    }
+
+    /// Body of the download thread, ends once the source is dropped
+    fn download(
+        buffer: &HttpBuffer,
+        agent: &Agent,
+        url: &str,
+        mut reader: Box<dyn Read + Send + Sync>,
+        seekable: bool,
+    ) {
+        let mut chunk = vec![0; Self::CHUNK];
+        // a connection lost in the middle of a file is picked up again once
+        let mut resumed = false;
+        loop {
+            let restart = {
+                let mut download = buffer.lock();
+                loop {
+                    if download.closed {
+                        return;
+                    }
+                    if let Some(offset) = download.restart.take() {
+                        break Some(offset);
+                    }
+                    if !download.complete
+                        && download.error.is_none()
+                        && download.ahead() < Self::AHEAD
+                    {
+                        break None;
+                    }
+                    download = buffer.condvar.wait(download).unwrap();
+                }
+            };
+            if let Some(offset) = restart {
+                debug!("requesting {} from byte {}", url, offset);
+                match Self::reconnect(agent, url, offset) {
+                    Ok(restarted) => reader = restarted,
+                    Err(err) => Self::fail(buffer, err),
+                }
+                continue;
+            }
+            let result = reader.read(&mut chunk);
+            let mut download = buffer.lock();
+            if download.restart.is_some() {
+                // read from where the source no longer is
+                continue;
+            }
+            match result {
+                Ok(0) if buffer.length.is_some_and(|length| download.end() < length) => {
+                    drop(download);
+                    Self::lost(buffer, &mut reader, agent, url, seekable, &mut resumed);
+                }
+                Ok(0) => {
+                    download.complete = true;
+                    buffer.condvar.notify_all();
+                }
+                Ok(n) => {
+                    resumed = false;
+                    download.data.extend(&chunk[..n]);
+                    download.trim();
+                    buffer.condvar.notify_all();
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => {
+                    warn!("download of {} failed: {}", url, err);
+                    drop(download);
+                    Self::lost(buffer, &mut reader, agent, url, seekable, &mut resumed);
+                }
+            }
+        }
+    }
+
+    /// The connection ended before the file did, continue where it left off if possible
+    fn lost(
+        buffer: &HttpBuffer,
+        reader: &mut Box<dyn Read + Send + Sync>,
+        agent: &Agent,
+        url: &str,
+        seekable: bool,
+        resumed: &mut bool,
+    ) {
+        let end = buffer.lock().end();
+        if seekable && !*resumed {
+            *resumed = true;
+            match Self::reconnect(agent, url, end) {
+                Ok(restarted) => *reader = restarted,
+                Err(err) => Self::fail(buffer, err),
+            }
+        } else {
+            // not UnexpectedEof, which the decoder takes for the end of the track
+            Self::fail(
+                buffer,
+                io::Error::new(io::ErrorKind::ConnectionAborted, "connection lost"),
+            );
+        }
+    }
+
+    /// Request the rest of the file from `offset`. Servers that ignore the range send all of
+    /// it, so the part before `offset` is skipped
+    fn reconnect(agent: &Agent, url: &str, offset: u64) -> io::Result<Box<dyn Read + Send + Sync>> {
+        let response = Self::request(agent, url, offset)?;
+        let partial = response.status() == 206;
+        let mut reader = response.into_reader();
+        if !partial && offset > 0 {
+            io::copy(&mut reader.by_ref().take(offset), &mut io::sink())?;
+        }
+        Ok(reader)
+    }
+
+    fn fail(buffer: &HttpBuffer, err: io::Error) {
+        let mut download = buffer.lock();
+        download.error = Some((err.kind(), err.to_string()));
+        buffer.condvar.notify_all();
+    }
+}
+
+impl Read for HttpSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut download = self.buffer.lock();
+        if download.position >= download.end() && !download.complete {
+            if let Some((kind, err)) = &download.error {
+                return Err(io::Error::new(*kind, err.clone()));
+            }
+            self.buffer.set_stalled(&mut download, true);
+            while download.ahead() < Self::REFILL && !download.complete && download.error.is_none()
+            {
+                download = self.buffer.condvar.wait(download).unwrap();
+            }
+            self.buffer.set_stalled(&mut download, false);
+            if download.position >= download.end() {
+                if let Some((kind, err)) = &download.error {
+                    return Err(io::Error::new(*kind, err.clone()));
+                }
+            }
+        }
+        let offset =
+            (download.position.saturating_sub(download.start) as usize).min(download.data.len());
+        let (front, back) = download.data.as_slices();
+        let (front, back) = match front.get(offset..) {
+            Some(front) => (front, back),
+            None => (&[][..], &back[offset - front.len()..]),
+        };
+        let mut n = front.len().min(buf.len());
+        buf[..n].copy_from_slice(&front[..n]);
+        let rest = back.len().min(buf.len() - n);
+        buf[n..n + rest].copy_from_slice(&back[..rest]);
+        n += rest;
+        download.position += n as u64;
+        download.trim();
+        // there may be room to download more now
+        self.buffer.condvar.notify_all();
+        Ok(n)
+    }
+}
+
+impl Seek for HttpSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let mut download = self.buffer.lock();
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(delta) => download.position.checked_add_signed(delta),
+            SeekFrom::End(delta) => match self.buffer.length {
+                Some(length) => length.checked_add_signed(delta),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "length of stream unknown",
+                    ))
+                }
+            },
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before start"))?;
+        let downloaded = position >= download.start && position <= download.end() + Self::SKIP;
+        if downloaded || (!self.seekable && position >= download.start) {
+            // streams can only seek forward by downloading what is in between
+            download.position = position;
+        } else if self.seekable {
+            download.data.clear();
+            download.start = position;
+            download.position = position;
+            download.restart = Some(position);
+            download.complete = false;
+            download.error = None;
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "server can't seek back in stream",
+            ));
+        }
+        self.buffer.condvar.notify_all();
+        Ok(position)
+    }
+}
+
+impl MediaSource for HttpSource {
+    fn is_seekable(&self) -> bool {
+        self.seekable
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.buffer.length
+    }
+}
+
+impl Drop for HttpSource {
+    fn drop(&mut self) {
+        self.buffer.lock().closed = true;
+        self.buffer.condvar.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::{TcpListener, TcpStream},
+    };
+
+    use symphonia::core::meta::StandardTagKey;
+
+    use super::*;
+    use crate::{
+        icy::tests::{block, tag},
+        track::tests::wav,
+        AudioPlayer, AudioPlayerEvent, NullSink, Track,
+    };
+
+    /// A server on a free local port that hands every connection to `respond` on a thread of
+    /// its own, with the `Range` offset of the request and the number of the connection
+    struct Server {
+        url: String,
+        ranges: Arc<Mutex<Vec<Option<u64>>>>,
+    }
+
+    impl Server {
+        fn start(
+            respond: impl Fn(&mut TcpStream, Option<u64>, usize) -> io::Result<()>
+                + Send
+                + Sync
+                + 'static,
+        ) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/track.wav", listener.local_addr().unwrap());
+            let ranges = Arc::new(Mutex::new(vec![]));
+            let respond = Arc::new(respond);
+            let thread_ranges = ranges.clone();
+            std::thread::spawn(move || {
+                for (i, stream) in listener.incoming().enumerate() {
+                    let mut stream = stream.unwrap();
+                    let range = Self::read_request(&stream);
+                    thread_ranges.lock().unwrap().push(range);
+                    let respond = respond.clone();
+                    // the client drops connections it no longer reads from
+                    std::thread::spawn(move || {
+                        let _ = respond(&mut stream, range, i);
+                    });
+                }
+            });
+            Self { url, ranges }
+        }
+
+        fn read_request(stream: &TcpStream) -> Option<u64> {
+            let mut range = None;
+            for line in BufReader::new(stream).lines() {
+                let line = line.unwrap();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("range") {
+                        let offset = value.trim().strip_prefix("bytes=").unwrap();
+                        range = Some(offset.trim_end_matches('-').parse().unwrap());
+                    }
+                }
+            }
+            range
+        }
+
+        fn ranges(&self) -> Vec<Option<u64>> {
+            self.ranges.lock().unwrap().clone()
+        }
+    }
+
+    fn head(stream: &mut TcpStream, status: &str, headers: &[(&str, String)]) -> io::Result<()> {
+        write!(stream, "HTTP/1.1 {}\r\nConnection: close\r\n", status)?;
+        for (name, value) in headers {
+            write!(stream, "{}: {}\r\n", name, value)?;
+        }
+        write!(stream, "\r\n")
+    }
+
+    /// Answers range requests with the rest of `data`
+    fn respond_ranges(data: &[u8], stream: &mut TcpStream, range: Option<u64>) -> io::Result<()> {
+        let start = range.unwrap_or(0) as usize;
+        let status = match range {
+            Some(_) => "206 Partial Content",
+            None => "200 OK",
+        };
+        head(
+            stream,
+            status,
+            &[
+                ("Content-Length", (data.len() - start).to_string()),
+                ("Accept-Ranges", "bytes".to_string()),
+            ],
+        )?;
+        stream.write_all(&data[start..])
+    }
+
+    /// Bytes that tell where in the file they are from
+    fn data(length: usize) -> Arc<Vec<u8>> {
+        Arc::new((0..length).map(|i| (i % 251) as u8).collect())
+    }
+
+    fn read_all(source: &mut HttpSource) -> Vec<u8> {
+        let mut read = vec![];
+        source.read_to_end(&mut read).unwrap();
+        read
+    }
+
+    #[test]
+    fn get() {
+        let data = data(300_000);
+        let file = data.clone();
+        let server = Server::start(move |stream, _, _| {
+            head(
+                stream,
+                "200 OK",
+                &[
+                    ("Content-Type", "audio/wav".to_string()),
+                    ("Content-Length", file.len().to_string()),
+                ],
+            )?;
+            stream.write_all(&file)
+        });
+        let (mut source, hint) = HttpSource::open(&server.url).unwrap();
+        assert_eq!(hint.as_deref(), Some("audio/wav"));
+        assert!(!source.is_seekable());
+        assert_eq!(source.byte_len(), Some(data.len() as u64));
+        assert_eq!(read_all(&mut source), *data);
+        assert!(source.buffer.buffering().complete);
+        assert_eq!(server.ranges(), [None]);
+    }
+
+    #[test]
+    fn seek_with_range_request() {
+        let data = data(8 * 1024 * 1024);
+        let file = data.clone();
+        let server = Server::start(move |stream, range, _| respond_ranges(&file, stream, range));
+        let (mut source, _) = HttpSource::open(&server.url).unwrap();
+        assert!(source.is_seekable());
+        let offset = 7 * 1024 * 1024 + 3;
+        assert_eq!(source.seek(SeekFrom::Start(offset)).unwrap(), offset);
+        assert_eq!(read_all(&mut source), data[offset as usize..]);
+        assert_eq!(server.ranges(), [None, Some(offset)]);
+    }
+
+    #[test]
+    fn seek_with_range_ignored() {
+        let data = data(8 * 1024 * 1024);
+        let file = data.clone();
+        // claims to accept ranges but always sends the whole file
+        let server = Server::start(move |stream, _, _| respond_ranges(&file, stream, None));
+        let (mut source, _) = HttpSource::open(&server.url).unwrap();
+        let offset = 7 * 1024 * 1024 + 3;
+        source.seek(SeekFrom::Start(offset)).unwrap();
+        assert_eq!(read_all(&mut source), data[offset as usize..]);
+        assert_eq!(server.ranges(), [None, Some(offset)]);
+    }
+
+    #[test]
+    fn resume_lost_connection() {
+        let data = data(300_000);
+        let lost_at = 123_457;
+        let file = data.clone();
+        let server = Server::start(move |stream, range, i| match i {
+            0 => {
+                head(
+                    stream,
+                    "200 OK",
+                    &[
+                        ("Content-Length", file.len().to_string()),
+                        ("Accept-Ranges", "bytes".to_string()),
+                    ],
+                )?;
+                stream.write_all(&file[..lost_at])
+            }
+            _ => respond_ranges(&file, stream, range),
+        });
+        let (mut source, _) = HttpSource::open(&server.url).unwrap();
+        assert_eq!(read_all(&mut source), *data);
+        assert_eq!(server.ranges(), [None, Some(lost_at as u64)]);
+    }
+
+    #[test]
+    fn lost_connection_is_an_error() {
+        let file = wav(&[0.25; 48000], 1, 48000);
+        let server = Server::start(move |stream, _, _| {
+            // without ranges the download can't pick up again
+            head(
+                stream,
+                "200 OK",
+                &[("Content-Length", file.len().to_string())],
+            )?;
+            stream.write_all(&file[..file.len() / 2])
+        });
+        let player = AudioPlayer::with_sink(Default::default(), NullSink::unthrottled(48000));
+        let events = player.controller().subscribe(None);
+        player.queue(Track::open_url(&server.url).unwrap());
+        player.controller().play();
+        player.wait_until_end();
+        let errors: Vec<_> = events
+            .try_iter()
+            .filter_map(|event| match event {
+                AudioPlayerEvent::Error(err) => Some(err.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert!(
+            errors.iter().any(|err| err.contains("connection lost")),
+            "{:?}",
+            errors
+        );
+    }
+
+    #[test]
+    fn icy_metadata() {
+        let audio = data(64);
+        let blocks = [
+            block("StreamTitle='Artist - One';StreamUrl='';"),
+            block(""),
+            block("StreamTitle='Two';"),
+            // sent again without a change
+            block("StreamTitle='Two';"),
+        ];
+        let mut body = vec![];
+        for (chunk, block) in audio.chunks(16).zip(&blocks) {
+            body.extend(chunk);
+            body.extend(block);
+        }
+        let server = Server::start(move |stream, _, _| {
+            head(
+                stream,
+                "200 OK",
+                &[
+                    ("Content-Length", body.len().to_string()),
+                    ("Accept-Ranges", "bytes".to_string()),
+                    ("icy-metaint", "16".to_string()),
+                    ("icy-genre", "Jazz".to_string()),
+                ],
+            )?;
+            stream.write_all(&body)
+        });
+        let (mut source, _) = HttpSource::open(&server.url).unwrap();
+        // the blocks move the audio away from where a range request would land
+        assert!(!source.is_seekable());
+        assert_eq!(
+            tag(source.station(), StandardTagKey::Genre).as_deref(),
+            Some("Jazz")
+        );
+        assert_eq!(read_all(&mut source), *audio);
+        let buffer = source.buffer();
+        source.seek(SeekFrom::Start(15)).unwrap();
+        assert!(buffer.take_metadata().is_none());
+        source.seek(SeekFrom::Start(16)).unwrap();
+        let one = buffer.take_metadata().unwrap();
+        assert_eq!(
+            tag(&one, StandardTagKey::TrackTitle).as_deref(),
+            Some("One")
+        );
+        assert_eq!(tag(&one, StandardTagKey::Artist).as_deref(), Some("Artist"));
+        source.seek(SeekFrom::Start(47)).unwrap();
+        assert!(buffer.take_metadata().is_none());
+        source.seek(SeekFrom::Start(64)).unwrap();
+        let two = buffer.take_metadata().unwrap();
+        assert_eq!(
+            tag(&two, StandardTagKey::TrackTitle).as_deref(),
+            Some("Two")
+        );
+        assert_eq!(tag(&two, StandardTagKey::Artist), None);
+        assert!(buffer.take_metadata().is_none());
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+    sync::{Arc, Mutex},
+};
+
+use symphonia::core::meta::{MetadataBuilder, MetadataRevision, StandardTagKey, Tag, Value};
+
+/// Metadata blocks taken out of a stream by an [IcyReader], with the byte of the audio they
+/// start at
+pub(super) type IcyUpdates = Arc<Mutex<VecDeque<(u64, IcyMetadata)>>>;
+
+/// Fields of a Shoutcast or Icecast metadata block like
+/// `StreamTitle='Artist - Title';StreamUrl='http://...';`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct IcyMetadata {
+    fields: Vec<(String, String)>,
+}
+
+impl IcyMetadata {
+    /// The block is padded with zeros to a multiple of 16 bytes. Its encoding is not specified,
+    /// it is read as UTF-8 if it is valid and as Latin-1 otherwise
+    fn parse(block: &[u8]) -> Self {
+        let end = block.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+        let block = &block[..end];
+        let text = match std::str::from_utf8(block) {
+            Ok(text) => text.to_string(),
+            Err(_) => block.iter().map(|&b| b as char).collect(),
+        };
+        // values are quoted but not escaped, so a quote is only taken as the end of a value
+        // when a semicolon follows, or at the end of a block without the last semicolon
+        let text = text.strip_suffix('\'').unwrap_or(&text);
+        let fields = text
+            .split("';")
+            .filter_map(|field| {
+                let (key, value) = field.split_once("='")?;
+                Some((key.trim().to_string(), value.to_string()))
+            })
+            .collect();
+        Self { fields }
+    }
+
+    /// As tags of a revision, with the title and artist taken from `StreamTitle` when it is
+    /// `Artist - Title` as most stations send it
+    pub(super) fn revision(&self) -> MetadataRevision {
+        let mut builder = MetadataBuilder::new();
+        for (key, value) in &self.fields {
+            builder.add_tag(Tag::new(None, key, Value::from(value.as_str())));
+            if key != "StreamTitle" {
+                continue;
+            }
+            let (artist, title) = match value.split_once(" - ") {
+                Some((artist, title)) => (Some(artist), title),
+                None => (None, value.as_str()),
+            };
+            builder.add_tag(Tag::new(
+                Some(StandardTagKey::TrackTitle),
+                "TITLE",
+                Value::from(title),
+            ));
+            if let Some(artist) = artist {
+                builder.add_tag(Tag::new(
+                    Some(StandardTagKey::Artist),
+                    "ARTIST",
+                    Value::from(artist),
+                ));
+            }
+        }
+        builder.metadata()
+    }
+}
+
+/// Tags of the station from the `icy-*` headers of the response
+pub(super) fn station(headers: impl IntoIterator<Item = (String, String)>) -> MetadataRevision {
+    let mut builder = MetadataBuilder::new();
+    for (name, value) in headers {
+        let name = name.to_ascii_lowercase();
+        let std_key = match name.as_str() {
+            "icy-genre" => Some(StandardTagKey::Genre),
+            "icy-description" => Some(StandardTagKey::Comment),
+            "icy-name" | "icy-url" => None,
+            _ => continue,
+        };
+        builder.add_tag(Tag::new(std_key, &name, Value::from(value.trim())));
+    }
+    builder.metadata()
+}
+
+/// Takes the metadata blocks out of a stream that was asked for them with `Icy-MetaData: 1`,
+/// which the server sends every `icy-metaint` bytes of audio
+pub(super) struct IcyReader<R> {
+    inner: R,
+    interval: usize,
+    /// Bytes of audio until the next metadata block
+    remaining: usize,
+    /// Bytes of audio read so far
+    position: u64,
+    /// Servers may send the same block again, only changes are passed on
+    last: Vec<u8>,
+    updates: IcyUpdates,
+}
+
+impl<R: Read> IcyReader<R> {
+    pub(super) fn new(inner: R, interval: usize, updates: IcyUpdates) -> Self {
+        Self {
+            inner,
+            interval,
+            remaining: interval,
+            position: 0,
+            last: vec![],
+            updates,
+        }
+    }
+
+    /// Returns false at the end of the stream
+    fn read_metadata(&mut self) -> io::Result<bool> {
+        let mut length = [0];
+        if self.inner.read(&mut length)? == 0 {
+            return Ok(false);
+        }
+        let mut block = vec![0; length[0] as usize * 16];
+        self.inner.read_exact(&mut block)?;
+        if !block.is_empty() && block != self.last {
+            let metadata = IcyMetadata::parse(&block);
+            self.updates
+                .lock()
+                .unwrap()
+                .push_back((self.position, metadata));
+            self.last = block;
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for IcyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            if !self.read_metadata()? {
+                return Ok(0);
+            }
+            self.remaining = self.interval;
+        }
+        let limit = buf.len().min(self.remaining);
+        let n = self.inner.read(&mut buf[..limit])?;
+        self.remaining -= n;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn fields(block: &[u8]) -> Vec<(String, String)> {
+        IcyMetadata::parse(block).fields
+    }
+
+    fn field(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    pub(crate) fn tag(revision: &MetadataRevision, key: StandardTagKey) -> Option<String> {
+        revision
+            .tags()
+            .iter()
+            .find(|tag| tag.std_key == Some(key))
+            .map(|tag| tag.value.to_string())
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            fields(b"StreamTitle='A - B';StreamUrl='http://example.com/';\0\0\0"),
+            [
+                field("StreamTitle", "A - B"),
+                field("StreamUrl", "http://example.com/")
+            ]
+        );
+        // the semicolon after the last field is left out by some servers
+        assert_eq!(
+            fields(b"StreamTitle='A - B'"),
+            [field("StreamTitle", "A - B")]
+        );
+        assert_eq!(
+            fields(b"StreamTitle='Guns N' Roses - Don't Cry';"),
+            [field("StreamTitle", "Guns N' Roses - Don't Cry")]
+        );
+        assert_eq!(
+            fields(b"StreamTitle='Caf\xe9';"),
+            [field("StreamTitle", "Café")]
+        );
+        assert_eq!(
+            fields("StreamTitle='Café';".as_bytes()),
+            [field("StreamTitle", "Café")]
+        );
+        assert_eq!(fields(b"\0\0\0\0"), []);
+    }
+
+    #[test]
+    fn revision() {
+        let revision = IcyMetadata::parse(b"StreamTitle='Artist - Title - Live';").revision();
+        assert_eq!(
+            tag(&revision, StandardTagKey::Artist).as_deref(),
+            Some("Artist")
+        );
+        assert_eq!(
+            tag(&revision, StandardTagKey::TrackTitle).as_deref(),
+            Some("Title - Live")
+        );
+        let revision = IcyMetadata::parse(b"StreamTitle='Station jingle';").revision();
+        assert_eq!(tag(&revision, StandardTagKey::Artist), None);
+        assert_eq!(
+            tag(&revision, StandardTagKey::TrackTitle).as_deref(),
+            Some("Station jingle")
+        );
+    }
+
+    #[test]
+    fn station_tags() {
+        let headers = [
+            ("Icy-Genre", " Jazz "),
+            ("icy-name", "Radio"),
+            ("icy-br", "128"),
+            ("Content-Type", "audio/mpeg"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        let revision = station(headers);
+        assert_eq!(
+            tag(&revision, StandardTagKey::Genre).as_deref(),
+            Some("Jazz")
+        );
+        let keys: Vec<_> = revision.tags().iter().map(|tag| tag.key.as_str()).collect();
+        assert_eq!(keys, ["icy-genre", "icy-name"]);
+    }
+
+    /// A metadata block with its length in front
+    pub(crate) fn block(metadata: &str) -> Vec<u8> {
+        let mut block = metadata.as_bytes().to_vec();
+        block.resize(block.len().div_ceil(16) * 16, 0);
+        let mut framed = vec![(block.len() / 16) as u8];
+        framed.extend(block);
+        framed
+    }
+
+    #[test]
+    fn reader() {
+        let audio: Vec<u8> = (0..40).collect();
+        let mut stream = vec![];
+        for (chunk, metadata) in audio.chunks(10).zip([
+            "StreamTitle='One';",
+            "",
+            "StreamTitle='One';",
+            "StreamTitle='Two'",
+        ]) {
+            stream.extend(chunk);
+            stream.extend(block(metadata));
+        }
+        let updates = IcyUpdates::default();
+        let mut reader = IcyReader::new(Cursor::new(stream), 10, updates.clone());
+        // reads don't cross a metadata block
+        let mut buf = [0; 7];
+        let mut read: Vec<u8> = vec![];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => break,
+                n => read.extend(&buf[..n]),
+            }
+        }
+        assert_eq!(read, audio);
+        let updates: Vec<_> = updates.lock().unwrap().drain(..).collect();
+        let expected = [(10, "One"), (40, "Two")];
+        assert_eq!(updates.len(), expected.len());
+        for ((offset, metadata), (expected_offset, title)) in updates.into_iter().zip(expected) {
+            assert_eq!(offset, expected_offset);
+            assert_eq!(metadata.fields, [field("StreamTitle", title)]);
+        }
+    }
+}
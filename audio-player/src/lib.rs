@@ -1,9 +1,35 @@
 mod decoder;
+mod equalizer;
+mod event;
+mod filter;
+mod http;
+mod icy;
+mod loudness;
 mod output;
 mod player;
+mod processor;
+mod queue;
 mod resampler;
+mod sink;
+mod source;
+mod speed;
+mod tags;
 mod track;
+mod volume;
 mod buffer;
 
+pub use buffer::SampleBuf;
+pub use equalizer::{Equalizer, EqualizerBand, EqualizerPreset, FilterKind};
+pub use event::AudioPlayerEvent;
+pub use http::Buffering;
+pub use loudness::{AlbumLoudness, Loudness};
+pub use output::{AudioOutputError, AudioSink, OutputConfig, OutputDevice};
 pub use player::*;
+pub use processor::{AudioProcessor, ProcessorId};
+pub use queue::{QueueItem, RepeatMode, TrackId};
+pub use resampler::ResamplerError;
+pub use sink::{NullSink, WavSink};
+pub use speed::SpeedMode;
+pub use tags::Tags;
 pub use track::*;
+pub use volume::NormalizationMode;
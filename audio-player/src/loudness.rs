@@ -0,0 +1,439 @@
+use std::{collections::VecDeque, time::Duration};
+
+use symphonia::core::audio::Channels;
+
+use crate::{
+    buffer::SampleBuf, filter::Biquad, player::AudioPlayerError, track::ReplayGain, Track,
+};
+
+/// Loudness of a track or an album as measured by EBU R128
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    integrated: f64,
+    range: f64,
+    true_peak: f64,
+}
+
+impl Loudness {
+    /// ReplayGain 2.0 plays everything at this loudness
+    const REPLAY_GAIN_REFERENCE: f64 = -18.0;
+
+    /// Measure a track by decoding it as fast as possible, afterwards it is rewound so it can
+    /// still be played
+    pub fn scan(track: &mut Track) -> Result<Self, AudioPlayerError> {
+        Ok(LoudnessMeter::scan(track)?.loudness())
+    }
+
+    /// Integrated loudness in LUFS, negative infinity if everything is silent
+    pub fn integrated(&self) -> f64 {
+        self.integrated
+    }
+
+    /// Loudness range in LU, how much the loudness varies
+    pub fn range(&self) -> f64 {
+        self.range
+    }
+
+    /// Largest sample after oversampling, `1.0` being full scale
+    pub fn true_peak(&self) -> f64 {
+        self.true_peak
+    }
+
+    /// Track values of [ReplayGain] for the measured loudness
+    pub fn replay_gain(&self) -> ReplayGain {
+        let (gain, peak) = self.gain();
+        ReplayGain::new(gain, peak, None, None)
+    }
+
+    fn gain(&self) -> (Option<f64>, Option<f64>) {
+        let gain = Some(Self::REPLAY_GAIN_REFERENCE - self.integrated).filter(|g| g.is_finite());
+        (gain, Some(self.true_peak))
+    }
+}
+
+/// Loudness of every track of an album and of the album as a whole
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlbumLoudness {
+    tracks: Vec<Loudness>,
+    album: Loudness,
+}
+
+impl AlbumLoudness {
+    /// Measure every track, the album loudness is gated over all of them together as if they
+    /// were one track. The tracks are rewound afterwards
+    pub fn scan(tracks: &mut [Track]) -> Result<Self, AudioPlayerError> {
+        let mut album = LoudnessMeter::new(&Channels::FRONT_LEFT, 48000);
+        let mut loudness = vec![];
+        for track in tracks {
+            let meter = LoudnessMeter::scan(track)?;
+            loudness.push(meter.loudness());
+            album.merge(meter);
+        }
+        Ok(Self {
+            tracks: loudness,
+            album: album.loudness(),
+        })
+    }
+
+    /// In the order the tracks were given to [AlbumLoudness::scan]
+    pub fn tracks(&self) -> &[Loudness] {
+        &self.tracks
+    }
+
+    pub fn album(&self) -> &Loudness {
+        &self.album
+    }
+
+    /// Track and album values of [ReplayGain] for the track at `index`
+    pub fn replay_gain(&self, index: usize) -> Option<ReplayGain> {
+        let (track_gain, track_peak) = self.tracks.get(index)?.gain();
+        let (album_gain, album_peak) = self.album.gain();
+        Some(ReplayGain::new(
+            track_gain, track_peak, album_gain, album_peak,
+        ))
+    }
+}
+
+/// Measures loudness as in ITU-R BS.1770, which EBU R128 builds on
+struct LoudnessMeter {
+    /// Weight of each channel, surround channels count more and LFE not at all
+    weights: Vec<f64>,
+    /// [k_weighting] per channel
+    filters: Vec<[Biquad; 2]>,
+    true_peak: TruePeak,
+    /// Frames in 100 ms, the step between gating blocks
+    step: usize,
+    /// Weighted sum of squares of the current step and how many frames it has
+    sum: f64,
+    frames: usize,
+    /// Sums of the last steps, as many as fit into a short-term window
+    steps: VecDeque<f64>,
+    /// Mean square of every 400 ms momentary block
+    blocks: Vec<f64>,
+    /// Mean square of every 3 s short-term window
+    short_term: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    const BLOCK_STEPS: usize = 4;
+    const SHORT_TERM_STEPS: usize = 30;
+    const ABSOLUTE_GATE: f64 = -70.0;
+    const RELATIVE_GATE: f64 = -10.0;
+    const RANGE_RELATIVE_GATE: f64 = -20.0;
+
+    fn new(channels: &Channels, sample_rate: u32) -> Self {
+        let weights = channels
+            .iter()
+            .map(|channel| match channel {
+                Channels::LFE1 | Channels::LFE2 => 0.0,
+                Channels::REAR_LEFT
+                | Channels::REAR_RIGHT
+                | Channels::SIDE_LEFT
+                | Channels::SIDE_RIGHT => 1.41,
+                _ => 1.0,
+            })
+            .collect::<Vec<_>>();
+        let rate = sample_rate as f64;
+        let filters = weights.iter().map(|_| k_weighting(rate)).collect();
+        Self {
+            true_peak: TruePeak::new(weights.len(), sample_rate),
+            weights,
+            filters,
+            step: (rate / 10.0).round() as usize,
+            sum: 0.0,
+            frames: 0,
+            steps: VecDeque::new(),
+            blocks: vec![],
+            short_term: vec![],
+        }
+    }
+
+    fn scan(track: &mut Track) -> Result<Self, AudioPlayerError> {
+        let codec_params = track.decoded.codec_params();
+        let channels = codec_params.channels.unwrap_or(Channels::FRONT_LEFT);
+        let sample_rate = codec_params.sample_rate.unwrap_or(48000);
+        let mut meter = Self::new(&channels, sample_rate);
+        loop {
+            match track.decoded.next() {
+                Ok(buffer) => meter.add(&SampleBuf::from(buffer)),
+                Err(err) if err.end_of_stream() => break,
+                Err(err) if err.packet_error() => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        track.decoded.seek(Duration::ZERO)?;
+        Ok(meter)
+    }
+
+    fn add(&mut self, buffer: &SampleBuf) {
+        for frame in 0..buffer.frames() {
+            for (c, samples) in buffer
+                .channel_samples()
+                .enumerate()
+                .take(self.weights.len())
+            {
+                let sample = samples[frame];
+                self.true_peak.add(c, sample);
+                let [shelf, pass] = &mut self.filters[c];
+                let filtered = pass.process(shelf.process(sample));
+                self.sum += self.weights[c] * filtered * filtered;
+            }
+            self.frames += 1;
+            if self.frames == self.step {
+                self.end_step();
+            }
+        }
+    }
+
+    fn end_step(&mut self) {
+        self.steps.push_back(self.sum);
+        if self.steps.len() > Self::SHORT_TERM_STEPS {
+            self.steps.pop_front();
+        }
+        let mean = |steps: usize| {
+            let sum = self.steps.iter().rev().take(steps).sum::<f64>();
+            sum / (steps * self.step) as f64
+        };
+        if self.steps.len() >= Self::BLOCK_STEPS {
+            self.blocks.push(mean(Self::BLOCK_STEPS));
+        }
+        if self.steps.len() >= Self::SHORT_TERM_STEPS {
+            self.short_term.push(mean(Self::SHORT_TERM_STEPS));
+        }
+        self.sum = 0.0;
+        self.frames = 0;
+    }
+
+    /// Add the measurements of `other` as if they were made here after the existing ones
+    fn merge(&mut self, other: LoudnessMeter) {
+        self.blocks.extend(other.blocks);
+        self.short_term.extend(other.short_term);
+        self.true_peak.peak = self.true_peak.peak.max(other.true_peak.peak);
+    }
+
+    fn loudness(&self) -> Loudness {
+        Loudness {
+            integrated: Self::integrated(&self.blocks),
+            range: Self::range(&self.short_term),
+            true_peak: self.true_peak.peak,
+        }
+    }
+
+    fn lufs(mean_square: f64) -> f64 {
+        -0.691 + 10.0 * mean_square.log10()
+    }
+
+    /// Mean square of `blocks` that are louder than `gate`
+    fn gated_mean(blocks: &[f64], gate: f64) -> Option<f64> {
+        let gated = blocks.iter().filter(|&&b| Self::lufs(b) > gate);
+        let (sum, count) = gated.fold((0.0, 0), |(sum, count), b| (sum + b, count + 1));
+        (count > 0).then(|| sum / count as f64)
+    }
+
+    fn integrated(blocks: &[f64]) -> f64 {
+        let Some(mean) = Self::gated_mean(blocks, Self::ABSOLUTE_GATE) else {
+            return f64::NEG_INFINITY;
+        };
+        let gate = Self::lufs(mean) + Self::RELATIVE_GATE;
+        Self::gated_mean(blocks, gate.max(Self::ABSOLUTE_GATE))
+            .map_or(f64::NEG_INFINITY, Self::lufs)
+    }
+
+    /// Spread between the 10th and the 95th percentile of the gated short-term loudness
+    fn range(short_term: &[f64]) -> f64 {
+        let Some(mean) = Self::gated_mean(short_term, Self::ABSOLUTE_GATE) else {
+            return 0.0;
+        };
+        let gate = (Self::lufs(mean) + Self::RANGE_RELATIVE_GATE).max(Self::ABSOLUTE_GATE);
+        let mut loudness = short_term
+            .iter()
+            .map(|&s| Self::lufs(s))
+            .filter(|&l| l > gate)
+            .collect::<Vec<_>>();
+        if loudness.len() < 2 {
+            return 0.0;
+        }
+        loudness.sort_by(f64::total_cmp);
+        let percentile = |p: f64| loudness[((loudness.len() - 1) as f64 * p).round() as usize];
+        percentile(0.95) - percentile(0.10)
+    }
+}
+
+/// The K-weighting filter, a high shelf followed by a high pass. The coefficients are derived
+/// for any sample rate as in libebur128, matching those of BS.1770 at 48 kHz
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    // stage 1 models the acoustic effect of the head
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+    // stage 2 is the revised low-frequency B-curve
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+    [shelf, pass]
+}
+
+/// Peak between the samples, found by oversampling with a windowed sinc interpolator
+struct TruePeak {
+    factor: usize,
+    /// Coefficients of all phases interleaved, phase `p` uses every `factor`th from `p`
+    coefficients: Vec<f64>,
+    /// Latest samples per channel, newest first
+    history: Vec<VecDeque<f64>>,
+    peak: f64,
+}
+
+impl TruePeak {
+    const TAPS: usize = 49;
+
+    fn new(channels: usize, sample_rate: u32) -> Self {
+        // 4 times oversampling is enough below 96 kHz
+        let factor = match sample_rate {
+            ..=95_999 => 4,
+            96_000..=191_999 => 2,
+            _ => 1,
+        };
+        let middle = (Self::TAPS - 1) as f64 / 2.0;
+        let coefficients = (0..Self::TAPS)
+            .map(|i| {
+                let m = (i as f64 - middle) * std::f64::consts::PI / factor as f64;
+                let sinc = if m.abs() < 1e-9 { 1.0 } else { m.sin() / m };
+                let window = 0.5
+                    * (1.0 - (std::f64::consts::TAU * i as f64 / (Self::TAPS - 1) as f64).cos());
+                sinc * window
+            })
+            .collect();
+        let length = Self::TAPS.div_ceil(factor);
+        Self {
+            factor,
+            coefficients,
+            history: vec![VecDeque::from(vec![0.0; length]); channels],
+            peak: 0.0,
+        }
+    }
+
+    fn add(&mut self, channel: usize, sample: f64) {
+        let history = &mut self.history[channel];
+        history.pop_back();
+        history.push_front(sample);
+        if self.factor == 1 {
+            self.peak = self.peak.max(sample.abs());
+            return;
+        }
+        for phase in 0..self.factor {
+            let interpolated = self
+                .coefficients
+                .iter()
+                .skip(phase)
+                .step_by(self.factor)
+                .zip(history.iter())
+                .map(|(c, s)| c * s)
+                .sum::<f64>();
+            self.peak = self.peak.max(interpolated.abs());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+
+    use super::*;
+    use crate::track::tests::wav;
+
+    const RATE: u32 = 48000;
+
+    /// A stereo sine in parts of `(peak dBFS, seconds)`, `None` being silence
+    fn sine(frequency: f64, phase: f64, parts: &[(Option<f64>, u32)]) -> Track {
+        let mut samples = vec![];
+        let mut i = 0;
+        for &(level, seconds) in parts {
+            let amplitude = level.map_or(0.0, |db| 10f64.powf(db / 20.0));
+            for _ in 0..seconds * RATE {
+                let sample = amplitude * (TAU * frequency * i as f64 / RATE as f64 + phase).sin();
+                samples.extend([sample as f32; 2]);
+                i += 1;
+            }
+        }
+        Track::open_bytes(wav(&samples, 2, RATE), Some("wav")).unwrap()
+    }
+
+    fn scan(track: &mut Track) -> Loudness {
+        Loudness::scan(track).unwrap()
+    }
+
+    #[test]
+    fn reference_level() {
+        // EBU Tech 3341 case 1
+        let loudness = scan(&mut sine(1000.0, 0.0, &[(Some(-23.0), 10)]));
+        assert!(
+            (loudness.integrated() + 23.0).abs() <= 0.1,
+            "{}",
+            loudness.integrated()
+        );
+        assert!(loudness.range() < 0.1);
+        let peak = 10f64.powf(-23.0 / 20.0);
+        assert!((loudness.true_peak() / peak - 1.0).abs() < 0.01);
+        let gain = loudness.replay_gain().track_gain().unwrap();
+        assert!((gain - 5.0).abs() <= 0.1, "{}", gain);
+    }
+
+    #[test]
+    fn absolute_gate() {
+        // blocks that are partly silent still count, so there is little silence around the tone
+        let silent = (None, 2);
+        let loudness = scan(&mut sine(1000.0, 0.0, &[silent, (Some(-23.0), 20), silent]));
+        assert!(
+            (loudness.integrated() + 23.0).abs() <= 0.1,
+            "{}",
+            loudness.integrated()
+        );
+        let loudness = scan(&mut sine(1000.0, 0.0, &[silent]));
+        assert_eq!(loudness.integrated(), f64::NEG_INFINITY);
+        assert_eq!(loudness.replay_gain().track_gain(), None);
+    }
+
+    #[test]
+    fn relative_gate() {
+        // EBU Tech 3341 case 3 at half the length, the quiet parts are more than 10 LU below
+        // the rest
+        let quiet = (Some(-36.0), 5);
+        let loudness = scan(&mut sine(1000.0, 0.0, &[quiet, (Some(-23.0), 30), quiet]));
+        assert!(
+            (loudness.integrated() + 23.0).abs() <= 0.1,
+            "{}",
+            loudness.integrated()
+        );
+    }
+
+    #[test]
+    fn true_peak_between_samples() {
+        // at a quarter of the sample rate and 45 degrees the samples miss every peak
+        let mut track = sine(RATE as f64 / 4.0, TAU / 8.0, &[(Some(-6.0), 1)]);
+        let peak = 10f64.powf(-6.0 / 20.0);
+        let loudness = scan(&mut track);
+        assert!(
+            (loudness.true_peak() / peak - 1.0).abs() < 0.03,
+            "{} {}",
+            loudness.true_peak(),
+            peak
+        );
+    }
+}
@@ -1,249 +1,701 @@
-use std::sync::mpsc::{self, SyncSender, TryRecvError};
-
-use cpal::{
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-    Device, Sample, SizedSample, Stream, StreamError, SupportedStreamConfig,
-};
-use symphonia::core::{
-    audio::{AudioBufferRef, SampleBuffer as SymphoniaSampleBuffer},
-    conv::ConvertibleSample,
-};
-use tracing::info;
-
-use crate::buffer::{self, SampleBuf, SampleBuffer};
-
-#[derive(Debug, thiserror::Error)]
-pub(super) enum AudioOutputError {
-    #[error("OutputDeviceUnavailable")]
-    OutputDeviceUnavailable,
-    #[error("DefaultStreamConfigError {0}")]
-    DefaultStreamConfig(#[from] cpal::DefaultStreamConfigError),
-    #[error("SupportedStreamConfigsError {0}")]
-    SupportedOutputConfigs(#[from] cpal::SupportedStreamConfigsError),
-    #[error("SupportedOutputConfigs")]
-    NoSupportedOutputConfigs,
-    #[error("UnsupportedSampleFormat {0}")]
-    UnsupportedSampleFormat(cpal::SampleFormat),
-    #[error("BuildStreamError {0}")]
-    BuildStream(#[from] cpal::BuildStreamError),
-    #[error("PlayStreamError {0}")]
-    PlayStream(#[from] cpal::PlayStreamError),
-    #[error("PauseStreamError {0}")]
-    PauseStream(#[from] cpal::PauseStreamError),
-}
-
-pub(super) trait AudioOutputWrite {
-    fn write(&mut self, samples: &SampleBuffer);
-    fn play(&mut self) -> Result<(), AudioOutputError>;
-    fn pause(&mut self) -> Result<(), AudioOutputError>;
-    fn sample_rate(&self) -> u32;
-}
-
-pub(super) enum AudioOutputWriter {
-    Cpal(CpalAudioOutputWriter),
-}
-
-enum CpalAudioOutputWriter {
-    I8(CpalAudioOutput<i8>),
-    I16(CpalAudioOutput<i16>),
-    I32(CpalAudioOutput<i32>),
-    // I64(SymphoniaAudioOutput<i64>),
-    U8(CpalAudioOutput<u8>),
-    U16(CpalAudioOutput<u16>),
-    U32(CpalAudioOutput<u32>),
-    // U64(SymphoniaAudioOutput<u64>),
-    F32(CpalAudioOutput<f32>),
-    F64(CpalAudioOutput<f64>),
-}
-
-impl AudioOutputWriter {
-    pub(super) fn new() -> Result<AudioOutputWriter, AudioOutputError> {
-        let host = cpal::default_host();
-        let device = host
-            .default_output_device()
-            .ok_or(AudioOutputError::OutputDeviceUnavailable)?;
-        let config = device.default_output_config()?;
-        info!("default: {:?}", config);
-        let supported = device
-            .supported_output_configs()?
-            .next()
-            .ok_or(AudioOutputError::NoSupportedOutputConfigs)?
-            .with_max_sample_rate();
-        info!("supported: {:?}", supported);
-
-        let writer = match config.sample_format() {
-            cpal::SampleFormat::I8 => {
-                CpalAudioOutputWriter::I8(CpalAudioOutput::<i8>::new(&device, &config)?)
-            }
-            cpal::SampleFormat::I16 => {
-                CpalAudioOutputWriter::I16(CpalAudioOutput::<i16>::new(&device, &config)?)
-            }
-            cpal::SampleFormat::I32 => {
-                CpalAudioOutputWriter::I32(CpalAudioOutput::<i32>::new(&device, &config)?)
-            }
-            // cpal::SampleFormat::I64 => SymphoniaAudioOutputWriter::I32(
-            //     SymphoniaAudioOutputter::<i64>::new(&device, &config)?,
-            // ),
-            cpal::SampleFormat::U8 => {
-                CpalAudioOutputWriter::U8(CpalAudioOutput::<u8>::new(&device, &config)?)
-            }
-            cpal::SampleFormat::U16 => {
-                CpalAudioOutputWriter::U16(CpalAudioOutput::<u16>::new(&device, &config)?)
-            }
-            cpal::SampleFormat::U32 => {
-                CpalAudioOutputWriter::U32(CpalAudioOutput::<u32>::new(&device, &config)?)
-            }
-            // cpal::SampleFormat::U64 => SymphoniaAudioOutputWriter::U64(
-            //     SymphoniaAudioOutputter::<u64>::new(&device, &config)?,
-            // ),
-            cpal::SampleFormat::F32 => {
-                CpalAudioOutputWriter::F32(CpalAudioOutput::<f32>::new(&device, &config)?)
-            }
-            cpal::SampleFormat::F64 => {
-                CpalAudioOutputWriter::F64(CpalAudioOutput::<f64>::new(&device, &config)?)
-            }
-            sample_format => return Err(AudioOutputError::UnsupportedSampleFormat(sample_format)),
-        };
-        Ok(AudioOutputWriter::Cpal(writer))
-    }
-}
-
-macro_rules! match_cpal_audio_output_writer {
-    (|$writer:ident| $expression:expr) => {
-        match $writer {
-            CpalAudioOutputWriter::I8($writer) => $expression,
-            CpalAudioOutputWriter::I16($writer) => $expression,
-            CpalAudioOutputWriter::I32($writer) => $expression,
-            // CpalAudioOutputWriter::I64($writer) => $expression,
-            CpalAudioOutputWriter::U8($writer) => $expression,
-            CpalAudioOutputWriter::U16($writer) => $expression,
-            CpalAudioOutputWriter::U32($writer) => $expression,
-            // CpalAudioOutputWriter::U64($writer) => $expression,
-            CpalAudioOutputWriter::F32($writer) => $expression,
-            CpalAudioOutputWriter::F64($writer) => $expression,
-        }
-    };
-}
-
-impl AudioOutputWrite for AudioOutputWriter {
-    fn write(&mut self, samples: &SampleBuffer) {
-        match self {
-            AudioOutputWriter::Cpal(writer) => {
-                match_cpal_audio_output_writer!(|writer| writer.write(samples))
-            }
-        }
-    }
-
-    fn play(&mut self) -> Result<(), AudioOutputError> {
-        match self {
-            AudioOutputWriter::Cpal(writer) => {
-                match_cpal_audio_output_writer!(|writer| writer.play())
-            }
-        }
-    }
-
-    fn pause(&mut self) -> Result<(), AudioOutputError> {
-        match self {
-            AudioOutputWriter::Cpal(writer) => {
-                match_cpal_audio_output_writer!(|writer| writer.pause())
-            }
-        }
-    }
-
-    fn sample_rate(&self) -> u32 {
-        match self {
-            AudioOutputWriter::Cpal(writer) => {
-                match_cpal_audio_output_writer!(|writer| writer.sample_rate())
-            }
-        }
-    }
-}
-
-struct CpalAudioOutput<T: Sample> {
-    stream: Stream,
-    tx: SyncSender<T>,
-    sample_rate: u32,
-}
-
-impl<T: SizedSample + cpal::FromSample<f64> + ConvertibleSample + Send + 'static>
-    CpalAudioOutput<T>
-{
-    fn new(
-        device: &Device,
-        config: &SupportedStreamConfig,
-    ) -> Result<CpalAudioOutput<T>, AudioOutputError> {
-        fn handle_err(err: StreamError) {
-            panic!("{}", err);
-        }
-
-        // May need to try rtrb/ringbuffer for performance
-        let (tx, rx) = mpsc::sync_channel::<T>(config.sample_rate().0 as usize);
-        let stream = device.build_output_stream(
-            &config.config(),
-            move |data, _| {
-                data.iter_mut().for_each(|d| {
-                    *d = match rx.try_recv() {
-                        Ok(data) => data,
-                        Err(TryRecvError::Empty) => T::MID,
-                        Err(TryRecvError::Disconnected) => panic!("closed"),
-                    }
-                });
-            },
-            handle_err,
-            None,
-        )?;
-        let sample_rate = config.sample_rate().0;
-
-        Ok(CpalAudioOutput {
-            stream,
-            tx,
-            sample_rate,
-        })
-    }
-
-    fn write_buf(&mut self, buffer: &SampleBuf) {
-        for sample in buffer.interleaved() {
-            self.tx.send(sample.to_sample()).unwrap();
-        }
-    }
-
-    fn write_symphonia(&mut self, buffer: AudioBufferRef) {
-        if buffer.frames() == 0 {
-            return;
-        }
-        let spec = buffer.spec();
-
-        let duration = buffer.capacity() as u64;
-        let mut sample_buffer = SymphoniaSampleBuffer::<T>::new(duration.into(), *spec);
-        sample_buffer.copy_interleaved_ref(buffer);
-        sample_buffer.samples().iter().for_each(|&s| {
-            self.tx.send(s).unwrap();
-        })
-    }
-}
-
-impl<T: SizedSample + cpal::FromSample<f64> + ConvertibleSample + Send + 'static> AudioOutputWrite
-    for CpalAudioOutput<T>
-{
-    fn write(&mut self, samples: &SampleBuffer) {
-        match samples {
-            SampleBuffer::Buf(buffer) => self.write_buf(buffer),
-            SampleBuffer::BufRef(buffer) => self.write_buf(buffer),
-            SampleBuffer::Symphonia(buffer) => self.write_symphonia(buffer.clone()),
-        }
-    }
-
-    fn play(&mut self) -> Result<(), AudioOutputError> {
-        self.stream.play()?;
-        Ok(())
-    }
-
-    fn pause(&mut self) -> Result<(), AudioOutputError> {
-        self.stream.pause()?;
-        Ok(())
-    }
-
-    fn sample_rate(&self) -> u32 {
-        self.sample_rate
-    }
-}
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Device, HostId, OutputCallbackInfo, Sample, SizedSample, Stream, StreamError,
+    SupportedStreamConfig,
+};
+use rtrb::{Consumer, Producer, RingBuffer};
+use symphonia::core::conv::ConvertibleSample;
+use tracing::info;
+
+use crate::buffer::{self, SampleBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum AudioOutputError {
+    #[error("OutputDeviceUnavailable")]
+    OutputDeviceUnavailable,
+    #[error("OutputDeviceNotFound {0}")]
+    OutputDeviceNotFound(String),
+    #[error("HostUnavailable {0}")]
+    HostUnavailable(#[from] cpal::HostUnavailable),
+    #[error("DevicesError {0}")]
+    Devices(#[from] cpal::DevicesError),
+    #[error("DeviceNameError {0}")]
+    DeviceName(#[from] cpal::DeviceNameError),
+    #[error("DefaultStreamConfigError {0}")]
+    DefaultStreamConfig(#[from] cpal::DefaultStreamConfigError),
+    #[error("SupportedStreamConfigsError {0}")]
+    SupportedOutputConfigs(#[from] cpal::SupportedStreamConfigsError),
+    #[error("SupportedOutputConfigs")]
+    NoSupportedOutputConfigs,
+    #[error("UnsupportedSampleFormat {0}")]
+    UnsupportedSampleFormat(cpal::SampleFormat),
+    #[error("BuildStreamError {0}")]
+    BuildStream(#[from] cpal::BuildStreamError),
+    #[error("PlayStreamError {0}")]
+    PlayStream(#[from] cpal::PlayStreamError),
+    #[error("PauseStreamError {0}")]
+    PauseStream(#[from] cpal::PauseStreamError),
+    #[error("StreamError {0}")]
+    Stream(#[from] StreamError),
+    #[error("StreamClosed")]
+    StreamClosed,
+    /// Failure of an [AudioSink] other than an output device
+    #[error("SinkError {0}")]
+    Sink(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// An output device available to [crate::AudioPlayerOptions::device] and
+/// [crate::AudioPlayerController::set_device]
+#[derive(Debug, Clone)]
+pub struct OutputDevice {
+    host: String,
+    name: String,
+    default: bool,
+    configs: Vec<OutputConfig>,
+}
+
+impl OutputDevice {
+    /// Unique among all hosts, unlike [OutputDevice::name]
+    pub fn id(&self) -> String {
+        format!("{}:{}", self.host, self.name)
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether this is the default device of the default host
+    pub fn is_default(&self) -> bool {
+        self.default
+    }
+
+    pub fn configs(&self) -> &[OutputConfig] {
+        &self.configs
+    }
+}
+
+/// A range of stream configurations supported by an [OutputDevice]
+#[derive(Debug, Clone)]
+pub struct OutputConfig {
+    channels: u16,
+    min_sample_rate: u32,
+    max_sample_rate: u32,
+    sample_format: String,
+}
+
+impl OutputConfig {
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn min_sample_rate(&self) -> u32 {
+        self.min_sample_rate
+    }
+
+    pub fn max_sample_rate(&self) -> u32 {
+        self.max_sample_rate
+    }
+
+    pub fn sample_format(&self) -> &str {
+        &self.sample_format
+    }
+}
+
+/// Hosts in the order devices are looked up, the default host first
+fn host_ids() -> Vec<HostId> {
+    let default = cpal::default_host().id();
+    std::iter::once(default)
+        .chain(cpal::available_hosts().into_iter().filter(|id| *id != default))
+        .collect()
+}
+
+pub(super) fn output_devices() -> Result<Vec<OutputDevice>, AudioOutputError> {
+    let default_name = default_device_name();
+    let mut devices = vec![];
+    for (i, host_id) in host_ids().into_iter().enumerate() {
+        // hosts like JACK are only usable while their server is running
+        let Ok(host) = cpal::host_from_id(host_id) else {
+            continue;
+        };
+        for device in host.output_devices()? {
+            let Ok(name) = device.name() else {
+                continue;
+            };
+            let configs = match device.supported_output_configs() {
+                Ok(configs) => configs
+                    .map(|config| OutputConfig {
+                        channels: config.channels(),
+                        min_sample_rate: config.min_sample_rate().0,
+                        max_sample_rate: config.max_sample_rate().0,
+                        sample_format: config.sample_format().to_string(),
+                    })
+                    .collect(),
+                Err(_) => vec![],
+            };
+            devices.push(OutputDevice {
+                host: host_id.name().to_string(),
+                default: i == 0 && default_name.as_ref() == Some(&name),
+                name,
+                configs,
+            });
+        }
+    }
+    Ok(devices)
+}
+
+/// Find an output device by [OutputDevice::id] or [OutputDevice::name], the default device if
+/// `device` is `None`
+fn find_device(device: Option<&str>) -> Result<Device, AudioOutputError> {
+    let Some(selector) = device else {
+        return cpal::default_host()
+            .default_output_device()
+            .ok_or(AudioOutputError::OutputDeviceUnavailable);
+    };
+    for host_id in host_ids() {
+        let Ok(host) = cpal::host_from_id(host_id) else {
+            continue;
+        };
+        for device in host.output_devices()? {
+            let Ok(name) = device.name() else {
+                continue;
+            };
+            if name == selector || format!("{}:{}", host_id.name(), name) == selector {
+                return Ok(device);
+            }
+        }
+    }
+    Err(AudioOutputError::OutputDeviceNotFound(selector.to_string()))
+}
+
+impl AudioOutputError {
+    /// The device is gone or the stream broke, a new stream is needed to continue
+    pub(super) fn device_lost(&self) -> bool {
+        matches!(
+            self,
+            AudioOutputError::Stream(_)
+                | AudioOutputError::StreamClosed
+                | AudioOutputError::PlayStream(cpal::PlayStreamError::DeviceNotAvailable)
+                | AudioOutputError::PauseStream(cpal::PauseStreamError::DeviceNotAvailable)
+        )
+    }
+}
+
+pub(super) fn default_device_name() -> Option<String> {
+    cpal::default_host()
+        .default_output_device()
+        .and_then(|device| device.name().ok())
+}
+
+/// Destination of the samples played by [crate::AudioPlayer], see [crate::AudioPlayer::with_sink]
+pub trait AudioSink {
+    /// Blocks while the sink cannot take more samples, which is what paces playback
+    fn write(&mut self, samples: &SampleBuf) -> Result<(), AudioOutputError>;
+    fn play(&mut self) -> Result<(), AudioOutputError>;
+    fn pause(&mut self) -> Result<(), AudioOutputError>;
+    /// Samples are resampled to this rate before [AudioSink::write]
+    fn sample_rate(&self) -> u32;
+    /// How long until a sample written now is heard, used to report the playback position
+    fn delay(&self) -> Duration {
+        Duration::ZERO
+    }
+    /// Drop the samples written but not played yet, so what is written next is heard right away
+    fn clear(&mut self) -> Result<(), AudioOutputError> {
+        Ok(())
+    }
+}
+
+/// Settings of the streams opened by [AudioOutputWriter::new]
+#[derive(Clone)]
+pub(super) struct StreamOptions {
+    /// Length of the ring buffer between the writer and the audio callback
+    pub(super) buffer_length: Duration,
+    /// Incremented by the audio callback whenever it runs out of samples
+    pub(super) underruns: Arc<AtomicU64>,
+}
+
+pub(super) enum AudioOutputWriter {
+    Cpal(CpalAudioOutputWriter),
+    Sink(Box<dyn AudioSink + Send>),
+}
+
+enum CpalAudioOutputWriter {
+    I8(CpalAudioOutput<i8>),
+    I16(CpalAudioOutput<i16>),
+    I32(CpalAudioOutput<i32>),
+    // I64(SymphoniaAudioOutput<i64>),
+    U8(CpalAudioOutput<u8>),
+    U16(CpalAudioOutput<u16>),
+    U32(CpalAudioOutput<u32>),
+    // U64(SymphoniaAudioOutput<u64>),
+    F32(CpalAudioOutput<f32>),
+    F64(CpalAudioOutput<f64>),
+}
+
+impl AudioOutputWriter {
+    /// `device` is an [OutputDevice::id] or [OutputDevice::name], the default device if `None`
+    pub(super) fn new(
+        device: Option<&str>,
+        options: &StreamOptions,
+    ) -> Result<AudioOutputWriter, AudioOutputError> {
+        let device = find_device(device)?;
+        info!("device: {:?}", device.name());
+        let config = device.default_output_config()?;
+        info!("default: {:?}", config);
+        let supported = device
+            .supported_output_configs()?
+            .next()
+            .ok_or(AudioOutputError::NoSupportedOutputConfigs)?
+            .with_max_sample_rate();
+        info!("supported: {:?}", supported);
+
+        let writer = match config.sample_format() {
+            cpal::SampleFormat::I8 => {
+                CpalAudioOutputWriter::I8(CpalAudioOutput::<i8>::new(&device, &config, options)?)
+            }
+            cpal::SampleFormat::I16 => {
+                CpalAudioOutputWriter::I16(CpalAudioOutput::<i16>::new(&device, &config, options)?)
+            }
+            cpal::SampleFormat::I32 => {
+                CpalAudioOutputWriter::I32(CpalAudioOutput::<i32>::new(&device, &config, options)?)
+            }
+            // cpal::SampleFormat::I64 => SymphoniaAudioOutputWriter::I32(
+            //     SymphoniaAudioOutputter::<i64>::new(&device, &config, options)?,
+            // ),
+            cpal::SampleFormat::U8 => {
+                CpalAudioOutputWriter::U8(CpalAudioOutput::<u8>::new(&device, &config, options)?)
+            }
+            cpal::SampleFormat::U16 => {
+                CpalAudioOutputWriter::U16(CpalAudioOutput::<u16>::new(&device, &config, options)?)
+            }
+            cpal::SampleFormat::U32 => {
+                CpalAudioOutputWriter::U32(CpalAudioOutput::<u32>::new(&device, &config, options)?)
+            }
+            // cpal::SampleFormat::U64 => SymphoniaAudioOutputWriter::U64(
+            //     SymphoniaAudioOutputter::<u64>::new(&device, &config, options)?,
+            // ),
+            cpal::SampleFormat::F32 => {
+                CpalAudioOutputWriter::F32(CpalAudioOutput::<f32>::new(&device, &config, options)?)
+            }
+            cpal::SampleFormat::F64 => {
+                CpalAudioOutputWriter::F64(CpalAudioOutput::<f64>::new(&device, &config, options)?)
+            }
+            sample_format => return Err(AudioOutputError::UnsupportedSampleFormat(sample_format)),
+        };
+        Ok(AudioOutputWriter::Cpal(writer))
+    }
+}
+
+macro_rules! match_cpal_audio_output_writer {
+    (|$writer:ident| $expression:expr) => {
+        match $writer {
+            CpalAudioOutputWriter::I8($writer) => $expression,
+            CpalAudioOutputWriter::I16($writer) => $expression,
+            CpalAudioOutputWriter::I32($writer) => $expression,
+            // CpalAudioOutputWriter::I64($writer) => $expression,
+            CpalAudioOutputWriter::U8($writer) => $expression,
+            CpalAudioOutputWriter::U16($writer) => $expression,
+            CpalAudioOutputWriter::U32($writer) => $expression,
+            // CpalAudioOutputWriter::U64($writer) => $expression,
+            CpalAudioOutputWriter::F32($writer) => $expression,
+            CpalAudioOutputWriter::F64($writer) => $expression,
+        }
+    };
+}
+
+impl AudioOutputWriter {
+    pub(super) fn device_name(&self) -> Option<&str> {
+        match self {
+            AudioOutputWriter::Cpal(writer) => {
+                match_cpal_audio_output_writer!(|writer| writer.device_name.as_deref())
+            }
+            AudioOutputWriter::Sink(_) => None,
+        }
+    }
+
+    /// Nothing more is written for now, so the buffer running empty is not an underrun
+    pub(super) fn idle(&self) {
+        match self {
+            AudioOutputWriter::Cpal(writer) => {
+                match_cpal_audio_output_writer!(|writer| writer.idle.store(true, Ordering::Relaxed))
+            }
+            AudioOutputWriter::Sink(_) => (),
+        }
+    }
+
+    /// Fade out from what is heard right now, returns once silent so the stream can be paused or
+    /// cleared without a click. Samples not yet played stay in the buffer
+    pub(super) fn fade_out(&self, duration: Duration) {
+        match self {
+            AudioOutputWriter::Cpal(writer) => {
+                match_cpal_audio_output_writer!(|writer| writer.fade_out(duration))
+            }
+            AudioOutputWriter::Sink(_) => (),
+        }
+    }
+
+    /// Undo [AudioOutputWriter::fade_out], ramping up over `duration`
+    pub(super) fn fade_in(&self, duration: Duration) {
+        match self {
+            AudioOutputWriter::Cpal(writer) => {
+                match_cpal_audio_output_writer!(|writer| writer.fade_in(duration))
+            }
+            AudioOutputWriter::Sink(_) => (),
+        }
+    }
+}
+
+impl AudioSink for AudioOutputWriter {
+    fn write(&mut self, samples: &SampleBuf) -> Result<(), AudioOutputError> {
+        match self {
+            AudioOutputWriter::Cpal(writer) => {
+                match_cpal_audio_output_writer!(|writer| writer.write(samples))
+            }
+            AudioOutputWriter::Sink(sink) => sink.write(samples),
+        }
+    }
+
+    fn play(&mut self) -> Result<(), AudioOutputError> {
+        match self {
+            AudioOutputWriter::Cpal(writer) => {
+                match_cpal_audio_output_writer!(|writer| writer.play())
+            }
+            AudioOutputWriter::Sink(sink) => sink.play(),
+        }
+    }
+
+    fn pause(&mut self) -> Result<(), AudioOutputError> {
+        match self {
+            AudioOutputWriter::Cpal(writer) => {
+                match_cpal_audio_output_writer!(|writer| writer.pause())
+            }
+            AudioOutputWriter::Sink(sink) => sink.pause(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self {
+            AudioOutputWriter::Cpal(writer) => {
+                match_cpal_audio_output_writer!(|writer| writer.sample_rate())
+            }
+            AudioOutputWriter::Sink(sink) => sink.sample_rate(),
+        }
+    }
+
+    fn delay(&self) -> Duration {
+        match self {
+            AudioOutputWriter::Cpal(writer) => {
+                match_cpal_audio_output_writer!(|writer| writer.delay())
+            }
+            AudioOutputWriter::Sink(sink) => sink.delay(),
+        }
+    }
+
+    fn clear(&mut self) -> Result<(), AudioOutputError> {
+        match self {
+            AudioOutputWriter::Cpal(writer) => {
+                match_cpal_audio_output_writer!(|writer| writer.clear())
+            }
+            AudioOutputWriter::Sink(sink) => sink.clear(),
+        }
+    }
+}
+
+/// Progress of the audio callback, shared without locks with the writer
+struct OutputClock {
+    /// Reference for `callback` as [Instant] cannot be stored atomically
+    epoch: Instant,
+    /// Nanoseconds after `epoch` the last callback ran
+    callback: AtomicU64,
+    /// Nanoseconds until the samples of the last callback are played, as reported by the device
+    latency: AtomicU64,
+    /// Nanoseconds of samples taken by the last callback
+    period: AtomicU64,
+    /// Samples taken from the ring buffer, whether played or dropped
+    read: AtomicU64,
+    /// Samples up to which the callback drops what it reads instead of playing it
+    discard: AtomicU64,
+}
+
+impl OutputClock {
+    fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            callback: AtomicU64::new(0),
+            latency: AtomicU64::new(0),
+            period: AtomicU64::new(0),
+            read: AtomicU64::new(0),
+            discard: AtomicU64::new(0),
+        }
+    }
+
+    fn update(&self, info: &OutputCallbackInfo, period: Duration) {
+        let timestamp = info.timestamp();
+        let latency = timestamp
+            .playback
+            .duration_since(&timestamp.callback)
+            .unwrap_or_default();
+        let callback = self.epoch.elapsed();
+        self.callback
+            .store(callback.as_nanos() as u64, Ordering::Relaxed);
+        self.latency
+            .store(latency.as_nanos() as u64, Ordering::Relaxed);
+        self.period
+            .store(period.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Time until the samples taken by the callback so far have all been played, the part
+    /// played since the last callback is interpolated from the clock
+    fn pending(&self) -> Duration {
+        let callback = Duration::from_nanos(self.callback.load(Ordering::Relaxed));
+        let latency = Duration::from_nanos(self.latency.load(Ordering::Relaxed));
+        let period = Duration::from_nanos(self.period.load(Ordering::Relaxed));
+        let played = self.epoch.elapsed().saturating_sub(callback).min(period);
+        (latency + period).saturating_sub(played)
+    }
+}
+
+/// Level ramp of the audio callback, so pausing and stopping fade out instead of cutting off
+struct OutputFade {
+    /// Whether the callback ramps up to full level or down to silence
+    audible: AtomicBool,
+    /// Change of level per frame, the bits of an `f64`
+    step: AtomicU64,
+    /// Set by the callback once faded out, from then on it leaves the samples in the buffer
+    silent: AtomicBool,
+}
+
+impl OutputFade {
+    fn new() -> Self {
+        Self {
+            audible: AtomicBool::new(true),
+            step: AtomicU64::new(f64::INFINITY.to_bits()),
+            silent: AtomicBool::new(false),
+        }
+    }
+
+    fn set(&self, audible: bool, duration: Duration, sample_rate: u32) {
+        let step = 1.0 / (duration.as_secs_f64() * sample_rate as f64);
+        self.step.store(step.to_bits(), Ordering::Relaxed);
+        if audible {
+            self.silent.store(false, Ordering::Relaxed);
+        }
+        self.audible.store(audible, Ordering::Release);
+    }
+}
+
+struct CpalAudioOutput<T: Sample> {
+    stream: Stream,
+    producer: Producer<T>,
+    /// How long to wait for the callback to make room in a full buffer
+    wait: Duration,
+    /// Set by [AudioOutputWriter::idle] until the next write
+    idle: Arc<AtomicBool>,
+    /// Errors reported by cpal on the audio thread
+    errors: Receiver<StreamError>,
+    clock: Arc<OutputClock>,
+    fade: Arc<OutputFade>,
+    /// Samples pushed to the ring buffer
+    written: u64,
+    sample_rate: u32,
+    channels: u16,
+    device_name: Option<String>,
+}
+
+impl<T: SizedSample + cpal::FromSample<f64> + ConvertibleSample + Send + 'static>
+    CpalAudioOutput<T>
+{
+    fn new(
+        device: &Device,
+        config: &SupportedStreamConfig,
+        options: &StreamOptions,
+    ) -> Result<CpalAudioOutput<T>, AudioOutputError> {
+        // the stream reports errors on its own thread, pass them on to the writer
+        let (error_tx, errors) = mpsc::channel();
+        let handle_err = move |err: StreamError| {
+            let _ = error_tx.send(err);
+        };
+
+        let samples = options.buffer_length.as_secs_f64()
+            * config.sample_rate().0 as f64
+            * config.channels() as f64;
+        let (producer, mut consumer) = RingBuffer::<T>::new((samples as usize).max(1));
+        let idle = Arc::new(AtomicBool::new(true));
+        let callback_idle = idle.clone();
+        let underruns = options.underruns.clone();
+        let clock = Arc::new(OutputClock::new());
+        let callback_clock = clock.clone();
+        let fade = Arc::new(OutputFade::new());
+        let callback_fade = fade.clone();
+        let mut level = 1.0;
+        let channels = config.channels() as usize;
+        let samples_per_second = config.sample_rate().0 as f64 * config.channels() as f64;
+        let stream = device.build_output_stream(
+            &config.config(),
+            move |data: &mut [T], info| {
+                // samples cleared by the writer are never played
+                let mut read = callback_clock.read.load(Ordering::Relaxed);
+                let discard = callback_clock.discard.load(Ordering::Acquire);
+                read += Self::skip(&mut consumer, discard.saturating_sub(read) as usize) as u64;
+                let audible = callback_fade.audible.load(Ordering::Acquire);
+                let step = f64::from_bits(callback_fade.step.load(Ordering::Relaxed));
+                // only take what is still heard while fading out
+                let wanted = match audible {
+                    true => data.len(),
+                    false => ((level / step).ceil() as usize * channels).min(data.len()),
+                };
+                let filled = Self::fill(&mut consumer, &mut data[..wanted]);
+                data[wanted..].fill(T::MID);
+                Self::ramp(&mut data[..filled], channels, &mut level, audible, step);
+                callback_fade
+                    .silent
+                    .store(!audible && level == 0.0, Ordering::Release);
+                read += filled as u64;
+                callback_clock.read.store(read, Ordering::Relaxed);
+                if filled < wanted && !callback_idle.load(Ordering::Relaxed) {
+                    underruns.fetch_add(1, Ordering::Relaxed);
+                }
+                // silence padding an underrun does not move the position
+                let period = Duration::from_secs_f64(filled as f64 / samples_per_second);
+                callback_clock.update(info, period);
+            },
+            handle_err,
+            None,
+        )?;
+        let sample_rate = config.sample_rate().0;
+
+        Ok(CpalAudioOutput {
+            stream,
+            producer,
+            wait: options.buffer_length / 8,
+            idle,
+            errors,
+            clock,
+            fade,
+            written: 0,
+            sample_rate,
+            channels: config.channels(),
+            device_name: device.name().ok(),
+        })
+    }
+
+    /// Copy as many samples as are available to `data` and silence the rest, this runs on the
+    /// audio thread so it must not block or allocate
+    fn fill(consumer: &mut Consumer<T>, data: &mut [T]) -> usize {
+        let filled = consumer.slots().min(data.len());
+        if let Ok(chunk) = consumer.read_chunk(filled) {
+            let (first, second) = chunk.as_slices();
+            data[..first.len()].copy_from_slice(first);
+            data[first.len()..filled].copy_from_slice(second);
+            chunk.commit_all();
+        }
+        data[filled..].fill(T::MID);
+        filled
+    }
+
+    /// Move `level` towards full level or silence by `step` every frame and apply it
+    fn ramp(data: &mut [T], channels: usize, level: &mut f64, audible: bool, step: f64) {
+        if audible && *level == 1.0 {
+            return;
+        }
+        for frame in data.chunks_mut(channels) {
+            *level = match audible {
+                true => (*level + step).min(1.0),
+                false => (*level - step).max(0.0),
+            };
+            let amp = <T::Float as Sample>::from_sample(*level);
+            frame.iter_mut().for_each(|s| *s = s.mul_amp(amp));
+        }
+    }
+
+    fn fade_out(&self, duration: Duration) {
+        self.fade.set(false, duration, self.sample_rate);
+        // the callback is not called while the stream is paused, don't wait for it forever
+        let deadline = Instant::now() + duration + self.wait;
+        while !self.fade.silent.load(Ordering::Acquire) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn fade_in(&self, duration: Duration) {
+        self.fade.set(true, duration, self.sample_rate);
+    }
+
+    /// Drop up to `samples` without playing them
+    fn skip(consumer: &mut Consumer<T>, samples: usize) -> usize {
+        let skipped = consumer.slots().min(samples);
+        if let Ok(chunk) = consumer.read_chunk(skipped) {
+            chunk.commit_all();
+        }
+        skipped
+    }
+}
+
+impl<T: SizedSample + cpal::FromSample<f64> + ConvertibleSample + Send + 'static> AudioSink
+    for CpalAudioOutput<T>
+{
+    fn write(&mut self, samples: &SampleBuf) -> Result<(), AudioOutputError> {
+        self.idle.store(false, Ordering::Relaxed);
+        let mut samples = samples.interleaved().map(|s| s.to_sample::<T>()).peekable();
+        while samples.peek().is_some() {
+            // gives up when the stream fails and stops consuming samples
+            if let Ok(err) = self.errors.try_recv() {
+                return Err(err.into());
+            }
+            if self.producer.is_abandoned() {
+                return Err(AudioOutputError::StreamClosed);
+            }
+            let slots = self.producer.slots();
+            if slots == 0 {
+                std::thread::sleep(self.wait);
+                continue;
+            }
+            if let Ok(chunk) = self.producer.write_chunk_uninit(slots) {
+                self.written += chunk.fill_from_iter(&mut samples) as u64;
+            }
+        }
+        Ok(())
+    }
+
+    fn play(&mut self) -> Result<(), AudioOutputError> {
+        self.stream.play()?;
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<(), AudioOutputError> {
+        self.stream.pause()?;
+        Ok(())
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Samples still in the ring buffer plus those the device has yet to play
+    fn delay(&self) -> Duration {
+        let read = self.clock.read.load(Ordering::Relaxed);
+        let discard = self.clock.discard.load(Ordering::Relaxed);
+        let buffered = self.written.saturating_sub(read.max(discard));
+        let buffered = buffered as f64 / (self.sample_rate as f64 * self.channels as f64);
+        Duration::from_secs_f64(buffered) + self.clock.pending()
+    }
+
+    /// The callback drops everything written so far, the few samples already passed on to the
+    /// device are still played
+    fn clear(&mut self) -> Result<(), AudioOutputError> {
+        self.clock.discard.store(self.written, Ordering::Release);
+        Ok(())
+    }
+}
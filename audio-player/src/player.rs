@@ -1,280 +1,1686 @@
-use std::{
-    error::Error,
-    path::Path,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        mpsc::{self, SendError, Sender},
-        Arc, Condvar, Mutex,
-    },
-    thread::JoinHandle,
-    time::Duration,
-};
-
-use crate::{
-    buffer::SampleBuffer,
-    decoder::{self, DecodedTrack, DecoderError},
-    output::{AudioOutputWrite, AudioOutputWriter},
-    resampler::{ResamplerError, RubatoResamplerBuffered},
-    track::Track,
-};
-
-#[derive(Debug, thiserror::Error)]
-pub enum AudioPlayerError {
-    #[error("DecoderError {0}")]
-    Decoder(#[from] DecoderError),
-    #[error("ExecutorError {0}")]
-    Executor(#[from] AudioPlayerExecutorError),
-}
-
-pub struct AudioPlayer {
-    controller: AudioPlayerController,
-    executor: AudioPlayerExecutor,
-}
-
-impl AudioPlayer {
-    pub fn new() -> Self {
-        let controller = AudioPlayerController::new();
-        let executor = AudioPlayerExecutor::new(controller.clone());
-        Self {
-            controller,
-            executor,
-        }
-    }
-
-    pub fn controller(&self) -> &AudioPlayerController {
-        &self.controller
-    }
-
-    pub fn open<F: AsRef<Path>>(&mut self, file: F) -> Result<Track, AudioPlayerError> {
-        let track = decoder::decode(&file)?;
-        Ok(track)
-    }
-
-    // Place track on queue
-    pub fn queue(&self, track: Track) -> Result<(), AudioPlayerError> {
-        self.executor.queue(track.decoded)?;
-        Ok(())
-    }
-
-    pub fn running(&self) -> bool {
-        self.controller.running()
-    }
-
-    // drain all tracks in the queue
-    pub fn drain(&mut self) {
-        self.executor = AudioPlayerExecutor::new(self.controller.clone());
-    }
-
-    pub fn wait_until_end(self) {
-        self.executor.wait_until_end();
-    }
-}
-
-#[derive(Debug)]
-pub enum AudioPlayerControllerError {
-    NotPlaying,
-}
-
-#[derive(Clone)]
-pub struct AudioPlayerController {
-    state: Arc<Mutex<AudioPlayerControllerState>>,
-    executor_condvar: Arc<Condvar>,
-    controller_condvar: Arc<Condvar>,
-}
-
-impl AudioPlayerController {
-    fn new() -> Self {
-        let state = Arc::new(Mutex::new(AudioPlayerControllerState::new()));
-        let executor_condvar = Arc::new(Condvar::new());
-        let controller_condvar = Arc::new(Condvar::new());
-        Self {
-            state,
-            executor_condvar,
-            controller_condvar,
-        }
-    }
-
-    pub fn play(&self) {
-        let mut state = self.state.lock().unwrap();
-        (*state).playing = true;
-        self.executor_condvar.notify_all();
-    }
-
-    pub fn pause(&self) {
-        let mut state = self.state.lock().unwrap();
-        (*state).playing = false;
-        self.executor_condvar.notify_all();
-    }
-
-    pub fn playing(&self) -> bool {
-        let state = self.state.lock().unwrap();
-        (*state).playing
-    }
-
-    pub fn position(&self) -> Result<Duration, AudioPlayerControllerError> {
-        let state = self.state.lock().unwrap();
-        state.position.ok_or(AudioPlayerControllerError::NotPlaying)
-    }
-
-    pub fn seek(&self, progress: Duration) {
-        let mut state = self.state.lock().unwrap();
-        if !state.running {
-            return;
-        }
-        (*state).seek_position = Some(progress);
-        while state.playing && state.seek_position.is_some() {
-            state = self.controller_condvar.wait(state).unwrap();
-        }
-    }
-
-    fn running(&self) -> bool {
-        let state = self.state.lock().unwrap();
-        (*state).running
-    }
-}
-
-struct AudioPlayerControllerState {
-    running: bool,
-    playing: bool,
-    position: Option<Duration>,
-    seek_position: Option<Duration>,
-}
-
-impl AudioPlayerControllerState {
-    fn new() -> Self {
-        let running = false;
-        let playing = false;
-        let position = None;
-        let seek_position = None;
-        Self {
-            running,
-            playing,
-            position,
-            seek_position,
-        }
-    }
-}
-
-#[derive(Debug, thiserror::Error)]
-enum AudioPlayerExecutorError {
-    #[error("SendError {0}")]
-    Send(#[from] SendError<DecodedTrack>),
-}
-
-struct AudioPlayerExecutor {
-    /// This is an option to drop in [AudioPlayerExecutor::wait_until_end]
-    tx: Option<Sender<DecodedTrack>>,
-    dropped: Arc<AtomicBool>,
-    /// This is an option to `join` in [AudioPlayerExecutor::wait_until_end]
-    handle: Option<JoinHandle<()>>,
-}
-
-impl AudioPlayerExecutor {
-    fn new(controller: AudioPlayerController) -> Self {
-        let (tx, rx) = mpsc::channel::<DecodedTrack>();
-        let dropped = Arc::new(AtomicBool::new(false));
-        let dropped_clone = dropped.clone();
-        let handle = std::thread::spawn(move || {
-            let run = move || -> Result<(), Box<dyn Error>> {
-                let mut output = AudioOutputWriter::new()?;
-                output.play()?;
-                while let Ok(mut track) = rx.recv() {
-                    // TODO: handle `delay` and `padding`
-                    let mut resampler = if track
-                        .codec_params()
-                        .sample_rate
-                        .is_some_and(|r| r == output.sample_rate())
-                    {
-                        None
-                    } else {
-                        match RubatoResamplerBuffered::new(
-                            track.codec_params(),
-                            output.sample_rate(),
-                        ) {
-                            Ok(r) => Some(r),
-                            Err(ResamplerError::InvalidCodecParameters) => None,
-                            Err(err) => return Err(err)?,
-                        }
-                        // match SymphoniaResampler::new(
-                        //     track.codec_params(),
-                        //     output.sample_rate(),
-                        // ) {
-                        //     Ok(r) => Some(r),
-                        //     Err(ResamplerError::InvalidCodecParameters) => None,
-                        //     Err(err) => return Err(err)?,
-                        // }
-                    };
-                    {
-                        let mut state = controller.state.lock().unwrap();
-                        state.running = true;
-                    }
-                    while !dropped.load(std::sync::atomic::Ordering::Acquire) {
-                        {
-                            let mut state = controller.state.lock().unwrap();
-                            if let Some(seek_position) = state.seek_position {
-                                // TODO: skip packets
-                                track.seek(seek_position)?;
-                                (*state).seek_position = None;
-                                controller.controller_condvar.notify_all();
-                            }
-                            (*state).position = Some(track.progress()?);
-                            let paused = !state.playing;
-                            while !state.playing {
-                                output.pause()?;
-                                state = controller.executor_condvar.wait(state).unwrap();
-                            }
-                            if paused {
-                                output.play()?;
-                            }
-                        }
-
-                        if let Ok(buffer) = track.next() {
-                            if let Some(ref mut resampler) = resampler {
-                                let mut samples = resampler.resample(buffer)?;
-                                while let Some(sample) = samples.next() {
-                                    output.write(&SampleBuffer::BufRef(sample?));
-                                }
-                                // output.write(resampler.resample_buffer(buffer)?);
-                            } else {
-                                output.write(&buffer);
-                            }
-                        } else {
-                            break;
-                        }
-                    }
-                    {
-                        let mut state = controller.state.lock().unwrap();
-                        state.running = false;
-                        controller.controller_condvar.notify_all();
-                    }
-                }
-                Ok(())
-            };
-            run().unwrap();
-        });
-
-        Self {
-            tx: Some(tx),
-            dropped: dropped_clone,
-            handle: Some(handle),
-        }
-    }
-
-    fn queue(&self, track: DecodedTrack) -> Result<(), AudioPlayerExecutorError> {
-        self.tx.as_ref().unwrap().send(track)?;
-        Ok(())
-    }
-
-    fn wait_until_end(mut self) {
-        self.tx = None;
-        if let Some(handle) = self.handle.take() {
-            handle.join().unwrap();
-        }
-    }
-}
-
-impl Drop for AudioPlayerExecutor {
-    fn drop(&mut self) {
-        self.dropped.store(true, Ordering::Release);
-    }
-}
+use std::{
+    collections::VecDeque,
+    io::{Read, Seek},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::Receiver,
+        Arc, Condvar, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use tracing::{error, warn};
+
+use crate::{
+    buffer::{SampleBuf, SampleBuffer},
+    decoder::{DecodedTrack, DecoderError},
+    equalizer::{Equalizer, EqualizerFilter},
+    event::{AudioPlayerEvent, Subscribers},
+    http::{Buffering, HttpBuffer},
+    output::{self, AudioOutputError, AudioOutputWriter, AudioSink, OutputDevice, StreamOptions},
+    processor::{AudioProcessor, ProcessorChain, ProcessorId, SharedProcessor},
+    queue::{Queue, QueueItem, RepeatMode, TrackId},
+    resampler::{ResamplerError, RubatoResamplerBuffered},
+    speed::{Speed, SpeedMode, TimeStretch},
+    track::Track,
+    volume::{Crossfade, Gain, Normalization, NormalizationMode, Volume},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum AudioPlayerError {
+    #[error("DecoderError {0}")]
+    Decoder(#[from] DecoderError),
+    #[error("ResamplerError {0}")]
+    Resampler(#[from] ResamplerError),
+    #[error("AudioOutputError {0}")]
+    Output(#[from] AudioOutputError),
+}
+
+impl AudioPlayerError {
+    fn packet_error(&self) -> bool {
+        match self {
+            AudioPlayerError::Decoder(err) => err.packet_error(),
+            _ => false,
+        }
+    }
+}
+
+/// What happens to playback when a track fails to decode
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeErrorPolicy {
+    /// Drop the bad packet and continue with the next one, errors that are not limited to a
+    /// single packet skip the track
+    #[default]
+    SkipPacket,
+    /// End the track and continue with the next one in the queue
+    SkipTrack,
+    /// Pause on the track that failed
+    Stop,
+}
+
+/// Settings for [AudioPlayer::with_options]
+#[derive(Debug, Clone)]
+pub struct AudioPlayerOptions {
+    /// [OutputDevice::id] or [OutputDevice::name] to play on, the default device if `None`
+    pub device: Option<String>,
+    /// Audio decoded ahead of the output device, longer buffers survive busier systems without
+    /// [AudioPlayerController::underruns] but make volume changes and seeking lag behind
+    pub buffer_length: Duration,
+    /// Seed of the order of [AudioPlayerController::set_shuffle] so it can be reproduced, a
+    /// random one if `None`
+    pub shuffle_seed: Option<u64>,
+    pub fades: FadeOptions,
+}
+
+impl Default for AudioPlayerOptions {
+    fn default() -> Self {
+        Self {
+            device: None,
+            buffer_length: Duration::from_millis(200),
+            shuffle_seed: None,
+            fades: FadeOptions::default(),
+        }
+    }
+}
+
+/// Transitions that keep pausing and changing tracks from clicking, a zero duration cuts off
+/// right away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FadeOptions {
+    /// Fade out before the output is paused
+    pub pause: Duration,
+    /// Fade in once playback resumes
+    pub resume: Duration,
+    /// Fade out when the current track is left before its end, by skipping or changing the queue
+    pub stop: Duration,
+    /// How long the end of a track is mixed with the start of the next one, no crossfade if zero
+    pub crossfade: Duration,
+    /// Play tracks of the same album back to back without crossfading them
+    pub crossfade_skip_same_album: bool,
+}
+
+impl Default for FadeOptions {
+    fn default() -> Self {
+        Self {
+            pause: Duration::from_millis(50),
+            resume: Duration::from_millis(50),
+            stop: Duration::from_millis(50),
+            crossfade: Duration::ZERO,
+            crossfade_skip_same_album: true,
+        }
+    }
+}
+
+pub struct AudioPlayer {
+    controller: AudioPlayerController,
+    executor: AudioPlayerExecutor,
+}
+
+impl AudioPlayer {
+    pub fn new() -> Self {
+        Self::with_options(Default::default())
+    }
+
+    pub fn with_options(options: AudioPlayerOptions) -> Self {
+        let controller = AudioPlayerController::new(&options);
+        let executor = AudioPlayerExecutor::new(controller.clone(), options, None);
+        Self {
+            controller,
+            executor,
+        }
+    }
+
+    /// Play into `sink` instead of an output device, [AudioPlayerOptions::device] is ignored
+    pub fn with_sink<S: AudioSink + Send + 'static>(
+        mut options: AudioPlayerOptions,
+        sink: S,
+    ) -> Self {
+        options.device = None;
+        let controller = AudioPlayerController::new(&options);
+        let executor = AudioPlayerExecutor::new(controller.clone(), options, Some(Box::new(sink)));
+        Self {
+            controller,
+            executor,
+        }
+    }
+
+    /// List the output devices of every available host
+    pub fn output_devices() -> Result<Vec<OutputDevice>, AudioPlayerError> {
+        Ok(output::output_devices()?)
+    }
+
+    pub fn controller(&self) -> &AudioPlayerController {
+        &self.controller
+    }
+
+    pub fn open<F: AsRef<Path>>(&mut self, file: F) -> Result<Track, AudioPlayerError> {
+        Track::open(file)
+    }
+
+    /// See [Track::open_url]
+    pub fn open_url(&mut self, url: &str) -> Result<Track, AudioPlayerError> {
+        Track::open_url(url)
+    }
+
+    /// See [Track::open_reader]
+    pub fn open_reader<R: Read + Seek + Send + 'static>(
+        &mut self,
+        reader: R,
+        hint: Option<&str>,
+    ) -> Result<Track, AudioPlayerError> {
+        Track::open_reader(reader, hint)
+    }
+
+    /// See [Track::open_stream]
+    pub fn open_stream<R: Read + Send + 'static>(
+        &mut self,
+        reader: R,
+        hint: Option<&str>,
+    ) -> Result<Track, AudioPlayerError> {
+        Track::open_stream(reader, hint)
+    }
+
+    /// See [Track::open_bytes]
+    pub fn open_bytes<B: AsRef<[u8]> + Send + Sync + 'static>(
+        &mut self,
+        bytes: B,
+        hint: Option<&str>,
+    ) -> Result<Track, AudioPlayerError> {
+        Track::open_bytes(bytes, hint)
+    }
+
+    // Place track at the end of the queue
+    pub fn queue(&self, track: Track) -> TrackId {
+        self.controller.push(track)
+    }
+
+    pub fn running(&self) -> bool {
+        self.controller.running()
+    }
+
+    // drain all tracks in the queue
+    pub fn drain(&mut self) {
+        self.controller.clear();
+    }
+
+    /// Blocks until every track in the queue has been played
+    pub fn wait_until_end(self) {
+        self.executor.wait_until_end();
+    }
+}
+
+#[derive(Debug)]
+pub enum AudioPlayerControllerError {
+    NotPlaying,
+    InvalidQueueIndex(usize),
+}
+
+#[derive(Clone)]
+pub struct AudioPlayerController {
+    state: Arc<Mutex<AudioPlayerControllerState>>,
+    executor_condvar: Arc<Condvar>,
+    controller_condvar: Arc<Condvar>,
+    /// Outside of `state` as the audio callback cannot take locks
+    underruns: Arc<AtomicU64>,
+}
+
+impl AudioPlayerController {
+    fn new(options: &AudioPlayerOptions) -> Self {
+        let state = Arc::new(Mutex::new(AudioPlayerControllerState::new(options)));
+        let executor_condvar = Arc::new(Condvar::new());
+        let controller_condvar = Arc::new(Condvar::new());
+        let underruns = Arc::new(AtomicU64::new(0));
+        Self {
+            state,
+            executor_condvar,
+            controller_condvar,
+            underruns,
+        }
+    }
+
+    /// How often the output device ran out of samples during playback, which is heard as a gap
+    pub fn underruns(&self) -> u64 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    pub fn play(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.playing {
+            state.subscribers.emit(AudioPlayerEvent::Resumed);
+            // the position stood still while paused
+            if let Some(position) = state.position.as_mut() {
+                position.measured = Instant::now();
+            }
+        }
+        (*state).playing = true;
+        self.executor_condvar.notify_all();
+    }
+
+    pub fn pause(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.playing {
+            state.subscribers.emit(AudioPlayerEvent::Paused);
+            (*state).position = state.position.map(|p| p.rebase(Instant::now()));
+        }
+        (*state).playing = false;
+        self.executor_condvar.notify_all();
+    }
+
+    /// Receive [AudioPlayerEvent]s, including the position every `position_interval` while playing
+    pub fn subscribe(&self, position_interval: Option<Duration>) -> Receiver<AudioPlayerEvent> {
+        let state = self.state.lock().unwrap();
+        state.subscribers.subscribe(position_interval)
+    }
+
+    pub fn playing(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        (*state).playing
+    }
+
+    /// The most recent error of the executor, also sent as [AudioPlayerEvent::Error]
+    pub fn last_error(&self) -> Option<Arc<AudioPlayerError>> {
+        let state = self.state.lock().unwrap();
+        state.last_error.clone()
+    }
+
+    /// Linear amplitude factor in `0.0..=1.0`, applied in software on top of the system volume
+    pub fn volume(&self) -> f32 {
+        let state = self.state.lock().unwrap();
+        state.volume.volume
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        let mut state = self.state.lock().unwrap();
+        (*state).volume.volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn muted(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.volume.muted
+    }
+
+    /// Silence the output without changing [AudioPlayerController::volume]
+    pub fn set_muted(&self, muted: bool) {
+        let mut state = self.state.lock().unwrap();
+        (*state).volume.muted = muted;
+    }
+
+    pub fn balance(&self) -> f32 {
+        let state = self.state.lock().unwrap();
+        state.volume.balance
+    }
+
+    /// `-1.0` plays only the left channel, `1.0` only the right and `0.0` both at full volume
+    pub fn set_balance(&self, balance: f32) {
+        let mut state = self.state.lock().unwrap();
+        (*state).volume.balance = balance.clamp(-1.0, 1.0);
+    }
+
+    pub fn normalization(&self) -> NormalizationMode {
+        let state = self.state.lock().unwrap();
+        state.normalization.mode
+    }
+
+    /// Play tracks at the loudness given by their ReplayGain tags, tracks without them are
+    /// played as they are
+    pub fn set_normalization(&self, mode: NormalizationMode) {
+        let mut state = self.state.lock().unwrap();
+        (*state).normalization.mode = mode;
+    }
+
+    pub fn preamp(&self) -> f64 {
+        let state = self.state.lock().unwrap();
+        state.normalization.preamp
+    }
+
+    /// Gain in dB added on top of the ReplayGain of every track, clamped to `-15.0..=15.0`
+    pub fn set_preamp(&self, preamp: f64) {
+        let mut state = self.state.lock().unwrap();
+        (*state).normalization.preamp = preamp.clamp(-15.0, 15.0);
+    }
+
+    pub fn prevent_clipping(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.normalization.prevent_clipping
+    }
+
+    /// Lower the gain of tracks whose ReplayGain peak would be pushed over full scale, on by
+    /// default
+    pub fn set_prevent_clipping(&self, prevent_clipping: bool) {
+        let mut state = self.state.lock().unwrap();
+        (*state).normalization.prevent_clipping = prevent_clipping;
+    }
+
+    pub fn equalizer(&self) -> Equalizer {
+        let state = self.state.lock().unwrap();
+        state.equalizer.clone()
+    }
+
+    /// Shape the sound with a graphic or parametric equalizer, the change is faded in while
+    /// playing. [Equalizer::default] leaves the sound as it is
+    pub fn set_equalizer(&self, equalizer: Equalizer) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .equalizer_filter
+            .lock()
+            .unwrap()
+            .set(equalizer.clone());
+        (*state).equalizer = equalizer;
+    }
+
+    /// The processors applied to the samples before they are written to the output, in order.
+    /// These are [ProcessorId::EQUALIZER] and [ProcessorId::VOLUME] until others are inserted
+    pub fn processors(&self) -> Vec<ProcessorId> {
+        let state = self.state.lock().unwrap();
+        state.processors.ids()
+    }
+
+    /// Apply `processor` to the samples at `index` of [AudioPlayerController::processors], or
+    /// last if it is past the end
+    pub fn insert_processor<P: AudioProcessor + 'static>(
+        &self,
+        index: usize,
+        processor: P,
+    ) -> ProcessorId {
+        let mut state = self.state.lock().unwrap();
+        (*state)
+            .processors
+            .insert(index, Arc::new(Mutex::new(processor)))
+    }
+
+    /// Returns false if there is no such processor or it is [ProcessorId::EQUALIZER] or
+    /// [ProcessorId::VOLUME], which can only be bypassed
+    pub fn remove_processor(&self, id: ProcessorId) -> bool {
+        let mut state = self.state.lock().unwrap();
+        (*state).processors.remove(id)
+    }
+
+    /// Move a processor to `index` of [AudioPlayerController::processors], or last if it is past
+    /// the end. Returns false if there is no such processor
+    pub fn move_processor(&self, id: ProcessorId, index: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        (*state).processors.move_to(id, index)
+    }
+
+    /// `None` if there is no such processor
+    pub fn processor_bypassed(&self, id: ProcessorId) -> Option<bool> {
+        let state = self.state.lock().unwrap();
+        state.processors.bypassed(id)
+    }
+
+    /// Skip a processor while keeping its place in the chain. Returns false if there is no such
+    /// processor
+    pub fn set_processor_bypassed(&self, id: ProcessorId, bypassed: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        (*state).processors.set_bypassed(id, bypassed)
+    }
+
+    /// The output device selected with [AudioPlayerOptions::device] or
+    /// [AudioPlayerController::set_device], `None` for the default device
+    pub fn device(&self) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        state.device.clone()
+    }
+
+    /// Move playback to another output device, keeping the queue and position. A device that
+    /// fails to open is reported as [AudioPlayerEvent::Error] and playback stays on the old one
+    pub fn set_device(&self, device: Option<String>) {
+        let mut state = self.state.lock().unwrap();
+        (*state).device = device.clone();
+        (*state).switch_device = Some(device);
+        self.executor_condvar.notify_all();
+    }
+
+    /// Factor of the normal playback speed
+    pub fn speed(&self) -> f64 {
+        let state = self.state.lock().unwrap();
+        state.speed.speed
+    }
+
+    /// Play faster or slower, clamped to `0.5..=3.0`. [AudioPlayerController::position] and the
+    /// track durations stay in track time
+    pub fn set_speed(&self, speed: f64) {
+        let mut state = self.state.lock().unwrap();
+        (*state).speed.speed = speed.clamp(Speed::MIN, Speed::MAX);
+    }
+
+    pub fn speed_mode(&self) -> SpeedMode {
+        let state = self.state.lock().unwrap();
+        state.speed.mode
+    }
+
+    pub fn set_speed_mode(&self, mode: SpeedMode) {
+        let mut state = self.state.lock().unwrap();
+        (*state).speed.mode = mode;
+    }
+
+    pub fn fades(&self) -> FadeOptions {
+        let state = self.state.lock().unwrap();
+        state.fades
+    }
+
+    /// Takes effect with the next transition, a crossfade in progress is finished as it started
+    pub fn set_fades(&self, fades: FadeOptions) {
+        let mut state = self.state.lock().unwrap();
+        (*state).fades = fades;
+    }
+
+    pub fn decode_error_policy(&self) -> DecodeErrorPolicy {
+        let state = self.state.lock().unwrap();
+        state.decode_error_policy
+    }
+
+    pub fn set_decode_error_policy(&self, policy: DecodeErrorPolicy) {
+        let mut state = self.state.lock().unwrap();
+        (*state).decode_error_policy = policy;
+    }
+
+    /// Position of the sample being heard, behind decoding by the output buffer and device latency
+    pub fn position(&self) -> Result<Duration, AudioPlayerControllerError> {
+        let state = self.state.lock().unwrap();
+        let position = state
+            .position
+            .ok_or(AudioPlayerControllerError::NotPlaying)?;
+        Ok(match state.playing {
+            true => position.at(Instant::now()),
+            false => position.position,
+        })
+    }
+
+    /// How far the download of the current track is ahead, `None` unless it is played from a
+    /// URL. [AudioPlayerEvent::Buffering] is sent when playback starts or stops waiting for it
+    pub fn buffering(&self) -> Option<Buffering> {
+        let state = self.state.lock().unwrap();
+        state.http.as_ref().map(|http| http.buffering())
+    }
+
+    /// Move playback of the current track to `progress`, also while paused or before it started.
+    /// This returns right away, [AudioPlayerEvent::Seeked] follows once the seek is done. Of
+    /// several seeks requested in quick succession only the last is carried out
+    pub fn seek(&self, progress: Duration) {
+        let mut state = self.state.lock().unwrap();
+        (*state).seek_position = Some(progress);
+        self.executor_condvar.notify_all();
+    }
+
+    /// List the tracks in the queue
+    pub fn queue(&self) -> Vec<QueueItem> {
+        let state = self.state.lock().unwrap();
+        state.queue.items()
+    }
+
+    /// The track that is playing, or will play once [AudioPlayerController::play] is called
+    pub fn current(&self) -> Option<QueueItem> {
+        let state = self.state.lock().unwrap();
+        state.queue.current().cloned()
+    }
+
+    /// Index of [AudioPlayerController::current] in [AudioPlayerController::queue]
+    pub fn current_index(&self) -> Option<usize> {
+        let state = self.state.lock().unwrap();
+        state.queue.current_index()
+    }
+
+    /// Place track at the end of the queue
+    pub fn push(&self, track: Track) -> TrackId {
+        let mut state = self.state.lock().unwrap();
+        let id = state.queue.push(track);
+        state.subscribers.emit(AudioPlayerEvent::QueueChanged);
+        self.executor_condvar.notify_all();
+        id
+    }
+
+    pub fn insert(
+        &self,
+        index: usize,
+        track: Track,
+    ) -> Result<TrackId, AudioPlayerControllerError> {
+        let mut state = self.state.lock().unwrap();
+        if index > state.queue.len() {
+            return Err(AudioPlayerControllerError::InvalidQueueIndex(index));
+        }
+        let id = state.queue.insert(index, track);
+        state.subscribers.emit(AudioPlayerEvent::QueueChanged);
+        self.executor_condvar.notify_all();
+        Ok(id)
+    }
+
+    /// Removing the current track skips to the one after it
+    pub fn remove(&self, index: usize) -> Result<QueueItem, AudioPlayerControllerError> {
+        let mut state = self.state.lock().unwrap();
+        let item = state
+            .queue
+            .remove(index)
+            .ok_or(AudioPlayerControllerError::InvalidQueueIndex(index))?;
+        state.subscribers.emit(AudioPlayerEvent::QueueChanged);
+        self.executor_condvar.notify_all();
+        Ok(item)
+    }
+
+    pub fn move_track(&self, from: usize, to: usize) -> Result<(), AudioPlayerControllerError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.queue.move_track(from, to) {
+            return Err(AudioPlayerControllerError::InvalidQueueIndex(
+                if from >= state.queue.len() { from } else { to },
+            ));
+        }
+        state.subscribers.emit(AudioPlayerEvent::QueueChanged);
+        self.executor_condvar.notify_all();
+        Ok(())
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.clear();
+        state.subscribers.emit(AudioPlayerEvent::QueueChanged);
+        self.executor_condvar.notify_all();
+    }
+
+    /// Skip to the next track, or stop if the current track is the last and the queue is not
+    /// repeated
+    pub fn next(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.next();
+        state.subscribers.emit(AudioPlayerEvent::QueueChanged);
+        self.executor_condvar.notify_all();
+    }
+
+    /// Go back to the previous track, or restart the first track
+    pub fn previous(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.previous();
+        state.subscribers.emit(AudioPlayerEvent::QueueChanged);
+        self.executor_condvar.notify_all();
+    }
+
+    pub fn jump_to(&self, index: usize) -> Result<(), AudioPlayerControllerError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.queue.jump_to(index) {
+            return Err(AudioPlayerControllerError::InvalidQueueIndex(index));
+        }
+        state.subscribers.emit(AudioPlayerEvent::QueueChanged);
+        self.executor_condvar.notify_all();
+        Ok(())
+    }
+
+    pub fn repeat(&self) -> RepeatMode {
+        let state = self.state.lock().unwrap();
+        state.queue.repeat()
+    }
+
+    /// With [RepeatMode::One] or [RepeatMode::All] the queue never runs out, so
+    /// [AudioPlayer::wait_until_end] only returns once repeat is turned off or the queue cleared
+    pub fn set_repeat(&self, repeat: RepeatMode) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.set_repeat(repeat);
+        self.executor_condvar.notify_all();
+    }
+
+    pub fn shuffle(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.queue.shuffle()
+    }
+
+    /// Play the queue in random order, no track is played again until every track was played
+    /// once. The order can be made reproducible with [AudioPlayerOptions::shuffle_seed]
+    pub fn set_shuffle(&self, shuffle: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.set_shuffle(shuffle);
+        self.executor_condvar.notify_all();
+    }
+
+    fn running(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        (*state).running
+    }
+}
+
+/// Position of the audible sample as last measured by the executor
+#[derive(Debug, Clone, Copy)]
+struct PlaybackPosition {
+    position: Duration,
+    measured: Instant,
+    /// Audio written past `position`, playback cannot get further than this before the next
+    /// measurement
+    buffered: Duration,
+    /// Track time played per second
+    speed: f64,
+}
+
+impl PlaybackPosition {
+    fn new(position: Duration, buffered: Duration, speed: f64) -> Self {
+        Self {
+            position,
+            measured: Instant::now(),
+            buffered,
+            speed,
+        }
+    }
+
+    /// Interpolated position at `now`, assuming playback continued since the measurement
+    fn at(&self, now: Instant) -> Duration {
+        let elapsed = now
+            .saturating_duration_since(self.measured)
+            .min(self.buffered);
+        self.position + elapsed.mul_f64(self.speed)
+    }
+
+    /// Move the measurement to `now`
+    fn rebase(&self, now: Instant) -> Self {
+        let elapsed = now
+            .saturating_duration_since(self.measured)
+            .min(self.buffered);
+        Self {
+            position: self.at(now),
+            measured: now,
+            buffered: self.buffered - elapsed,
+            speed: self.speed,
+        }
+    }
+}
+
+/// Maps the samples written to the output back to the position in the track they came from
+struct PlaybackClock {
+    sample_rate: u32,
+    /// Frames written to the output
+    written: u64,
+    /// Track position at the first of `written` frames and the speed from there on, oldest first
+    marks: VecDeque<(u64, Duration, f64)>,
+}
+
+impl PlaybackClock {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            written: 0,
+            marks: VecDeque::new(),
+        }
+    }
+
+    /// `frames` taken from the track at `position` and played at `speed` are about to be written
+    fn write(&mut self, position: Duration, frames: usize, speed: f64) {
+        self.marks.push_back((self.written, position, speed));
+        self.written += frames as u64;
+    }
+
+    /// Forget the positions written so far, as they no longer continue into what follows
+    fn clear(&mut self) {
+        self.marks.clear();
+    }
+
+    /// Position of the sample heard now, given how long the output takes to play what was
+    /// written. Before anything written since [PlaybackClock::clear] is heard it is the position
+    /// playback continues from
+    fn position(&mut self, delay: Duration) -> Option<Duration> {
+        let delay = (delay.as_secs_f64() * self.sample_rate as f64) as u64;
+        let heard = self.written.saturating_sub(delay);
+        while self
+            .marks
+            .get(1)
+            .is_some_and(|&(frame, _, _)| frame <= heard)
+        {
+            self.marks.pop_front();
+        }
+        let &(frame, position, speed) = self.marks.front()?;
+        let frames = heard.saturating_sub(frame);
+        Some(position + Duration::from_secs_f64(frames as f64 * speed / self.sample_rate as f64))
+    }
+}
+
+struct AudioPlayerControllerState {
+    running: bool,
+    playing: bool,
+    position: Option<PlaybackPosition>,
+    seek_position: Option<Duration>,
+    queue: Queue,
+    /// Download of the current track if it is played from a URL
+    http: Option<Arc<HttpBuffer>>,
+    /// Set by [AudioPlayer::wait_until_end] to stop the executor once the queue is exhausted
+    closing: bool,
+    subscribers: Subscribers,
+    last_error: Option<Arc<AudioPlayerError>>,
+    decode_error_policy: DecodeErrorPolicy,
+    volume: Volume,
+    normalization: Normalization,
+    equalizer: Equalizer,
+    /// The built-in processors, kept apart from the chain to pass them their settings
+    equalizer_filter: Arc<Mutex<EqualizerFilter>>,
+    gain: Arc<Mutex<Gain>>,
+    processors: ProcessorChain,
+    speed: Speed,
+    fades: FadeOptions,
+    device: Option<String>,
+    /// Device requested by [AudioPlayerController::set_device] the executor has yet to switch to
+    switch_device: Option<Option<String>>,
+}
+
+impl AudioPlayerControllerState {
+    fn new(options: &AudioPlayerOptions) -> Self {
+        let running = false;
+        let playing = false;
+        let position = None;
+        let seek_position = None;
+        let queue = Queue::new(options.shuffle_seed);
+        let http = None;
+        let closing = false;
+        let subscribers = Subscribers::new();
+        let last_error = None;
+        let decode_error_policy = DecodeErrorPolicy::default();
+        let volume = Volume::default();
+        let normalization = Normalization::default();
+        let equalizer = Equalizer::default();
+        let equalizer_filter = Arc::new(Mutex::new(EqualizerFilter::new()));
+        let gain = Arc::new(Mutex::new(Gain::new()));
+        let processors = ProcessorChain::new(equalizer_filter.clone(), gain.clone());
+        let speed = Speed::default();
+        let fades = options.fades;
+        let device = options.device.clone();
+        let switch_device = None;
+        Self {
+            running,
+            playing,
+            position,
+            seek_position,
+            queue,
+            http,
+            closing,
+            subscribers,
+            last_error,
+            decode_error_policy,
+            volume,
+            normalization,
+            equalizer,
+            equalizer_filter,
+            gain,
+            processors,
+            speed,
+            fades,
+            device,
+            switch_device,
+        }
+    }
+
+    fn report(&mut self, err: AudioPlayerError) {
+        error!("{}", err);
+        let err = Arc::new(err);
+        self.last_error = Some(err.clone());
+        self.subscribers.emit(AudioPlayerEvent::Error(err));
+    }
+
+    fn stop(&mut self) {
+        if self.playing {
+            self.playing = false;
+            self.subscribers.emit(AudioPlayerEvent::Paused);
+        }
+    }
+}
+
+struct AudioPlayerExecutor {
+    controller: AudioPlayerController,
+    dropped: Arc<AtomicBool>,
+    /// This is an option to `join` in [AudioPlayerExecutor::wait_until_end]
+    handle: Option<JoinHandle<()>>,
+}
+
+impl AudioPlayerExecutor {
+    /// How long before the end of a track the next one is prepared
+    const PRELOAD_AHEAD: Duration = Duration::from_secs(5);
+    /// How often to look for a new default device while following the default
+    const DEFAULT_DEVICE_POLL: Duration = Duration::from_secs(1);
+
+    fn new(
+        controller: AudioPlayerController,
+        options: AudioPlayerOptions,
+        sink: Option<Box<dyn AudioSink + Send>>,
+    ) -> Self {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let dropped_clone = dropped.clone();
+        let controller_clone = controller.clone();
+        let handle = std::thread::spawn(move || {
+            let executor_controller = controller.clone();
+            let run = move || -> Result<(), AudioPlayerError> {
+                let mut device = options.device;
+                let stream_options = StreamOptions {
+                    buffer_length: options.buffer_length,
+                    underruns: controller.underruns.clone(),
+                };
+                let mut output = match sink {
+                    Some(sink) => AudioOutputWriter::Sink(sink),
+                    None => AudioOutputWriter::new(device.as_deref(), &stream_options)?,
+                };
+                let gain = controller.state.lock().unwrap().gain.clone();
+                // the processors that are not bypassed, in order
+                let mut processors: Vec<SharedProcessor> = vec![];
+                let mut clock = PlaybackClock::new(output.sample_rate());
+                let mut output_lost = false;
+                let mut default_checked = Instant::now();
+                output.play()?;
+                let mut fades = options.fades;
+                let mut speed = Speed::default();
+                // whether the output was faded out for pausing
+                let mut faded = false;
+                // the following track, prepared ahead of time so it starts without a gap
+                let mut next: Option<TrackPlayback> = None;
+                loop {
+                    let mut current = match next.take() {
+                        Some(playback) => playback,
+                        None => {
+                            let (id, track, rewind) = {
+                                let mut state = controller.state.lock().unwrap();
+                                loop {
+                                    if dropped.load(Ordering::Acquire) {
+                                        return Ok(());
+                                    }
+                                    if let Some(checkout) = state.queue.checkout_current() {
+                                        speed = state.speed;
+                                        break checkout;
+                                    }
+                                    if state.closing {
+                                        return Ok(());
+                                    }
+                                    output.idle();
+                                    state = controller.executor_condvar.wait(state).unwrap();
+                                }
+                            };
+                            TrackPlayback::new(id, track, rewind, output.sample_rate(), speed)
+                        }
+                    };
+                    {
+                        let mut state = controller.state.lock().unwrap();
+                        state.running = true;
+                        state.http = current.track.http();
+                        if let Some(http) = &state.http {
+                            let subscribers = state.subscribers.clone();
+                            let id = current.id;
+                            http.set_listener(Some(Box::new(move |buffering| {
+                                subscribers.emit(AudioPlayerEvent::Buffering(id, buffering));
+                            })));
+                        }
+                        clock.clear();
+                        state.position = Some(PlaybackPosition::new(
+                            current.position,
+                            Duration::ZERO,
+                            speed.speed,
+                        ));
+                        if let Some(item) = state.queue.item(current.id).cloned() {
+                            state.subscribers.emit(AudioPlayerEvent::TrackStarted(item));
+                        }
+                    }
+                    let mut finished = false;
+                    let mut failed = false;
+                    // mixing the end of the track into the start of `next`
+                    let mut crossfade: Option<Crossfade> = None;
+                    while !dropped.load(Ordering::Acquire) {
+                        let (preload, volume, (normalization, next_normalization), switch_device) = {
+                            let mut state = controller.state.lock().unwrap();
+                            if state.queue.take_current_changed() {
+                                break;
+                            }
+                            fades = state.fades;
+                            speed = state.speed;
+                            if let Some(active) = state.processors.take_changed() {
+                                processors = active;
+                            }
+                            current.set_speed(speed);
+                            if let Some(next) = next.as_mut() {
+                                next.set_speed(speed);
+                            }
+                            if let Some(seek_position) = state.seek_position {
+                                (*state).seek_position = None;
+                                match current.seek(seek_position) {
+                                    Ok(()) => {
+                                        // the samples from before the seek are never heard
+                                        if let Err(err) = output.clear() {
+                                            output_lost |= Self::device_lost(err)?;
+                                        }
+                                        clock.clear();
+                                        processors.iter().for_each(|processor| {
+                                            processor.lock().unwrap().reset()
+                                        });
+                                        // the next track is started over when it is due again
+                                        if crossfade.take().is_some() {
+                                            if let Some(stale) = next.take() {
+                                                state.queue.checkin(stale.id, stale.track);
+                                            }
+                                        }
+                                        (*state).position = Some(PlaybackPosition::new(
+                                            current.position,
+                                            Duration::ZERO,
+                                            speed.speed,
+                                        ));
+                                        state
+                                            .subscribers
+                                            .emit(AudioPlayerEvent::Seeked(current.position));
+                                    }
+                                    Err(err) => state.report(err),
+                                }
+                            }
+                            let delay = output.delay();
+                            // keep the position where it was paused
+                            let position = clock.position(delay).filter(|_| state.playing);
+                            if let Some(position) = position {
+                                (*state).position =
+                                    Some(PlaybackPosition::new(position, delay, speed.speed));
+                                state.subscribers.position(position);
+                            }
+                            if let Some(metadata) = current.track.take_metadata() {
+                                if let Some(details) = state.queue.details_mut(current.id) {
+                                    details.update(&metadata);
+                                    let details = details.clone();
+                                    state.subscribers.emit(AudioPlayerEvent::MetadataChanged(
+                                        current.id, details,
+                                    ));
+                                }
+                            }
+                            if let Some(stream_info) = state.queue.stream_info_mut(current.id) {
+                                stream_info.set_instant_bitrate(current.track.bitrate());
+                            }
+                            if !state.playing && !faded {
+                                // fade out what is left in the output before pausing it
+                                drop(state);
+                                output.fade_out(fades.pause);
+                                faded = true;
+                                continue;
+                            }
+                            let paused = !state.playing;
+                            while !state.playing
+                                && !state.queue.current_changed()
+                                && state.seek_position.is_none()
+                                && !dropped.load(Ordering::Acquire)
+                            {
+                                if let Err(err) = output.pause() {
+                                    output_lost |= Self::device_lost(err)?;
+                                }
+                                state = controller.executor_condvar.wait(state).unwrap();
+                            }
+                            if !state.playing {
+                                // current track changed, seeked or player dropped while paused
+                                continue;
+                            }
+                            if paused {
+                                if let Err(err) = output.play() {
+                                    output_lost |= Self::device_lost(err)?;
+                                }
+                            }
+                            if faded {
+                                output.fade_in(fades.resume);
+                                faded = false;
+                            }
+
+                            // the queue may have changed since the next track was prepared
+                            let next_id = state.queue.next_id();
+                            if next.as_ref().map(|n| n.id) != next_id {
+                                if let Some(stale) = next.take() {
+                                    state.queue.checkin(stale.id, stale.track);
+                                }
+                            }
+                            let remaining = current.remaining();
+                            let near_end = remaining.map_or(current.ended, |r| {
+                                r <= Self::PRELOAD_AHEAD + fades.crossfade
+                            });
+                            let preload = if next.is_none() && near_end {
+                                state.queue.checkout_next()
+                            } else {
+                                None
+                            };
+                            match next {
+                                Some(ref next) if crossfade.is_none() => {
+                                    let album = |id| {
+                                        state.queue.item(id).and_then(|item| item.details().album())
+                                    };
+                                    crossfade = remaining.and_then(|remaining| {
+                                        Crossfade::start(
+                                            &fades,
+                                            remaining,
+                                            speed.speed,
+                                            current.sample_rate,
+                                            [album(current.id), album(next.id)],
+                                        )
+                                    });
+                                }
+                                Some(_) => (),
+                                // the next track was taken away by a queue change
+                                None => crossfade = None,
+                            }
+                            let normalization = |id| {
+                                state.queue.item(id).map_or(1.0, |item| {
+                                    state
+                                        .normalization
+                                        .gain(item.details().replay_gain(), state.queue.shuffle())
+                                })
+                            };
+                            let normalization = (
+                                normalization(current.id),
+                                next.as_ref().map_or(1.0, |next| normalization(next.id)),
+                            );
+                            (
+                                preload,
+                                state.volume,
+                                normalization,
+                                state.switch_device.take(),
+                            )
+                        };
+                        // a lost device is replaced by the default, which is followed when it changes
+                        let default_changed = device.is_none()
+                            && default_checked.elapsed() >= Self::DEFAULT_DEVICE_POLL
+                            && {
+                                default_checked = Instant::now();
+                                // custom sinks have no device to compare with
+                                output.device_name().is_some_and(|name| {
+                                    output::default_device_name()
+                                        .is_some_and(|default| default != name)
+                                })
+                            };
+                        let switch_device = match switch_device {
+                            Some(switch_device) => Some(switch_device),
+                            None if output_lost || default_changed => Some(None),
+                            None => None,
+                        };
+                        if let Some(switch_device) = switch_device {
+                            let next = next.as_mut();
+                            match Self::switch_output(
+                                &mut output,
+                                &processors,
+                                &mut clock,
+                                switch_device.as_deref(),
+                                &stream_options,
+                                &mut current,
+                                next,
+                            ) {
+                                Ok(()) => {
+                                    let lost_device = std::mem::replace(&mut device, switch_device);
+                                    let mut state = controller.state.lock().unwrap();
+                                    if state.switch_device.is_none() {
+                                        (*state).device = device.clone();
+                                    }
+                                    if output_lost {
+                                        output_lost = false;
+                                        state
+                                            .subscribers
+                                            .emit(AudioPlayerEvent::DeviceLost(lost_device));
+                                    }
+                                    state
+                                        .subscribers
+                                        .emit(AudioPlayerEvent::DeviceChanged(device.clone()));
+                                }
+                                Err(err) => {
+                                    let mut state = controller.state.lock().unwrap();
+                                    if state.switch_device.is_none() {
+                                        (*state).device = device.clone();
+                                    }
+                                    state.report(err.into());
+                                    if output_lost {
+                                        // nothing to play on, try again once playback resumes
+                                        state.stop();
+                                        continue;
+                                    }
+                                }
+                            }
+                        }
+                        if let Some((id, track, rewind)) = preload {
+                            let mut playback =
+                                TrackPlayback::new(id, track, rewind, output.sample_rate(), speed);
+                            playback.preload();
+                            next = Some(playback);
+                        }
+
+                        let position = current.position;
+                        let buffer = match current.next() {
+                            Ok(buffer) => buffer,
+                            Err(err) => {
+                                let mut state = controller.state.lock().unwrap();
+                                let packet_error = err.packet_error();
+                                let policy = state.decode_error_policy;
+                                state.report(err);
+                                match policy {
+                                    DecodeErrorPolicy::SkipPacket if packet_error => continue,
+                                    DecodeErrorPolicy::Stop => {
+                                        state.stop();
+                                        continue;
+                                    }
+                                    _ => {
+                                        finished = true;
+                                        failed = true;
+                                        break;
+                                    }
+                                }
+                            }
+                        };
+                        match buffer {
+                            Some((mut buffer, buffer_speed)) => {
+                                if let (Some(crossfade), Some(next)) =
+                                    (crossfade.as_mut(), next.as_mut())
+                                {
+                                    let incoming = next.next_frames(buffer.frames());
+                                    // the mix is normalized for the current track
+                                    let incoming_gain = next_normalization / normalization;
+                                    crossfade.apply(&mut buffer, &incoming, incoming_gain);
+                                }
+                                gain.lock().unwrap().set(volume, normalization);
+                                for processor in &processors {
+                                    let mut processor = processor.lock().unwrap();
+                                    processor.process(&mut buffer, output.sample_rate());
+                                }
+                                clock.write(position, buffer.frames(), buffer_speed);
+                                // the buffer is decoded again once the output is replaced
+                                if let Err(err) = output.write(&buffer) {
+                                    output_lost |= Self::device_lost(err)?;
+                                }
+                            }
+                            None => {
+                                finished = true;
+                                break;
+                            }
+                        }
+                    }
+                    if !finished && !dropped.load(Ordering::Acquire) {
+                        // what is left of the track in the output is cut off, fade it out first
+                        if !faded {
+                            output.fade_out(fades.stop);
+                        }
+                        if let Err(err) = output.clear() {
+                            output_lost |= Self::device_lost(err)?;
+                        }
+                        if !faded {
+                            output.fade_in(Duration::ZERO);
+                        }
+                    }
+                    {
+                        let mut state = controller.state.lock().unwrap();
+                        state.running = false;
+                        if let Some(http) = state.http.take() {
+                            http.set_listener(None);
+                        }
+                        state.position = None;
+                        state.seek_position = None;
+                        state.queue.checkin(current.id, current.track);
+                        if finished {
+                            // a broken track is not repeated over and over
+                            match failed {
+                                true => state.queue.next(),
+                                false => state.queue.advance(),
+                            }
+                            state
+                                .subscribers
+                                .emit(AudioPlayerEvent::TrackEnded(current.id));
+                        }
+                        // continue straight into the prepared track if it is still the next one
+                        if let Some(playback) = next.take() {
+                            if finished
+                                && !state.queue.current_changed()
+                                && state.queue.current_id() == Some(playback.id)
+                            {
+                                next = Some(playback);
+                            } else {
+                                state.queue.checkin(playback.id, playback.track);
+                            }
+                        }
+                        controller.controller_condvar.notify_all();
+                    }
+                }
+            };
+            if let Err(err) = run() {
+                // the output failed, leave the controller as if the queue ran out
+                let mut state = executor_controller.state.lock().unwrap();
+                state.running = false;
+                state.position = None;
+                state.seek_position = None;
+                state.stop();
+                state.report(err);
+                executor_controller.controller_condvar.notify_all();
+            }
+        });
+
+        Self {
+            controller: controller_clone,
+            dropped: dropped_clone,
+            handle: Some(handle),
+        }
+    }
+
+    /// `Ok(true)` if the output needs to be replaced, other output errors are passed on
+    fn device_lost(err: AudioOutputError) -> Result<bool, AudioPlayerError> {
+        if err.device_lost() {
+            warn!("output device lost: {}", err);
+            Ok(true)
+        } else {
+            Err(err.into())
+        }
+    }
+
+    /// Open `device` and continue the tracks on it from where decoding is
+    fn switch_output(
+        output: &mut AudioOutputWriter,
+        processors: &[SharedProcessor],
+        clock: &mut PlaybackClock,
+        device: Option<&str>,
+        stream_options: &StreamOptions,
+        current: &mut TrackPlayback,
+        next: Option<&mut TrackPlayback>,
+    ) -> Result<(), AudioOutputError> {
+        let mut writer = AudioOutputWriter::new(device, stream_options)?;
+        writer.play()?;
+        *output = writer;
+        processors
+            .iter()
+            .for_each(|processor| processor.lock().unwrap().reset());
+        *clock = PlaybackClock::new(output.sample_rate());
+        // samples queued on the old device are lost
+        current.set_output_sample_rate(output.sample_rate());
+        if let Some(next) = next {
+            next.set_output_sample_rate(output.sample_rate());
+        }
+        Ok(())
+    }
+
+    fn wait_until_end(mut self) {
+        {
+            let mut state = self.controller.state.lock().unwrap();
+            state.closing = true;
+            self.controller.executor_condvar.notify_all();
+        }
+        if let Some(handle) = self.handle.take() {
+            handle.join().unwrap();
+        }
+    }
+}
+
+/// A track checked out of the queue, producing samples at the output sample rate
+struct TrackPlayback {
+    id: TrackId,
+    track: DecodedTrack,
+    resampler: Option<RubatoResamplerBuffered>,
+    sample_rate: u32,
+    /// Speed of the samples decoded from now on
+    speed: Speed,
+    /// Only with [SpeedMode::PreservePitch]
+    stretch: Option<TimeStretch>,
+    /// Position in the track of the next sample returned by [TrackPlayback::next]
+    position: Duration,
+    /// Frames still to be dropped from the decoded packets to land exactly on a seek position
+    skip: usize,
+    /// Samples decoded ahead of playback, with the speed they play at
+    pending: VecDeque<(SampleBuf, f64)>,
+    ended: bool,
+    /// Failure while preparing the track, returned by the first call to [TrackPlayback::next]
+    error: Option<AudioPlayerError>,
+}
+
+impl TrackPlayback {
+    /// The track is never lost on failure so it can be checked back into the queue, the error is
+    /// returned from [TrackPlayback::next] instead
+    fn new(
+        id: TrackId,
+        mut track: DecodedTrack,
+        rewind: bool,
+        output_sample_rate: u32,
+        speed: Speed,
+    ) -> Self {
+        let mut error = None;
+        if rewind {
+            if let Err(err) = track.seek(Duration::ZERO) {
+                error = Some(err.into());
+            }
+        }
+        let resampler = match Self::resampler(&track, output_sample_rate, speed) {
+            Ok(resampler) => resampler,
+            Err(err) => {
+                error = Some(err);
+                None
+            }
+        };
+        let position = track.progress().unwrap_or_default();
+        Self {
+            id,
+            track,
+            resampler,
+            sample_rate: output_sample_rate,
+            speed,
+            stretch: Self::stretch(output_sample_rate, speed),
+            position,
+            skip: 0,
+            pending: VecDeque::new(),
+            ended: error.is_some(),
+            error,
+        }
+    }
+
+    /// A resampler is also needed at the output sample rate to change the speed with
+    /// [SpeedMode::Varispeed]
+    fn resampler(
+        track: &DecodedTrack,
+        output_sample_rate: u32,
+        speed: Speed,
+    ) -> Result<Option<RubatoResamplerBuffered>, AudioPlayerError> {
+        let resample_speed = Self::resample_speed(speed);
+        if resample_speed == 1.0
+            && track
+                .codec_params()
+                .sample_rate
+                .is_some_and(|r| r == output_sample_rate)
+        {
+            return Ok(None);
+        }
+        match RubatoResamplerBuffered::new(track.codec_params(), output_sample_rate) {
+            Ok(mut r) => {
+                r.set_speed(resample_speed)?;
+                Ok(Some(r))
+            }
+            Err(ResamplerError::InvalidCodecParameters) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn resample_speed(speed: Speed) -> f64 {
+        match speed.mode {
+            SpeedMode::Varispeed => speed.speed,
+            SpeedMode::PreservePitch => 1.0,
+        }
+    }
+
+    fn stretch(output_sample_rate: u32, speed: Speed) -> Option<TimeStretch> {
+        match speed.mode {
+            SpeedMode::Varispeed => None,
+            SpeedMode::PreservePitch => Some(TimeStretch::new(output_sample_rate, speed.speed)),
+        }
+    }
+
+    /// Drop the samples decoded for the old rate and continue from the decoder position
+    fn set_output_sample_rate(&mut self, output_sample_rate: u32) {
+        let result =
+            Self::resampler(&self.track, output_sample_rate, self.speed).and_then(|resampler| {
+                self.resampler = resampler;
+                self.stretch = Self::stretch(output_sample_rate, self.speed);
+                self.sample_rate = output_sample_rate;
+                let position = self.track.progress()?;
+                self.seek(position)
+            });
+        if let Err(err) = result {
+            self.error = Some(err);
+            self.ended = true;
+        }
+    }
+
+    /// Applies to the samples decoded from now on, those decoded ahead keep their speed
+    fn set_speed(&mut self, speed: Speed) {
+        if speed == self.speed {
+            return;
+        }
+        if let Err(err) = self.change_speed(speed) {
+            self.error = Some(err);
+            self.ended = true;
+        }
+    }
+
+    fn change_speed(&mut self, speed: Speed) -> Result<(), AudioPlayerError> {
+        if speed.mode != SpeedMode::PreservePitch {
+            // what is held back for stretching is still played at the old speed
+            if let Some(mut stretch) = self.stretch.take() {
+                let stretched = stretch.flush();
+                if stretched.frames() > 0 {
+                    self.pending.push_back((stretched, self.speed.speed));
+                }
+            }
+        }
+        match self.resampler {
+            Some(ref mut resampler) => resampler.set_speed(Self::resample_speed(speed))?,
+            None => self.resampler = Self::resampler(&self.track, self.sample_rate, speed)?,
+        }
+        match self.stretch {
+            Some(ref mut stretch) => stretch.set_speed(speed.speed),
+            None => self.stretch = Self::stretch(self.sample_rate, speed),
+        }
+        self.speed = speed;
+        Ok(())
+    }
+
+    /// Track time taken up by `frames` played at `speed`
+    fn duration(&self, frames: usize, speed: f64) -> Duration {
+        Duration::from_secs_f64(frames as f64 * speed / self.sample_rate as f64)
+    }
+
+    /// Time left until the end of the track, including what was decoded ahead
+    fn remaining(&self) -> Option<Duration> {
+        let pending = self
+            .pending
+            .iter()
+            .map(|(buffer, speed)| self.duration(buffer.frames(), *speed))
+            .sum::<Duration>();
+        Some(self.track.remaining()? + pending)
+    }
+
+    /// Decode the start of the track so it is ready the moment it is needed
+    fn preload(&mut self) {
+        if self.pending.is_empty() && self.error.is_none() {
+            match self.next() {
+                Ok(Some((buffer, speed))) => self.put_back(buffer, speed),
+                Ok(None) => (),
+                // hold on to it until the track is played
+                Err(err) => self.error = Some(err),
+            }
+        }
+    }
+
+    /// Return a buffer taken with [TrackPlayback::next] as if it was never taken
+    fn put_back(&mut self, buffer: SampleBuf, speed: f64) {
+        let duration = self.duration(buffer.frames(), speed);
+        self.position = self.position.saturating_sub(duration);
+        self.pending.push_front((buffer, speed));
+    }
+
+    /// Up to `frames` frames, fewer once the track ends. An error is held on to until the track
+    /// is played on its own
+    fn next_frames(&mut self, frames: usize) -> SampleBuf {
+        let mut taken: Option<(SampleBuf, f64)> = None;
+        while taken.as_ref().map_or(0, |(t, _)| t.frames()) < frames {
+            match self.next() {
+                Ok(Some((buffer, speed))) => match taken {
+                    Some((ref mut taken, ref mut taken_speed)) => {
+                        taken.append(&buffer);
+                        *taken_speed = speed;
+                    }
+                    None => taken = Some((buffer, speed)),
+                },
+                Ok(None) => break,
+                Err(err) => {
+                    self.error = Some(err);
+                    break;
+                }
+            }
+        }
+        let (mut taken, speed) = taken.unwrap_or_default();
+        if taken.frames() > frames {
+            let rest = taken.split_off(frames);
+            self.put_back(rest, speed);
+        }
+        taken
+    }
+
+    /// The next samples and the speed they play at
+    fn next(&mut self) -> Result<Option<(SampleBuf, f64)>, AudioPlayerError> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        loop {
+            if let Some((buffer, speed)) = self.pending.pop_front() {
+                self.position += self.duration(buffer.frames(), speed);
+                return Ok(Some((buffer, speed)));
+            }
+            if self.ended {
+                return Ok(None);
+            }
+            match self.track.next() {
+                // everything was trimmed as encoder delay or padding, or is before a seek position
+                Ok(buffer) if buffer.frames() <= self.skip => self.skip -= buffer.frames(),
+                Ok(buffer) => {
+                    let buffer = match self.skip {
+                        0 => buffer,
+                        skip => {
+                            let mut buffer = SampleBuf::from(buffer);
+                            buffer.drain_front(skip);
+                            self.skip = 0;
+                            SampleBuffer::Buf(buffer)
+                        }
+                    };
+                    match self.resampler {
+                        Some(ref mut resampler) => {
+                            let mut resampled = vec![];
+                            let mut samples = resampler.resample(buffer)?;
+                            while let Some(sample) = samples.next() {
+                                resampled.push(sample?.clone());
+                            }
+                            resampled.into_iter().for_each(|b| self.push_pending(b));
+                        }
+                        None => {
+                            let buffer = SampleBuf::from(buffer);
+                            self.push_pending(buffer);
+                        }
+                    }
+                }
+                Err(err) if err.end_of_stream() => {
+                    self.ended = true;
+                    if let Some(ref mut resampler) = self.resampler {
+                        let flushed = resampler.flush()?;
+                        flushed.into_iter().for_each(|b| self.push_pending(b));
+                    }
+                    if let Some(ref mut stretch) = self.stretch {
+                        let stretched = stretch.flush();
+                        self.pending.push_back((stretched, self.speed.speed));
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Queue samples at the output sample rate, after stretching them
+    fn push_pending(&mut self, buffer: SampleBuf) {
+        let buffer = match self.stretch {
+            Some(ref mut stretch) => stretch.process(&buffer),
+            None => buffer,
+        };
+        if buffer.frames() > 0 {
+            self.pending.push_back((buffer, self.speed.speed));
+        }
+    }
+
+    /// Decoding continues from the packet containing `position`, the frames before it are
+    /// skipped so the first sample returned is the one at `position`
+    fn seek(&mut self, position: Duration) -> Result<(), AudioPlayerError> {
+        self.track.seek(position)?;
+        self.position = self.track.progress()?;
+        self.skip = 0;
+        if let Some(sample_rate) = self.track.codec_params().sample_rate {
+            let skip = position.saturating_sub(self.position).as_secs_f64() * sample_rate as f64;
+            self.skip = skip.round() as usize;
+            self.position = self.position.max(position);
+        }
+        if let Some(ref mut resampler) = self.resampler {
+            resampler.reset();
+        }
+        if let Some(ref mut stretch) = self.stretch {
+            stretch.reset();
+        }
+        self.pending.clear();
+        self.ended = false;
+        Ok(())
+    }
+}
+
+impl Drop for AudioPlayerExecutor {
+    fn drop(&mut self) {
+        // hold the lock so the executor cannot miss the notification
+        let _state = self.controller.state.lock().unwrap();
+        self.dropped.store(true, Ordering::Release);
+        self.controller.executor_condvar.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{track::tests::silence, NullSink};
+
+    #[test]
+    fn queue_changes_are_emitted() {
+        let player = AudioPlayer::with_sink(Default::default(), NullSink::unthrottled(8000));
+        let controller = player.controller();
+        for _ in 0..3 {
+            player.queue(silence());
+        }
+        let events = controller.subscribe(None);
+        controller.next();
+        controller.previous();
+        controller.jump_to(2).unwrap();
+        controller.move_track(0, 1).unwrap();
+        assert!(controller.jump_to(3).is_err());
+        let changes = events
+            .try_iter()
+            .filter(|event| matches!(event, AudioPlayerEvent::QueueChanged))
+            .count();
+        assert_eq!(changes, 4);
+        assert_eq!(controller.current_index(), Some(2));
+    }
+
+    #[test]
+    fn playback_clock() {
+        let ms = Duration::from_millis;
+        let mut clock = PlaybackClock::new(1000);
+        assert_eq!(clock.position(ms(0)), None);
+        // a second of the track at normal speed, then two seconds of it at twice the speed
+        clock.write(ms(0), 1000, 1.0);
+        clock.write(ms(1000), 1000, 2.0);
+        assert_eq!(clock.position(ms(2500)), Some(ms(0)));
+        assert_eq!(clock.position(ms(1500)), Some(ms(500)));
+        assert_eq!(clock.position(ms(1000)), Some(ms(1000)));
+        assert_eq!(clock.position(ms(500)), Some(ms(2000)));
+        assert_eq!(clock.position(ms(0)), Some(ms(3000)));
+
+        // after seeking the old position is not heard anymore
+        clock.clear();
+        clock.write(ms(10000), 1000, 1.0);
+        assert_eq!(clock.position(ms(1500)), Some(ms(10000)));
+        assert_eq!(clock.position(ms(250)), Some(ms(10750)));
+    }
+
+    struct Silence;
+
+    impl AudioProcessor for Silence {
+        fn process(&mut self, buffer: &mut SampleBuf, _sample_rate: u32) {
+            buffer
+                .channel_samples_mut()
+                .flatten()
+                .for_each(|s| *s = 0.0);
+        }
+    }
+
+    #[test]
+    fn processors() {
+        let player = AudioPlayer::with_sink(Default::default(), NullSink::unthrottled(8000));
+        let controller = player.controller();
+        let built_in = [ProcessorId::EQUALIZER, ProcessorId::VOLUME];
+        assert_eq!(controller.processors(), built_in);
+
+        let id = controller.insert_processor(1, Silence);
+        assert_eq!(
+            controller.processors(),
+            [ProcessorId::EQUALIZER, id, ProcessorId::VOLUME]
+        );
+        assert!(controller.move_processor(id, 5));
+        assert_eq!(controller.processors(), [built_in[0], built_in[1], id]);
+
+        assert!(controller.set_processor_bypassed(ProcessorId::VOLUME, true));
+        assert_eq!(
+            controller.processor_bypassed(ProcessorId::VOLUME),
+            Some(true)
+        );
+        assert!(!controller.remove_processor(ProcessorId::EQUALIZER));
+        assert!(!controller.remove_processor(ProcessorId::VOLUME));
+        assert!(controller.remove_processor(id));
+        assert!(!controller.remove_processor(id));
+        assert_eq!(controller.processor_bypassed(id), None);
+        assert_eq!(controller.processors(), built_in);
+    }
+}
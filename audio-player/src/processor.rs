@@ -0,0 +1,251 @@
+use std::sync::{Arc, Mutex};
+
+use crate::buffer::SampleBuf;
+
+/// An effect applied to the samples on their way to the output, added to the player with
+/// [crate::AudioPlayerController::insert_processor]. Settings that change during playback can be
+/// shared with the processor through an `Arc`, it is called from the playback thread
+pub trait AudioProcessor: Send {
+    /// Modify `buffer` in place, its samples are at the output `sample_rate` which changes
+    /// with the output device
+    fn process(&mut self, buffer: &mut SampleBuf, sample_rate: u32);
+
+    /// Forget what was processed so far, called when playback jumps like after seeking
+    fn reset(&mut self) {}
+}
+
+/// Identifies a processor for as long as it stays in the chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ProcessorId(u64);
+
+impl ProcessorId {
+    /// The equalizer set with [crate::AudioPlayerController::set_equalizer]
+    pub const EQUALIZER: ProcessorId = ProcessorId(0);
+    /// Volume, balance and loudness normalization, changed through the controller
+    pub const VOLUME: ProcessorId = ProcessorId(1);
+
+    fn built_in(self) -> bool {
+        self == Self::EQUALIZER || self == Self::VOLUME
+    }
+}
+
+pub(super) type SharedProcessor = Arc<Mutex<dyn AudioProcessor>>;
+
+struct ChainEntry {
+    id: ProcessorId,
+    processor: SharedProcessor,
+    bypassed: bool,
+}
+
+/// The processors in the order they are applied
+pub(super) struct ProcessorChain {
+    entries: Vec<ChainEntry>,
+    next_id: u64,
+    changed: bool,
+}
+
+impl ProcessorChain {
+    /// The chain with the built-in equalizer followed by the volume
+    pub(super) fn new(equalizer: SharedProcessor, volume: SharedProcessor) -> Self {
+        let entry = |id, processor| ChainEntry {
+            id,
+            processor,
+            bypassed: false,
+        };
+        Self {
+            entries: vec![
+                entry(ProcessorId::EQUALIZER, equalizer),
+                entry(ProcessorId::VOLUME, volume),
+            ],
+            next_id: 2,
+            changed: true,
+        }
+    }
+
+    pub(super) fn ids(&self) -> Vec<ProcessorId> {
+        self.entries.iter().map(|e| e.id).collect()
+    }
+
+    fn index(&self, id: ProcessorId) -> Option<usize> {
+        self.entries.iter().position(|e| e.id == id)
+    }
+
+    /// Insert at `index`, or at the end if it is past it
+    pub(super) fn insert(&mut self, index: usize, processor: SharedProcessor) -> ProcessorId {
+        let id = ProcessorId(self.next_id);
+        self.next_id += 1;
+        let index = index.min(self.entries.len());
+        self.entries.insert(
+            index,
+            ChainEntry {
+                id,
+                processor,
+                bypassed: false,
+            },
+        );
+        self.changed = true;
+        id
+    }
+
+    /// The built-in processors stay, they can be bypassed instead
+    pub(super) fn remove(&mut self, id: ProcessorId) -> bool {
+        if id.built_in() {
+            return false;
+        }
+        let Some(index) = self.index(id) else {
+            return false;
+        };
+        self.entries.remove(index);
+        self.changed = true;
+        true
+    }
+
+    /// Move to `index`, or to the end if it is past it
+    pub(super) fn move_to(&mut self, id: ProcessorId, index: usize) -> bool {
+        let Some(from) = self.index(id) else {
+            return false;
+        };
+        let entry = self.entries.remove(from);
+        self.entries.insert(index.min(self.entries.len()), entry);
+        self.changed = true;
+        true
+    }
+
+    pub(super) fn bypassed(&self, id: ProcessorId) -> Option<bool> {
+        self.index(id).map(|i| self.entries[i].bypassed)
+    }
+
+    pub(super) fn set_bypassed(&mut self, id: ProcessorId, bypassed: bool) -> bool {
+        let Some(index) = self.index(id) else {
+            return false;
+        };
+        if self.entries[index].bypassed != bypassed {
+            self.entries[index].bypassed = bypassed;
+            self.changed = true;
+        }
+        true
+    }
+
+    /// The processors that are not bypassed if the chain changed since the last call
+    pub(super) fn take_changed(&mut self) -> Option<Vec<SharedProcessor>> {
+        if !std::mem::take(&mut self.changed) {
+            return None;
+        }
+        let active = self.entries.iter().filter(|e| !e.bypassed);
+        Some(active.map(|e| e.processor.clone()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Adds `value` to every sample, to see the order processors ran in
+    struct Add(f64);
+
+    impl AudioProcessor for Add {
+        fn process(&mut self, buffer: &mut SampleBuf, _sample_rate: u32) {
+            buffer
+                .channel_samples_mut()
+                .flatten()
+                .for_each(|s| *s = *s * 10.0 + self.0);
+        }
+    }
+
+    fn shared(value: f64) -> SharedProcessor {
+        Arc::new(Mutex::new(Add(value)))
+    }
+
+    fn chain() -> ProcessorChain {
+        ProcessorChain::new(shared(1.0), shared(2.0))
+    }
+
+    /// Run the active processors on a single sample of 0
+    fn run(chain: &mut ProcessorChain) -> Option<f64> {
+        let mut buffer = SampleBuf::with_buffer(vec![vec![0.0]]);
+        for processor in chain.take_changed()? {
+            processor.lock().unwrap().process(&mut buffer, 48000);
+        }
+        buffer.samples(0).map(|s| s[0])
+    }
+
+    #[test]
+    fn insert() {
+        let mut chain = chain();
+        assert_eq!(chain.ids(), [ProcessorId::EQUALIZER, ProcessorId::VOLUME]);
+        assert_eq!(run(&mut chain), Some(12.0));
+        assert_eq!(run(&mut chain), None);
+
+        let first = chain.insert(0, shared(3.0));
+        let last = chain.insert(10, shared(4.0));
+        assert_ne!(first, last);
+        assert_eq!(
+            chain.ids(),
+            [first, ProcessorId::EQUALIZER, ProcessorId::VOLUME, last]
+        );
+        assert_eq!(run(&mut chain), Some(3124.0));
+    }
+
+    #[test]
+    fn remove() {
+        let mut chain = chain();
+        let id = chain.insert(1, shared(3.0));
+        run(&mut chain);
+
+        assert!(!chain.remove(ProcessorId::EQUALIZER));
+        assert!(!chain.remove(ProcessorId::VOLUME));
+        assert_eq!(run(&mut chain), None);
+
+        assert!(chain.remove(id));
+        assert!(!chain.remove(id));
+        assert_eq!(chain.ids(), [ProcessorId::EQUALIZER, ProcessorId::VOLUME]);
+        assert_eq!(run(&mut chain), Some(12.0));
+
+        // ids are not reused
+        assert_ne!(chain.insert(0, shared(3.0)), id);
+    }
+
+    #[test]
+    fn reorder() {
+        let mut chain = chain();
+        let id = chain.insert(2, shared(3.0));
+
+        assert!(chain.move_to(id, 0));
+        assert_eq!(
+            chain.ids(),
+            [id, ProcessorId::EQUALIZER, ProcessorId::VOLUME]
+        );
+        assert_eq!(run(&mut chain), Some(312.0));
+
+        assert!(chain.move_to(ProcessorId::EQUALIZER, 10));
+        assert_eq!(
+            chain.ids(),
+            [id, ProcessorId::VOLUME, ProcessorId::EQUALIZER]
+        );
+        assert_eq!(run(&mut chain), Some(321.0));
+
+        assert!(chain.remove(id));
+        assert!(!chain.move_to(id, 0));
+    }
+
+    #[test]
+    fn bypass() {
+        let mut chain = chain();
+        run(&mut chain);
+
+        assert!(chain.set_bypassed(ProcessorId::EQUALIZER, true));
+        assert_eq!(chain.bypassed(ProcessorId::EQUALIZER), Some(true));
+        assert_eq!(chain.ids(), [ProcessorId::EQUALIZER, ProcessorId::VOLUME]);
+        assert_eq!(run(&mut chain), Some(2.0));
+
+        // no change, the chain is not sent again
+        assert!(chain.set_bypassed(ProcessorId::EQUALIZER, true));
+        assert_eq!(run(&mut chain), None);
+
+        assert!(chain.set_bypassed(ProcessorId::EQUALIZER, false));
+        assert_eq!(run(&mut chain), Some(12.0));
+
+        assert!(!chain.set_bypassed(ProcessorId(10), true));
+        assert_eq!(chain.bypassed(ProcessorId(10)), None);
+    }
+}
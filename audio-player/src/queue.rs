@@ -0,0 +1,594 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash, Hasher},
+};
+
+use crate::{
+    decoder::DecodedTrack,
+    track::{StreamInfo, Track, TrackDetails, TrackSource},
+};
+
+/// Identifies a track for as long as it stays in the queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TrackId(u64);
+
+/// Snapshot of a track in the queue
+#[derive(Debug, Clone)]
+pub struct QueueItem {
+    id: TrackId,
+    source: TrackSource,
+    details: TrackDetails,
+    stream_info: StreamInfo,
+}
+
+impl QueueItem {
+    pub fn id(&self) -> TrackId {
+        self.id
+    }
+
+    pub fn source(&self) -> &TrackSource {
+        &self.source
+    }
+
+    pub fn details(&self) -> &TrackDetails {
+        &self.details
+    }
+
+    /// Includes the instant bitrate while the track is playing
+    pub fn stream_info(&self) -> &StreamInfo {
+        &self.stream_info
+    }
+}
+
+impl Hash for QueueItem {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+/// What plays once the current track finishes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RepeatMode {
+    /// Stop after the last track
+    #[default]
+    Off,
+    /// Play the current track again
+    One,
+    /// Start over from the first track after the last
+    All,
+}
+
+/// SplitMix64, plenty to shuffle a queue with
+struct Rng(u64);
+
+impl Rng {
+    /// A random seed if `None`
+    fn new(seed: Option<u64>) -> Self {
+        Self(seed.unwrap_or_else(|| RandomState::new().build_hasher().finish()))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`, `n` must not be 0
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Random order of the queue, no track plays twice until every track has played
+struct Shuffle {
+    /// Tracks played in this round, the current one last
+    played: Vec<TrackId>,
+    /// Picked ahead of time so it can be prepared before the current track ends
+    next: Option<TrackId>,
+}
+
+struct QueuedTrack {
+    item: QueueItem,
+    /// `None` while the executor is playing the track
+    decoded: Option<DecodedTrack>,
+    /// Set once the track has been handed to the executor so it is rewound before replaying
+    started: bool,
+}
+
+pub(super) struct Queue {
+    tracks: Vec<QueuedTrack>,
+    /// Index of the current track, `tracks.len()` when the queue has been played through
+    current: usize,
+    /// Set when the current track was replaced while the executor is playing
+    current_changed: bool,
+    next_id: u64,
+    repeat: RepeatMode,
+    shuffle: Option<Shuffle>,
+    rng: Rng,
+}
+
+impl Queue {
+    /// `shuffle_seed` makes the shuffle order reproducible
+    pub(super) fn new(shuffle_seed: Option<u64>) -> Self {
+        Self {
+            tracks: vec![],
+            current: 0,
+            current_changed: false,
+            next_id: 0,
+            repeat: RepeatMode::Off,
+            shuffle: None,
+            rng: Rng::new(shuffle_seed),
+        }
+    }
+
+    pub(super) fn repeat(&self) -> RepeatMode {
+        self.repeat
+    }
+
+    pub(super) fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.repeat = repeat;
+        // whether to start another round may have changed
+        if self.shuffle.as_ref().is_some_and(|s| s.next.is_none()) {
+            self.pick_next();
+        }
+    }
+
+    pub(super) fn shuffle(&self) -> bool {
+        self.shuffle.is_some()
+    }
+
+    pub(super) fn set_shuffle(&mut self, shuffle: bool) {
+        if shuffle == self.shuffle.is_some() {
+            return;
+        }
+        self.shuffle = shuffle.then(|| Shuffle {
+            played: vec![],
+            next: None,
+        });
+        self.moved();
+    }
+
+    pub(super) fn items(&self) -> Vec<QueueItem> {
+        self.tracks.iter().map(|t| t.item.clone()).collect()
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    pub(super) fn current_index(&self) -> Option<usize> {
+        (self.current < self.tracks.len()).then_some(self.current)
+    }
+
+    pub(super) fn current_id(&self) -> Option<TrackId> {
+        self.current().map(|t| t.id)
+    }
+
+    /// The track that will play once the current one finishes
+    pub(super) fn next_id(&self) -> Option<TrackId> {
+        self.successor(self.repeat).map(|i| self.tracks[i].item.id)
+    }
+
+    fn index(&self, id: TrackId) -> Option<usize> {
+        self.tracks.iter().position(|t| t.item.id == id)
+    }
+
+    /// Index of the track following the current one, `None` when playback ends after it
+    fn successor(&self, repeat: RepeatMode) -> Option<usize> {
+        if self.current >= self.tracks.len() {
+            return None;
+        }
+        match (repeat, &self.shuffle) {
+            (RepeatMode::One, _) => Some(self.current),
+            (_, Some(shuffle)) => shuffle.next.and_then(|id| self.index(id)),
+            (RepeatMode::All, None) => Some((self.current + 1) % self.tracks.len()),
+            (RepeatMode::Off, None) => Some(self.current + 1).filter(|&i| i < self.tracks.len()),
+        }
+    }
+
+    /// Move to the track following the current one
+    fn go_to_successor(&mut self, repeat: RepeatMode) {
+        let successor = self.successor(repeat);
+        if let (Some(shuffle), Some(index)) = (self.shuffle.as_mut(), successor) {
+            // every track has been played, the next round starts
+            if shuffle.played.contains(&self.tracks[index].item.id) {
+                shuffle.played.clear();
+            }
+        }
+        self.current = successor.unwrap_or(self.tracks.len());
+        self.moved();
+    }
+
+    /// Keep the shuffle order up to date after the current track changed
+    fn moved(&mut self) {
+        let current = self.current_id();
+        if let (Some(shuffle), Some(current)) = (self.shuffle.as_mut(), current) {
+            shuffle.played.retain(|&id| id != current);
+            shuffle.played.push(current);
+        }
+        self.pick_next();
+    }
+
+    /// Choose the track to shuffle to after the current one from those not played this round
+    fn pick_next(&mut self) {
+        let Some(shuffle) = self.shuffle.as_mut() else {
+            return;
+        };
+        let current = self.tracks.get(self.current).map(|t| t.item.id);
+        let ids = self.tracks.iter().map(|t| t.item.id);
+        let mut candidates: Vec<_> = ids
+            .clone()
+            .filter(|id| !shuffle.played.contains(id) && Some(*id) != current)
+            .collect();
+        if candidates.is_empty() && self.repeat == RepeatMode::All {
+            // the next round starts with anything but the track that just played
+            candidates = ids.clone().filter(|id| Some(*id) != current).collect();
+            if candidates.is_empty() {
+                candidates = ids.collect();
+            }
+        }
+        shuffle.next = match candidates.len() {
+            0 => None,
+            n => Some(candidates[self.rng.below(n)]),
+        };
+    }
+
+    pub(super) fn current(&self) -> Option<&QueueItem> {
+        self.tracks.get(self.current).map(|t| &t.item)
+    }
+
+    pub(super) fn item(&self, id: TrackId) -> Option<&QueueItem> {
+        self.tracks
+            .iter()
+            .find(|t| t.item.id == id)
+            .map(|t| &t.item)
+    }
+
+    pub(super) fn details_mut(&mut self, id: TrackId) -> Option<&mut TrackDetails> {
+        self.tracks
+            .iter_mut()
+            .find(|t| t.item.id == id)
+            .map(|t| &mut t.item.details)
+    }
+
+    pub(super) fn stream_info_mut(&mut self, id: TrackId) -> Option<&mut StreamInfo> {
+        self.tracks
+            .iter_mut()
+            .find(|t| t.item.id == id)
+            .map(|t| &mut t.item.stream_info)
+    }
+
+    pub(super) fn push(&mut self, track: Track) -> TrackId {
+        self.insert(self.tracks.len(), track)
+    }
+
+    /// Caller must ensure `index <= self.len()`
+    pub(super) fn insert(&mut self, index: usize, track: Track) -> TrackId {
+        let id = TrackId(self.next_id);
+        self.next_id += 1;
+        let at_end = self.current >= self.tracks.len();
+        self.tracks.insert(
+            index,
+            QueuedTrack {
+                item: QueueItem {
+                    id,
+                    source: track.source,
+                    details: track.details,
+                    stream_info: track.stream_info,
+                },
+                decoded: Some(track.decoded),
+                started: false,
+            },
+        );
+        // keep pointing at the same track, or at the new one if the queue was exhausted
+        if index < self.current || (!at_end && index == self.current) {
+            self.current += 1;
+        }
+        if at_end {
+            self.moved();
+        } else if self.shuffle.as_ref().is_some_and(|s| s.next.is_none()) {
+            self.pick_next();
+        }
+        id
+    }
+
+    pub(super) fn remove(&mut self, index: usize) -> Option<QueueItem> {
+        if index >= self.tracks.len() {
+            return None;
+        }
+        let removed = self.tracks.remove(index);
+        if index < self.current {
+            self.current -= 1;
+        } else if index == self.current {
+            self.current_changed = true;
+            if let Some(shuffle) = self.shuffle.as_mut() {
+                // skip to where shuffle was going next rather than the track that took its place
+                shuffle.played.retain(|&id| id != removed.item.id);
+                let next = shuffle.next.and_then(|id| self.index(id));
+                self.current = next.unwrap_or(self.tracks.len());
+            }
+            self.moved();
+        } else if self
+            .shuffle
+            .as_ref()
+            .is_some_and(|s| s.next == Some(removed.item.id))
+        {
+            self.pick_next();
+        }
+        Some(removed.item)
+    }
+
+    pub(super) fn move_track(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.tracks.len() || to >= self.tracks.len() {
+            return false;
+        }
+        let track = self.tracks.remove(from);
+        self.tracks.insert(to, track);
+        if self.current == from {
+            self.current = to;
+        } else if from < self.current && to >= self.current {
+            self.current -= 1;
+        } else if from > self.current && to <= self.current {
+            self.current += 1;
+        }
+        true
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.tracks.clear();
+        self.current = 0;
+        self.current_changed = true;
+        if let Some(shuffle) = self.shuffle.as_mut() {
+            shuffle.played.clear();
+            shuffle.next = None;
+        }
+    }
+
+    /// Repeating the current track is ignored when skipping
+    pub(super) fn next(&mut self) {
+        let repeat = match self.repeat {
+            RepeatMode::One => RepeatMode::Off,
+            repeat => repeat,
+        };
+        self.go_to_successor(repeat);
+        self.current_changed = true;
+    }
+
+    pub(super) fn previous(&mut self) {
+        match self.shuffle.as_mut() {
+            // back to the track shuffled to before, or restart the first of this round
+            Some(shuffle) => {
+                if shuffle.played.len() > 1 {
+                    shuffle.played.pop();
+                }
+                if let Some(&id) = shuffle.played.last() {
+                    self.current = self.index(id).unwrap_or(self.current);
+                }
+            }
+            None => self.current = self.current.saturating_sub(1),
+        }
+        self.moved();
+        self.current_changed = true;
+    }
+
+    pub(super) fn jump_to(&mut self, index: usize) -> bool {
+        if index >= self.tracks.len() {
+            return false;
+        }
+        self.current = index;
+        self.moved();
+        self.current_changed = true;
+        true
+    }
+
+    /// Advance after the current track finished on its own
+    pub(super) fn advance(&mut self) {
+        self.go_to_successor(self.repeat);
+    }
+
+    pub(super) fn current_changed(&self) -> bool {
+        self.current_changed
+    }
+
+    /// Returns whether the current track changed since the last call
+    pub(super) fn take_current_changed(&mut self) -> bool {
+        std::mem::take(&mut self.current_changed)
+    }
+
+    /// Hand the current track to the executor.
+    ///
+    /// The returned bool is true if the track was played before and needs to be rewound.
+    pub(super) fn checkout_current(&mut self) -> Option<(TrackId, DecodedTrack, bool)> {
+        self.current_changed = false;
+        let track = self.tracks.get_mut(self.current)?;
+        let decoded = track.decoded.take()?;
+        let rewind = std::mem::replace(&mut track.started, true);
+        Some((track.item.id, decoded, rewind))
+    }
+
+    /// Hand the track after the current one to the executor ahead of time
+    pub(super) fn checkout_next(&mut self) -> Option<(TrackId, DecodedTrack, bool)> {
+        let next = self.successor(self.repeat)?;
+        let track = self.tracks.get_mut(next)?;
+        let decoded = track.decoded.take()?;
+        let rewind = std::mem::replace(&mut track.started, true);
+        Some((track.item.id, decoded, rewind))
+    }
+
+    /// Return a track previously taken with [Queue::checkout_current]
+    pub(super) fn checkin(&mut self, id: TrackId, decoded: DecodedTrack) {
+        // dropped if the track was removed in the meantime
+        if let Some(track) = self.tracks.iter_mut().find(|t| t.item.id == id) {
+            track.decoded = Some(decoded);
+            // no longer playing
+            track.item.stream_info.set_instant_bitrate(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::track::tests::silence;
+
+    fn queue(tracks: usize, seed: u64) -> (Queue, Vec<TrackId>) {
+        let mut queue = Queue::new(Some(seed));
+        let ids = (0..tracks).map(|_| queue.push(silence())).collect();
+        (queue, ids)
+    }
+
+    /// Ids of the current track and of the `count - 1` tracks that follow it
+    fn play(queue: &mut Queue, count: usize) -> Vec<Option<TrackId>> {
+        let mut played = vec![queue.current_id()];
+        for _ in 1..count {
+            queue.advance();
+            played.push(queue.current_id());
+        }
+        played
+    }
+
+    #[test]
+    fn next_previous_jump() {
+        let (mut queue, ids) = queue(3, 0);
+        assert_eq!(queue.current_id(), Some(ids[0]));
+        queue.previous();
+        assert_eq!(queue.current_id(), Some(ids[0]));
+        queue.next();
+        assert_eq!(queue.current_id(), Some(ids[1]));
+        assert!(queue.jump_to(2));
+        assert!(!queue.jump_to(3));
+        assert_eq!(queue.current_id(), Some(ids[2]));
+        // past the last track the queue is played through
+        queue.next();
+        assert_eq!(queue.current_index(), None);
+        queue.next();
+        queue.previous();
+        assert_eq!(queue.current_id(), Some(ids[2]));
+    }
+
+    #[test]
+    fn shuffle_seed() {
+        let order = |seed| {
+            let (mut queue, _) = queue(10, seed);
+            queue.set_shuffle(true);
+            play(&mut queue, 10)
+        };
+        let (_, ids) = queue(10, 0);
+        let in_order: Vec<_> = ids.into_iter().map(Some).collect();
+        assert_eq!(order(7), order(7));
+        assert_ne!(order(7), order(8));
+        assert_ne!(order(7), in_order);
+    }
+
+    #[test]
+    fn shuffle_rounds() {
+        let (mut queue, ids) = queue(5, 1);
+        queue.set_repeat(RepeatMode::All);
+        queue.set_shuffle(true);
+        let played = play(&mut queue, 15);
+        for round in played.chunks(5) {
+            let round: HashSet<_> = round.iter().map(|id| id.unwrap()).collect();
+            assert_eq!(round, ids.iter().copied().collect());
+        }
+        // a new round doesn't start with the track that just played
+        assert!(played.windows(2).all(|pair| pair[0] != pair[1]));
+        // without repeat playback ends once every track played
+        let (mut queue, ids) = self::queue(5, 1);
+        queue.set_shuffle(true);
+        let played = play(&mut queue, 6);
+        let round: HashSet<_> = played[..5].iter().map(|id| id.unwrap()).collect();
+        assert_eq!(round, ids.into_iter().collect());
+        assert_eq!(played[5], None);
+    }
+
+    #[test]
+    fn repeat_one() {
+        let (mut queue, ids) = queue(3, 0);
+        queue.set_repeat(RepeatMode::One);
+        queue.advance();
+        assert_eq!(queue.current_id(), Some(ids[0]));
+        assert_eq!(queue.next_id(), Some(ids[0]));
+        // skipping moves on anyway
+        queue.next();
+        assert_eq!(queue.current_id(), Some(ids[1]));
+        queue.previous();
+        assert_eq!(queue.current_id(), Some(ids[0]));
+        queue.previous();
+        assert_eq!(queue.current_id(), Some(ids[0]));
+    }
+
+    #[test]
+    fn repeat_all() {
+        let (mut queue, ids) = queue(3, 0);
+        queue.set_repeat(RepeatMode::All);
+        assert_eq!(
+            play(&mut queue, 5),
+            [ids[0], ids[1], ids[2], ids[0], ids[1]].map(Some)
+        );
+        queue.next();
+        queue.next();
+        assert_eq!(queue.current_id(), Some(ids[0]));
+        queue.set_repeat(RepeatMode::Off);
+        assert_eq!(
+            play(&mut queue, 4),
+            [Some(ids[0]), Some(ids[1]), Some(ids[2]), None]
+        );
+        assert_eq!(queue.current_index(), None);
+    }
+
+    #[test]
+    fn insert_keeps_current() {
+        let (mut queue, ids) = queue(3, 0);
+        queue.jump_to(1);
+        queue.insert(0, silence());
+        assert_eq!(queue.current_index(), Some(2));
+        queue.insert(2, silence());
+        assert_eq!(queue.current_index(), Some(3));
+        queue.insert(4, silence());
+        assert_eq!(queue.current_index(), Some(3));
+        assert_eq!(queue.current_id(), Some(ids[1]));
+        // once played through the queue continues with what is added
+        let (mut queue, _) = self::queue(1, 0);
+        queue.advance();
+        assert_eq!(queue.current_index(), None);
+        let added = queue.insert(1, silence());
+        assert_eq!(queue.current_id(), Some(added));
+    }
+
+    #[test]
+    fn remove_keeps_current() {
+        let (mut queue, ids) = queue(5, 0);
+        queue.jump_to(2);
+        queue.take_current_changed();
+        queue.remove(0);
+        assert_eq!(queue.current_id(), Some(ids[2]));
+        queue.remove(3);
+        assert_eq!(queue.current_id(), Some(ids[2]));
+        assert!(!queue.current_changed());
+        // the following track takes the place of the current one
+        queue.remove(1);
+        assert_eq!(queue.current_id(), Some(ids[3]));
+        assert!(queue.current_changed());
+        assert!(queue.remove(5).is_none());
+    }
+
+    #[test]
+    fn move_keeps_current() {
+        let (mut queue, ids) = queue(5, 0);
+        queue.jump_to(2);
+        assert!(queue.move_track(2, 4));
+        assert_eq!(queue.current_index(), Some(4));
+        assert!(queue.move_track(0, 4));
+        assert_eq!(queue.current_index(), Some(3));
+        assert!(queue.move_track(4, 0));
+        assert_eq!(queue.current_index(), Some(4));
+        assert!(queue.move_track(1, 2));
+        assert_eq!(queue.current_index(), Some(4));
+        assert_eq!(queue.current_id(), Some(ids[2]));
+        assert!(!queue.move_track(0, 5));
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use crate::{
+    buffer::SampleBuf,
+    output::{AudioOutputError, AudioSink},
+};
+
+/// Discards every sample, for running the player without a sound card
+pub struct NullSink {
+    sample_rate: u32,
+    realtime: bool,
+    /// When playback started or resumed
+    started: Option<Instant>,
+    /// Frames written since `started`, including those not yet played when paused
+    frames: u64,
+}
+
+impl NullSink {
+    /// How far writes may get ahead of the clock, like the buffer of an output device
+    const BUFFER: Duration = Duration::from_millis(100);
+
+    /// Takes samples at the pace an output device would
+    pub fn realtime(sample_rate: u32) -> Self {
+        Self::new(sample_rate, true)
+    }
+
+    /// Takes samples as fast as they are decoded
+    pub fn unthrottled(sample_rate: u32) -> Self {
+        Self::new(sample_rate, false)
+    }
+
+    fn new(sample_rate: u32, realtime: bool) -> Self {
+        Self {
+            sample_rate,
+            realtime,
+            started: None,
+            frames: 0,
+        }
+    }
+
+    /// Length of the samples written since `started`
+    fn written(&self) -> Duration {
+        Duration::from_secs_f64(self.frames as f64 / self.sample_rate as f64)
+    }
+}
+
+impl AudioSink for NullSink {
+    fn write(&mut self, samples: &SampleBuf) -> Result<(), AudioOutputError> {
+        if !self.realtime {
+            return Ok(());
+        }
+        let started = *self.started.get_or_insert_with(Instant::now);
+        self.frames += samples.frames() as u64;
+        if let Some(ahead) = self.written().checked_sub(started.elapsed() + Self::BUFFER) {
+            std::thread::sleep(ahead);
+        }
+        Ok(())
+    }
+
+    fn play(&mut self) -> Result<(), AudioOutputError> {
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<(), AudioOutputError> {
+        // what was written ahead is still to be played once resumed
+        let ahead = self.delay().as_secs_f64() * self.sample_rate as f64;
+        self.started = None;
+        self.frames = ahead as u64;
+        Ok(())
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn delay(&self) -> Duration {
+        match self.started {
+            Some(started) => self.written().saturating_sub(started.elapsed()),
+            None => Duration::ZERO,
+        }
+    }
+
+    fn clear(&mut self) -> Result<(), AudioOutputError> {
+        self.started = None;
+        self.frames = 0;
+        Ok(())
+    }
+}
+
+/// Renders playback to a WAV file of 32-bit float samples, as fast as it is decoded
+pub struct WavSink {
+    writer: WavWriter<BufWriter<File>>,
+    channels: usize,
+    sample_rate: u32,
+    /// Frames written since the header was last updated
+    unflushed: u32,
+}
+
+impl WavSink {
+    /// Tracks with a different number of channels are mixed to `channels`, mono is copied to
+    /// every channel, channels missing from the track are left silent and extra channels are
+    /// averaged into `channel % channels`
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Self, AudioOutputError> {
+        let spec = WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let writer =
+            WavWriter::create(path, spec).map_err(|err| AudioOutputError::Sink(err.into()))?;
+        Ok(Self {
+            writer,
+            channels: channels as usize,
+            sample_rate,
+            unflushed: 0,
+        })
+    }
+}
+
+impl AudioSink for WavSink {
+    fn write(&mut self, samples: &SampleBuf) -> Result<(), AudioOutputError> {
+        // the channels of the track mixed into each channel of the file
+        let channels = samples.channel_mix(self.channels);
+        for frame in 0..samples.frames() {
+            for mixed in &channels {
+                let sum: f64 = mixed.iter().map(|samples| samples[frame]).sum();
+                let sample = sum / mixed.len().max(1) as f64;
+                self.writer
+                    .write_sample(sample as f32)
+                    .map_err(|err| AudioOutputError::Sink(err.into()))?;
+            }
+        }
+        // keep the header up to date every second so the file is valid even if the player is
+        // never dropped, dropping the sink updates it too
+        self.unflushed += samples.frames() as u32;
+        if self.unflushed >= self.sample_rate {
+            self.unflushed = 0;
+            self.writer
+                .flush()
+                .map_err(|err| AudioOutputError::Sink(err.into()))?;
+        }
+        Ok(())
+    }
+
+    fn play(&mut self) -> Result<(), AudioOutputError> {
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<(), AudioOutputError> {
+        Ok(())
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{f32::consts::TAU, path::PathBuf};
+
+    use hound::WavReader;
+
+    use super::*;
+    use crate::{track::tests::wav, AudioPlayer, AudioPlayerEvent, Track};
+
+    const RATE: u32 = 48000;
+
+    /// Half a second of a 440 Hz sine at half of full scale in every channel
+    fn sine(channels: u16) -> Track {
+        let samples: Vec<f32> = (0..RATE / 2)
+            .map(|i| 0.5 * (TAU * 440.0 * i as f32 / RATE as f32).sin())
+            .flat_map(|sample| std::iter::repeat_n(sample, channels as usize))
+            .collect();
+        Track::open_bytes(wav(&samples, channels, RATE), Some("wav")).unwrap()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("audio-player-{}-{}.wav", name, std::process::id()))
+    }
+
+    /// Interleaved samples of the file, which is removed
+    fn read_samples(path: &Path) -> Vec<f32> {
+        let samples = WavReader::open(path)
+            .unwrap()
+            .samples()
+            .map(Result::unwrap)
+            .collect();
+        std::fs::remove_file(path).unwrap();
+        samples
+    }
+
+    #[test]
+    fn null_sink_plays_queue() {
+        let player = AudioPlayer::with_sink(Default::default(), NullSink::unthrottled(RATE));
+        let events = player.controller().subscribe(None);
+        let ids = [player.queue(sine(2)), player.queue(sine(1))];
+        player.controller().play();
+        player.wait_until_end();
+        let ended: Vec<_> = events
+            .try_iter()
+            .filter_map(|event| match event {
+                AudioPlayerEvent::TrackEnded(id) => Some(id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ended, ids);
+    }
+
+    #[test]
+    fn wav_sink_renders_queue() {
+        let path = temp_path("queue");
+        let sink = WavSink::create(&path, RATE, 2).unwrap();
+        let player = AudioPlayer::with_sink(Default::default(), sink);
+        player.queue(sine(2));
+        player.queue(sine(1));
+        player.controller().play();
+        player.wait_until_end();
+        let samples = read_samples(&path);
+        // two tracks of half a second in two channels
+        assert_eq!(samples.len(), 2 * RATE as usize);
+        // mono is copied to both channels
+        assert!(samples.chunks(2).all(|frame| frame[0] == frame[1]));
+        let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!((peak - 0.5).abs() < 0.01, "peak {}", peak);
+    }
+
+    #[test]
+    fn wav_sink_mixes_channels() {
+        let path = temp_path("mix");
+        let mut sink = WavSink::create(&path, RATE, 2).unwrap();
+        let quad = [1.0, 0.5, 0.0, 0.25].map(|sample| vec![sample; 4]).to_vec();
+        sink.write(&SampleBuf::with_buffer(quad)).unwrap();
+        drop(sink);
+        let samples = read_samples(&path);
+        assert_eq!(samples.len(), 8);
+        assert!(samples.chunks(2).all(|frame| frame == [0.5, 0.375]));
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    sync::{Mutex, PoisonError},
+};
+
+use symphonia::core::io::MediaSource;
+
+/// A reader that can seek as a [MediaSource], which also has to be `Sync`. The mutex is only
+/// there for that, it is never locked
+pub(super) struct ReaderSource<R> {
+    reader: Mutex<R>,
+    len: u64,
+}
+
+impl<R: Read + Seek + Send> ReaderSource<R> {
+    pub(super) fn new(mut reader: R) -> io::Result<Self> {
+        let position = reader.stream_position()?;
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(position))?;
+        Ok(Self {
+            reader: Mutex::new(reader),
+            len,
+        })
+    }
+
+    fn reader(&mut self) -> &mut R {
+        self.reader
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<R: Read + Seek + Send> Read for ReaderSource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader().read(buf)
+    }
+}
+
+impl<R: Read + Seek + Send> Seek for ReaderSource<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.reader().seek(pos)
+    }
+}
+
+impl<R: Read + Seek + Send> MediaSource for ReaderSource<R> {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.len)
+    }
+}
+
+/// A reader that can only be read front to back as a [MediaSource], like a pipe
+pub(super) struct StreamSource<R> {
+    reader: Mutex<R>,
+}
+
+impl<R: Read + Send> StreamSource<R> {
+    pub(super) fn new(reader: R) -> Self {
+        Self {
+            reader: Mutex::new(reader),
+        }
+    }
+}
+
+impl<R: Read + Send> Read for StreamSource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .read(buf)
+    }
+}
+
+impl<R: Read + Send> Seek for StreamSource<R> {
+    fn seek(&mut self, _: SeekFrom) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "stream can't seek",
+        ))
+    }
+}
+
+impl<R: Read + Send> MediaSource for StreamSource<R> {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
@@ -0,0 +1,300 @@
+use crate::buffer::SampleBuf;
+
+/// How [crate::AudioPlayerController::set_speed] changes the speed of playback
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SpeedMode {
+    /// Resample the audio, so the pitch rises when playing faster like a tape played fast
+    #[default]
+    Varispeed,
+    /// Stretch the audio in time while keeping its pitch, suited to speech
+    PreservePitch,
+}
+
+/// Playback speed settings, changed through the controller
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Speed {
+    /// Factor of the normal speed in `Speed::MIN..=Speed::MAX`
+    pub(super) speed: f64,
+    pub(super) mode: SpeedMode,
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            mode: SpeedMode::default(),
+        }
+    }
+}
+
+impl Speed {
+    pub(super) const MIN: f64 = 0.5;
+    pub(super) const MAX: f64 = 3.0;
+}
+
+/// Time stretching with WSOLA (waveform similarity overlap-add). Segments are overlap-added at a
+/// fixed hop on the output while the hop on the input follows the speed, and each segment is
+/// shifted a little to where it best continues the previous one so the waveforms line up
+pub(super) struct TimeStretch {
+    speed: f64,
+    /// Length of a segment, overlapped by half with its neighbours
+    segment: usize,
+    /// How far a segment may be shifted from where the speed puts it
+    tolerance: usize,
+    window: Vec<f64>,
+    /// Input not yet consumed, per channel
+    input: Vec<Vec<f64>>,
+    /// Where the next segment is taken from in `input` before shifting it
+    position: f64,
+    /// Where the previous segment continues in `input`, `None` before the first segment
+    continuation: Option<usize>,
+    /// Overlap-added segments, the first half is complete after each step
+    output: Vec<Vec<f64>>,
+    /// Output frames owed for the input received so far
+    expected: f64,
+    /// Output frames produced so far
+    produced: u64,
+}
+
+impl TimeStretch {
+    /// Shorter segments smear transients less, longer ones keep low voices intact
+    const SEGMENT_SECONDS: f64 = 0.04;
+    const TOLERANCE_SECONDS: f64 = 0.01;
+    /// Only every so many frames are compared when looking for the best shift
+    const STRIDE: usize = 4;
+
+    pub(super) fn new(sample_rate: u32, speed: f64) -> Self {
+        let segment = (Self::SEGMENT_SECONDS * sample_rate as f64) as usize / 2 * 2;
+        let tolerance = (Self::TOLERANCE_SECONDS * sample_rate as f64) as usize;
+        // periodic Hann, which adds up to one when overlapped by half
+        let window = (0..segment)
+            .map(|i| 0.5 - 0.5 * (std::f64::consts::TAU * i as f64 / segment as f64).cos())
+            .collect();
+        Self {
+            speed,
+            segment,
+            tolerance,
+            window,
+            input: vec![],
+            position: 0.0,
+            continuation: None,
+            output: vec![],
+            expected: 0.0,
+            produced: 0,
+        }
+    }
+
+    pub(super) fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+    }
+
+    /// Stretch `buffer`, the output lags behind the input by about a segment
+    pub(super) fn process(&mut self, buffer: &SampleBuf) -> SampleBuf {
+        if self.input.len() != buffer.channels() {
+            self.input = vec![vec![]; buffer.channels()];
+            self.output = vec![vec![0.0; self.segment]; buffer.channels()];
+        }
+        self.input
+            .iter_mut()
+            .zip(buffer.channel_samples())
+            .for_each(|(input, samples)| input.extend_from_slice(samples));
+        self.expected += buffer.frames() as f64 / self.speed;
+        let mut stretched = vec![vec![]; self.input.len()];
+        while self.ready() {
+            self.step(&mut stretched);
+        }
+        self.discard_consumed();
+        SampleBuf::with_buffer(stretched)
+    }
+
+    /// Stretch the input that is held back, filling up with silence, and start over
+    pub(super) fn flush(&mut self) -> SampleBuf {
+        let mut stretched = vec![vec![]; self.input.len()];
+        let expected = self.expected.round() as u64;
+        let produced = self.produced;
+        while self.produced < expected {
+            if !self.ready() {
+                let silence = self.segment + self.tolerance;
+                self.input
+                    .iter_mut()
+                    .for_each(|input| input.resize(input.len() + silence, 0.0));
+            }
+            self.step(&mut stretched);
+        }
+        let frames = expected.saturating_sub(produced) as usize;
+        stretched
+            .iter_mut()
+            .for_each(|samples| samples.truncate(frames));
+        self.reset();
+        SampleBuf::with_buffer(stretched)
+    }
+
+    /// Forget the input received so far, to continue from another position in the track
+    pub(super) fn reset(&mut self) {
+        self.input.iter_mut().for_each(|input| input.clear());
+        self.output.iter_mut().for_each(|output| output.fill(0.0));
+        self.position = 0.0;
+        self.continuation = None;
+        self.expected = 0.0;
+        self.produced = 0;
+    }
+
+    fn ready(&self) -> bool {
+        let available = self.input.first().map_or(0, |input| input.len());
+        self.position.round() as usize + self.tolerance + self.segment <= available
+    }
+
+    /// Overlap-add the next segment and move half a segment to `stretched`
+    fn step(&mut self, stretched: &mut [Vec<f64>]) {
+        let hop = self.segment / 2;
+        let nominal = self.position.round() as usize;
+        let start = match self.continuation {
+            Some(continuation) => self.best_start(continuation, nominal),
+            None => nominal,
+        };
+        for (c, output) in self.output.iter_mut().enumerate() {
+            let input = &self.input[c][start..start + self.segment];
+            for (i, (output, input)) in output.iter_mut().zip(input).enumerate() {
+                // nothing to overlap the start of the first segment with
+                let weight = match self.continuation {
+                    None if i < hop => 1.0,
+                    _ => self.window[i],
+                };
+                *output += input * weight;
+            }
+            stretched[c].extend(output.drain(..hop));
+            output.resize(self.segment, 0.0);
+        }
+        self.continuation = Some(start + hop);
+        self.position += hop as f64 * self.speed;
+        self.produced += hop as u64;
+    }
+
+    /// Start near `nominal` whose first half is most similar to what follows the previous
+    /// segment at `continuation`, which is preferred when there is a tie
+    fn best_start(&self, continuation: usize, nominal: usize) -> usize {
+        let hop = self.segment / 2;
+        let lowest = nominal.saturating_sub(self.tolerance);
+        let highest = nominal + self.tolerance;
+        let similarity = |start: usize| {
+            let (mut correlation, mut energy) = (0.0, 0.0);
+            for input in &self.input {
+                for i in (0..hop).step_by(Self::STRIDE) {
+                    correlation += input[continuation + i] * input[start + i];
+                    energy += input[start + i] * input[start + i];
+                }
+            }
+            correlation / (energy + f64::EPSILON).sqrt()
+        };
+        let mut best = continuation.clamp(lowest, highest);
+        let mut best_similarity = similarity(best);
+        for start in lowest..=highest {
+            let similarity = similarity(start);
+            if similarity > best_similarity {
+                best = start;
+                best_similarity = similarity;
+            }
+        }
+        best
+    }
+
+    /// Drop the input no later segment can start from
+    fn discard_consumed(&mut self) {
+        let Some(continuation) = self.continuation else {
+            return;
+        };
+        let consumed =
+            continuation.min((self.position.round() as usize).saturating_sub(self.tolerance));
+        self.input.iter_mut().for_each(|input| {
+            input.drain(..consumed);
+        });
+        self.position -= consumed as f64;
+        self.continuation = Some(continuation - consumed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    fn sine(frequency: f64, frames: usize) -> Vec<f64> {
+        (0..frames)
+            .map(|i| 0.5 * (TAU * frequency * i as f64 / RATE as f64).sin())
+            .collect()
+    }
+
+    /// Stretch stereo `samples` given in buffers of `chunk` frames, then flush
+    fn stretch(samples: &[f64], speed: f64, chunk: usize) -> Vec<f64> {
+        let mut stretch = TimeStretch::new(RATE, speed);
+        let mut output = vec![];
+        let mut append = |buffer: SampleBuf| {
+            assert_eq!(buffer.channels(), 2);
+            assert_eq!(buffer.samples(0), buffer.samples(1));
+            output.extend_from_slice(buffer.samples(0).unwrap());
+        };
+        for samples in samples.chunks(chunk) {
+            let buffer = SampleBuf::with_buffer(vec![samples.to_vec(); 2]);
+            append(stretch.process(&buffer));
+        }
+        append(stretch.flush());
+        output
+    }
+
+    /// Frequency from the number of times the signal crosses zero
+    fn frequency(samples: &[f64]) -> f64 {
+        let crossings = samples
+            .windows(2)
+            .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+            .count();
+        crossings as f64 / 2.0 / (samples.len() as f64 / RATE as f64)
+    }
+
+    #[test]
+    fn length() {
+        let input = sine(440.0, RATE as usize);
+        for speed in [Speed::MIN, 1.0, 1.5, Speed::MAX] {
+            let output = stretch(&input, speed, 1000);
+            let expected = input.len() as f64 / speed;
+            assert!(
+                (output.len() as f64 - expected).abs() <= 1.0,
+                "{} {}",
+                speed,
+                output.len()
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_pitch() {
+        let input = sine(440.0, RATE as usize);
+        for speed in [Speed::MIN, 1.0, 1.5, Speed::MAX] {
+            let output = stretch(&input, speed, 1000);
+            // leave out the start and the silence the flush may have added at the end
+            let steady = &output[output.len() / 4..output.len() * 3 / 4];
+            let frequency = frequency(steady);
+            assert!(
+                (frequency / 440.0 - 1.0).abs() < 0.02,
+                "{} {}",
+                speed,
+                frequency
+            );
+        }
+    }
+
+    #[test]
+    fn reset() {
+        let input = sine(440.0, RATE as usize / 2);
+        let mut stretch = TimeStretch::new(RATE, 2.0);
+        stretch.process(&SampleBuf::with_buffer(vec![input.clone()]));
+        stretch.reset();
+        let mut frames = stretch
+            .process(&SampleBuf::with_buffer(vec![input.clone()]))
+            .frames();
+        frames += stretch.flush().frames();
+        assert_eq!(frames, input.len() / 2);
+    }
+}
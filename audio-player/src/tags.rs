@@ -0,0 +1,351 @@
+use std::collections::BTreeMap;
+
+use symphonia::core::meta::{StandardTagKey, Tag, Value};
+
+/// Tags of a track read from its container and from metadata around it like ID3
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tags {
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    album_artist: Option<String>,
+    track_number: Option<u32>,
+    track_total: Option<u32>,
+    disc_number: Option<u32>,
+    disc_total: Option<u32>,
+    date: Option<String>,
+    genre: Option<String>,
+    composer: Option<String>,
+    comment: Option<String>,
+    label: Option<String>,
+    isrc: Option<String>,
+    bpm: Option<f64>,
+    musicbrainz_recording_id: Option<String>,
+    musicbrainz_track_id: Option<String>,
+    musicbrainz_album_id: Option<String>,
+    musicbrainz_artist_id: Option<String>,
+    musicbrainz_album_artist_id: Option<String>,
+    musicbrainz_release_group_id: Option<String>,
+    sort_title: Option<String>,
+    sort_artist: Option<String>,
+    sort_album: Option<String>,
+    sort_album_artist: Option<String>,
+    sort_composer: Option<String>,
+    /// Values of every tag by the key of its format, including those without a field here
+    raw: BTreeMap<String, Vec<String>>,
+}
+
+impl Tags {
+    /// Read `tag` into its field, the first value of a tag that appears more than once is kept
+    pub(super) fn add(&mut self, tag: &Tag) {
+        if let Some(text) = Self::text(&tag.value) {
+            self.raw.entry(tag.key.clone()).or_default().push(text);
+        }
+        let Some(std_key) = tag.std_key else {
+            return;
+        };
+        let value = &tag.value;
+        let field = match std_key {
+            StandardTagKey::TrackTitle => &mut self.title,
+            StandardTagKey::Artist => &mut self.artist,
+            StandardTagKey::Album => &mut self.album,
+            StandardTagKey::AlbumArtist => &mut self.album_artist,
+            StandardTagKey::Date => &mut self.date,
+            StandardTagKey::Genre => &mut self.genre,
+            StandardTagKey::Composer => &mut self.composer,
+            StandardTagKey::Comment => &mut self.comment,
+            StandardTagKey::Label => &mut self.label,
+            StandardTagKey::IdentIsrc => &mut self.isrc,
+            StandardTagKey::MusicBrainzRecordingId => &mut self.musicbrainz_recording_id,
+            StandardTagKey::MusicBrainzTrackId => &mut self.musicbrainz_track_id,
+            StandardTagKey::MusicBrainzAlbumId => &mut self.musicbrainz_album_id,
+            StandardTagKey::MusicBrainzArtistId => &mut self.musicbrainz_artist_id,
+            StandardTagKey::MusicBrainzAlbumArtistId => &mut self.musicbrainz_album_artist_id,
+            StandardTagKey::MusicBrainzReleaseGroupId => &mut self.musicbrainz_release_group_id,
+            StandardTagKey::SortTrackTitle => &mut self.sort_title,
+            StandardTagKey::SortArtist => &mut self.sort_artist,
+            StandardTagKey::SortAlbum => &mut self.sort_album,
+            StandardTagKey::SortAlbumArtist => &mut self.sort_album_artist,
+            StandardTagKey::SortComposer => &mut self.sort_composer,
+            StandardTagKey::TrackNumber => {
+                Self::number(value, &mut self.track_number, &mut self.track_total);
+                return;
+            }
+            StandardTagKey::TrackTotal => {
+                Self::number(value, &mut self.track_total, &mut None);
+                return;
+            }
+            StandardTagKey::DiscNumber => {
+                Self::number(value, &mut self.disc_number, &mut self.disc_total);
+                return;
+            }
+            StandardTagKey::DiscTotal => {
+                Self::number(value, &mut self.disc_total, &mut None);
+                return;
+            }
+            StandardTagKey::Bpm => {
+                if self.bpm.is_none() {
+                    self.bpm = Self::text(value).and_then(|bpm| bpm.parse().ok());
+                }
+                return;
+            }
+            _ => return,
+        };
+        if field.is_none() {
+            *field = Self::text(value);
+        }
+    }
+
+    /// Text of values that are not binary, `None` if empty
+    fn text(value: &Value) -> Option<String> {
+        match value {
+            Value::Binary(_) | Value::Flag => None,
+            Value::String(v) => Some(v.trim().to_string()),
+            v => Some(v.to_string()),
+        }
+        .filter(|v| !v.is_empty())
+    }
+
+    /// Numbers like `3` or `3/12` with the total after the slash, as in ID3
+    fn number(value: &Value, number: &mut Option<u32>, total: &mut Option<u32>) {
+        let Some(text) = Self::text(value) else {
+            return;
+        };
+        let (n, t) = match text.split_once('/') {
+            Some((n, t)) => (n, Some(t)),
+            None => (text.as_str(), None),
+        };
+        if number.is_none() {
+            *number = n.trim().parse().ok();
+        }
+        if total.is_none() {
+            *total = t.and_then(|t| t.trim().parse().ok());
+        }
+    }
+
+    /// Forget the title and artist, which change with every song of a radio stream
+    pub(super) fn clear_song(&mut self) {
+        self.title = None;
+        self.artist = None;
+    }
+
+    /// Take the values missing here from `other`
+    pub(super) fn fill(&mut self, other: &Tags) {
+        let fill = |field: &mut Option<String>, other: &Option<String>| {
+            if field.is_none() {
+                field.clone_from(other);
+            }
+        };
+        fill(&mut self.title, &other.title);
+        fill(&mut self.artist, &other.artist);
+        fill(&mut self.album, &other.album);
+        fill(&mut self.album_artist, &other.album_artist);
+        fill(&mut self.date, &other.date);
+        fill(&mut self.genre, &other.genre);
+        fill(&mut self.composer, &other.composer);
+        fill(&mut self.comment, &other.comment);
+        fill(&mut self.label, &other.label);
+        fill(&mut self.isrc, &other.isrc);
+        fill(
+            &mut self.musicbrainz_recording_id,
+            &other.musicbrainz_recording_id,
+        );
+        fill(&mut self.musicbrainz_track_id, &other.musicbrainz_track_id);
+        fill(&mut self.musicbrainz_album_id, &other.musicbrainz_album_id);
+        fill(
+            &mut self.musicbrainz_artist_id,
+            &other.musicbrainz_artist_id,
+        );
+        fill(
+            &mut self.musicbrainz_album_artist_id,
+            &other.musicbrainz_album_artist_id,
+        );
+        fill(
+            &mut self.musicbrainz_release_group_id,
+            &other.musicbrainz_release_group_id,
+        );
+        fill(&mut self.sort_title, &other.sort_title);
+        fill(&mut self.sort_artist, &other.sort_artist);
+        fill(&mut self.sort_album, &other.sort_album);
+        fill(&mut self.sort_album_artist, &other.sort_album_artist);
+        fill(&mut self.sort_composer, &other.sort_composer);
+        self.track_number = self.track_number.or(other.track_number);
+        self.track_total = self.track_total.or(other.track_total);
+        self.disc_number = self.disc_number.or(other.disc_number);
+        self.disc_total = self.disc_total.or(other.disc_total);
+        self.bpm = self.bpm.or(other.bpm);
+        for (key, values) in &other.raw {
+            self.raw
+                .entry(key.clone())
+                .or_insert_with(|| values.clone());
+        }
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn artist(&self) -> Option<&str> {
+        self.artist.as_deref()
+    }
+
+    pub fn album(&self) -> Option<&str> {
+        self.album.as_deref()
+    }
+
+    pub fn album_artist(&self) -> Option<&str> {
+        self.album_artist.as_deref()
+    }
+
+    pub fn track_number(&self) -> Option<u32> {
+        self.track_number
+    }
+
+    /// Number of tracks on the disc or the album
+    pub fn track_total(&self) -> Option<u32> {
+        self.track_total
+    }
+
+    pub fn disc_number(&self) -> Option<u32> {
+        self.disc_number
+    }
+
+    pub fn disc_total(&self) -> Option<u32> {
+        self.disc_total
+    }
+
+    /// As written in the tag, usually a year or `YYYY-MM-DD`
+    pub fn date(&self) -> Option<&str> {
+        self.date.as_deref()
+    }
+
+    pub fn genre(&self) -> Option<&str> {
+        self.genre.as_deref()
+    }
+
+    pub fn composer(&self) -> Option<&str> {
+        self.composer.as_deref()
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    /// Record label
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// International Standard Recording Code
+    pub fn isrc(&self) -> Option<&str> {
+        self.isrc.as_deref()
+    }
+
+    /// Beats per minute
+    pub fn bpm(&self) -> Option<f64> {
+        self.bpm
+    }
+
+    pub fn musicbrainz_recording_id(&self) -> Option<&str> {
+        self.musicbrainz_recording_id.as_deref()
+    }
+
+    pub fn musicbrainz_track_id(&self) -> Option<&str> {
+        self.musicbrainz_track_id.as_deref()
+    }
+
+    pub fn musicbrainz_album_id(&self) -> Option<&str> {
+        self.musicbrainz_album_id.as_deref()
+    }
+
+    pub fn musicbrainz_artist_id(&self) -> Option<&str> {
+        self.musicbrainz_artist_id.as_deref()
+    }
+
+    pub fn musicbrainz_album_artist_id(&self) -> Option<&str> {
+        self.musicbrainz_album_artist_id.as_deref()
+    }
+
+    pub fn musicbrainz_release_group_id(&self) -> Option<&str> {
+        self.musicbrainz_release_group_id.as_deref()
+    }
+
+    /// Title to sort by, e.g. without a leading article
+    pub fn sort_title(&self) -> Option<&str> {
+        self.sort_title.as_deref()
+    }
+
+    pub fn sort_artist(&self) -> Option<&str> {
+        self.sort_artist.as_deref()
+    }
+
+    pub fn sort_album(&self) -> Option<&str> {
+        self.sort_album.as_deref()
+    }
+
+    pub fn sort_album_artist(&self) -> Option<&str> {
+        self.sort_album_artist.as_deref()
+    }
+
+    pub fn sort_composer(&self) -> Option<&str> {
+        self.sort_composer.as_deref()
+    }
+
+    /// Every tag that is not binary by the key of its format, like `TPE1` in ID3 or `ARTIST`
+    /// in Vorbis comments, with all values of tags that appear more than once
+    pub fn raw(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.raw
+    }
+
+    /// First value of the tag with `key`, ignoring the case of the key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.raw
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .and_then(|(_, values)| values.first())
+            .map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(text: &str) -> (Option<u32>, Option<u32>) {
+        let (mut number, mut total) = (None, None);
+        Tags::number(&Value::from(text), &mut number, &mut total);
+        (number, total)
+    }
+
+    fn tag(std_key: StandardTagKey, key: &str, value: &str) -> Tag {
+        Tag::new(Some(std_key), key, Value::from(value))
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(number("3/12"), (Some(3), Some(12)));
+        assert_eq!(number(" 3 "), (Some(3), None));
+        assert_eq!(number(" 3 / 12 "), (Some(3), Some(12)));
+        assert_eq!(number("three"), (None, None));
+        assert_eq!(number("3/twelve"), (Some(3), None));
+        assert_eq!(number(""), (None, None));
+        // the total from the track number does not replace one that is known
+        let mut tags = Tags::default();
+        tags.add(&tag(StandardTagKey::TrackTotal, "TRACKTOTAL", "10"));
+        tags.add(&tag(StandardTagKey::TrackNumber, "TRACKNUMBER", "3/12"));
+        assert_eq!(tags.track_number(), Some(3));
+        assert_eq!(tags.track_total(), Some(10));
+    }
+
+    #[test]
+    fn repeated_tag() {
+        let mut tags = Tags::default();
+        tags.add(&tag(StandardTagKey::Artist, "ARTIST", "First"));
+        tags.add(&tag(StandardTagKey::Artist, "ARTIST", " Second "));
+        tags.add(&Tag::new(None, "MOOD", Value::from("calm")));
+        assert_eq!(tags.artist(), Some("First"));
+        assert_eq!(tags.raw()["ARTIST"], ["First", "Second"]);
+        assert_eq!(tags.get("ARTIST"), Some("First"));
+        assert_eq!(tags.get("MOOD"), Some("calm"));
+    }
+}
@@ -1,137 +1,712 @@
-use std::{rc::Rc, time::Duration};
-
-use symphonia::core::{
-    meta::{MetadataRevision, StandardTagKey, StandardVisualKey, Value, Visual},
-    probe::ProbeResult,
-};
-use tracing::debug;
-
-use crate::decoder::DecodedTrack;
-
-pub struct Track {
-    pub(super) decoded: DecodedTrack,
-    pub(super) details: TrackDetails,
-}
-
-impl Track {
-    pub fn details(&self) -> &TrackDetails {
-        &self.details
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct TrackDetails {
-    duration: Option<Duration>,
-    title: Option<String>,
-    artist: Option<String>,
-    cover: Option<Cover>,
-}
-
-impl TrackDetails {
-    pub(super) fn new(probe_result: &mut ProbeResult) -> Self {
-        // Give priority to metadata in container
-        let metadata = probe_result.format.metadata();
-        let mut new = match metadata.current() {
-            Some(metadata) => Self::read_metadata(metadata),
-            None => Self {
-                duration: None,
-                title: None,
-                artist: None,
-                cover: None,
-            },
-        };
-        if let Some(metadata) = probe_result.metadata.get() {
-            if let Some(metadata) = metadata.current() {
-                let new_2 = Self::read_metadata(metadata);
-                if new.cover.is_none() {
-                    new.cover = new_2.cover;
-                }
-                if new.title.is_none() {
-                    new.title = new_2.title;
-                }
-            }
-        }
-        new.duration = probe_result
-            .format
-            .default_track()
-            .map(|track| {
-                if let Some(time_base) = track.codec_params.time_base {
-                    if let Some(n_frames) = track.codec_params.n_frames {
-                        return Some(time_base.calc_time(n_frames).into());
-                    }
-                }
-                None
-            })
-            .flatten();
-
-        new
-    }
-
-    fn read_metadata(metadata: &MetadataRevision) -> Self {
-        let mut new = Self {
-            duration: None,
-            title: None,
-            artist: None,
-            cover: None,
-        };
-        new.cover = metadata
-            .visuals()
-            .iter()
-            .find(|&visual| match visual.usage {
-                Some(StandardVisualKey::FrontCover) => true,
-                _ => {
-                    debug!(
-                        "visual: {{ usage: {:?} media_type: {} }}",
-                        visual.usage, visual.media_type
-                    );
-                    false
-                }
-            })
-            .map(|v| Cover {
-                data: v.data.clone(),
-            });
-        metadata.tags().iter().for_each(|tag| match tag.std_key {
-            Some(StandardTagKey::TrackTitle) => {
-                new.title = match &tag.value {
-                    Value::String(v) => Some(v.clone()),
-                    _ => None,
-                }
-            }
-            Some(StandardTagKey::Artist) => {
-                new.artist = match &tag.value {
-                    Value::String(v) => Some(v.clone()),
-                    _ => None,
-                };
-            }
-            _ => debug!("{} {:?} {}", tag.key, tag.std_key, tag.value),
-        });
-        new
-    }
-
-    pub fn duration(&self) -> Option<&Duration> {
-        self.duration.as_ref()
-    }
-
-    pub fn title(&self) -> Option<&str> {
-        self.title.as_deref()
-    }
-
-    pub fn artist(&self) -> Option<&str> {
-        self.artist.as_deref()
-    }
-
-    pub fn cover(&self) -> Option<&Cover> {
-        self.cover.as_ref()
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct Cover {
-    data: Box<[u8]>,
-}
-
-impl Cover {
-    pub fn data(&self) -> &Box<[u8]> {
-        &self.data
-    }
-}
+use std::{
+    fmt::Display,
+    io::{Cursor, Read, Seek},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use symphonia::core::{
+    audio::Channels,
+    codecs::{
+        CodecParameters, CodecType, CODEC_TYPE_ALAC, CODEC_TYPE_FLAC, CODEC_TYPE_MONKEYS_AUDIO,
+        CODEC_TYPE_PCM_ALAW, CODEC_TYPE_PCM_MULAW, CODEC_TYPE_TTA, CODEC_TYPE_WAVPACK,
+    },
+    meta::{MetadataRevision, StandardTagKey, StandardVisualKey, Value},
+};
+use tracing::debug;
+
+use crate::{
+    decoder::{self, DecodedTrack, Probed},
+    http::{self, HttpSource},
+    source::{ReaderSource, StreamSource},
+    tags::Tags,
+    AudioPlayerError,
+};
+
+pub struct Track {
+    pub(super) decoded: DecodedTrack,
+    pub(super) source: TrackSource,
+    pub(super) details: TrackDetails,
+    pub(super) stream_info: StreamInfo,
+}
+
+impl Track {
+    /// Open a file without a player, e.g. to measure it with [crate::Loudness::scan]. Paths
+    /// starting with `http://` or `https://` are opened with [Track::open_url]
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, AudioPlayerError> {
+        if let Some(url) = http::url(path.as_ref()) {
+            return Self::open_url(url);
+        }
+        Ok(decoder::decode(&path)?)
+    }
+
+    /// Open a track from a `http://` or `https://` URL, like a radio stream or a file on a media
+    /// server. It is downloaded ahead while playing, see [crate::AudioPlayerController::buffering].
+    /// Seeking needs the server to support range requests
+    pub fn open_url(url: &str) -> Result<Self, AudioPlayerError> {
+        let (source, hint) = HttpSource::open(url).map_err(decoder::DecoderError::from)?;
+        let http = source.buffer();
+        let station = source.station().clone();
+        let mut track = decoder::decode_source(
+            Box::new(source),
+            hint.as_deref(),
+            TrackSource::Url(url.to_string()),
+        )?;
+        track.decoded.set_http(http);
+        track.details.fill(TrackDetails::read_metadata(&station));
+        Ok(track)
+    }
+
+    /// Open a track from any reader, like a file in an archive. `hint` is the file extension
+    /// like `mp3` or the MIME type like `audio/mpeg` of the format, if known
+    pub fn open_reader<R: Read + Seek + Send + 'static>(
+        reader: R,
+        hint: Option<&str>,
+    ) -> Result<Self, AudioPlayerError> {
+        let source = ReaderSource::new(reader).map_err(decoder::DecoderError::from)?;
+        Ok(decoder::decode_source(
+            Box::new(source),
+            hint,
+            TrackSource::Reader,
+        )?)
+    }
+
+    /// Open a track from a reader that can only be read front to back, like a pipe. The track
+    /// can't seek and only plays once
+    pub fn open_stream<R: Read + Send + 'static>(
+        reader: R,
+        hint: Option<&str>,
+    ) -> Result<Self, AudioPlayerError> {
+        Ok(decoder::decode_source(
+            Box::new(StreamSource::new(reader)),
+            hint,
+            TrackSource::Reader,
+        )?)
+    }
+
+    /// Open a track from the encoded bytes of a file, like `Vec<u8>` or `&'static [u8]`
+    pub fn open_bytes<B: AsRef<[u8]> + Send + Sync + 'static>(
+        bytes: B,
+        hint: Option<&str>,
+    ) -> Result<Self, AudioPlayerError> {
+        Ok(decoder::decode_source(
+            Box::new(Cursor::new(bytes)),
+            hint,
+            TrackSource::Bytes,
+        )?)
+    }
+
+    pub fn source(&self) -> &TrackSource {
+        &self.source
+    }
+
+    pub fn details(&self) -> &TrackDetails {
+        &self.details
+    }
+
+    pub fn stream_info(&self) -> &StreamInfo {
+        &self.stream_info
+    }
+
+    /// Replace the ReplayGain values of the tags, e.g. with ones from [crate::Loudness::scan]
+    pub fn set_replay_gain(&mut self, replay_gain: ReplayGain) {
+        self.details.replay_gain = replay_gain;
+    }
+}
+
+/// Where a track was opened from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TrackSource {
+    File(PathBuf),
+    /// [Track::open_url]
+    Url(String),
+    /// [Track::open_reader] or [Track::open_stream]
+    Reader,
+    /// [Track::open_bytes]
+    Bytes,
+}
+
+impl Display for TrackSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrackSource::File(path) => write!(f, "{}", path.to_string_lossy()),
+            TrackSource::Url(url) => write!(f, "{}", url),
+            TrackSource::Reader => write!(f, "reader"),
+            TrackSource::Bytes => write!(f, "memory"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrackDetails {
+    duration: Option<Duration>,
+    tags: Tags,
+    cover: Option<Cover>,
+    replay_gain: ReplayGain,
+}
+
+impl TrackDetails {
+    pub(super) fn new(probed: &mut Probed) -> Self {
+        // Give priority to metadata in container
+        let metadata = probed.format.metadata();
+        let mut new = match metadata.current() {
+            Some(metadata) => Self::read_metadata(metadata),
+            None => Self::read_metadata(&MetadataRevision::default()),
+        };
+        if let Some(metadata) = probed.metadata.as_mut() {
+            if let Some(metadata) = metadata.metadata().current() {
+                new.fill(Self::read_metadata(metadata));
+            }
+        }
+        new.duration = probed
+            .format
+            .default_track()
+            .map(|track| {
+                if let Some(time_base) = track.codec_params.time_base {
+                    if let Some(n_frames) = track.codec_params.n_frames {
+                        return Some(time_base.calc_time(n_frames).into());
+                    }
+                }
+                None
+            })
+            .flatten();
+
+        new
+    }
+
+    /// Apply a metadata revision found while playing, its values replace those known so far.
+    /// A new title or artist is a new song, so the other one is not kept from the last song
+    pub(super) fn update(&mut self, metadata: &MetadataRevision) {
+        let mut old = std::mem::replace(self, Self::read_metadata(metadata));
+        if self.tags.title().is_some() || self.tags.artist().is_some() {
+            old.tags.clear_song();
+        }
+        self.fill(old);
+    }
+
+    /// Take the values missing here from `other`
+    fn fill(&mut self, other: TrackDetails) {
+        self.duration = self.duration.or(other.duration);
+        self.tags.fill(&other.tags);
+        if self.cover.is_none() {
+            self.cover = other.cover;
+        }
+        self.replay_gain.fill(&other.replay_gain);
+    }
+
+    fn read_metadata(metadata: &MetadataRevision) -> Self {
+        let mut new = Self {
+            duration: None,
+            tags: Tags::default(),
+            cover: None,
+            replay_gain: ReplayGain::default(),
+        };
+        new.cover = metadata
+            .visuals()
+            .iter()
+            .find(|&visual| match visual.usage {
+                Some(StandardVisualKey::FrontCover) => true,
+                _ => {
+                    debug!(
+                        "visual: {{ usage: {:?} media_type: {} }}",
+                        visual.usage, visual.media_type
+                    );
+                    false
+                }
+            })
+            .map(|v| Cover {
+                data: v.data.clone(),
+            });
+        metadata.tags().iter().for_each(|tag| {
+            new.tags.add(tag);
+            match tag.std_key {
+                Some(StandardTagKey::ReplayGainTrackGain) => {
+                    new.replay_gain.track_gain = ReplayGain::parse(&tag.value);
+                }
+                Some(StandardTagKey::ReplayGainTrackPeak) => {
+                    new.replay_gain.track_peak = ReplayGain::parse(&tag.value);
+                }
+                Some(StandardTagKey::ReplayGainAlbumGain) => {
+                    new.replay_gain.album_gain = ReplayGain::parse(&tag.value);
+                }
+                Some(StandardTagKey::ReplayGainAlbumPeak) => {
+                    new.replay_gain.album_peak = ReplayGain::parse(&tag.value);
+                }
+                // Opus uses its own tags, which symphonia has no standard key for
+                None if tag.key.eq_ignore_ascii_case("R128_TRACK_GAIN") => {
+                    new.replay_gain.track_gain = ReplayGain::parse_r128(&tag.value);
+                }
+                None if tag.key.eq_ignore_ascii_case("R128_ALBUM_GAIN") => {
+                    new.replay_gain.album_gain = ReplayGain::parse_r128(&tag.value);
+                }
+                _ => debug!("{} {:?} {}", tag.key, tag.std_key, tag.value),
+            }
+        });
+        new
+    }
+
+    pub(super) fn set_duration(&mut self, duration: Duration) {
+        self.duration = Some(duration);
+    }
+
+    pub fn duration(&self) -> Option<&Duration> {
+        self.duration.as_ref()
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.tags.title()
+    }
+
+    pub fn artist(&self) -> Option<&str> {
+        self.tags.artist()
+    }
+
+    pub fn album(&self) -> Option<&str> {
+        self.tags.album()
+    }
+
+    /// All tags of the track, of which [TrackDetails::title], [TrackDetails::artist] and
+    /// [TrackDetails::album] are shortcuts
+    pub fn tags(&self) -> &Tags {
+        &self.tags
+    }
+
+    pub fn cover(&self) -> Option<&Cover> {
+        self.cover.as_ref()
+    }
+
+    pub fn replay_gain(&self) -> &ReplayGain {
+        &self.replay_gain
+    }
+}
+
+/// Loudness normalization values from the ReplayGain tags of a track, or the R128 tags of Opus
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReplayGain {
+    track_gain: Option<f64>,
+    track_peak: Option<f64>,
+    album_gain: Option<f64>,
+    album_peak: Option<f64>,
+}
+
+impl ReplayGain {
+    /// R128 gains are relative to -23 LUFS, ReplayGain 2.0 gains to -18 LUFS
+    const R128_OFFSET: f64 = 5.0;
+
+    pub fn new(
+        track_gain: Option<f64>,
+        track_peak: Option<f64>,
+        album_gain: Option<f64>,
+        album_peak: Option<f64>,
+    ) -> Self {
+        Self {
+            track_gain,
+            track_peak,
+            album_gain,
+            album_peak,
+        }
+    }
+
+    /// Gain in dB that brings the track to the ReplayGain reference loudness
+    pub fn track_gain(&self) -> Option<f64> {
+        self.track_gain
+    }
+
+    /// Largest sample of the track, `1.0` being full scale
+    pub fn track_peak(&self) -> Option<f64> {
+        self.track_peak
+    }
+
+    /// Gain in dB that brings the album of the track to the ReplayGain reference loudness
+    pub fn album_gain(&self) -> Option<f64> {
+        self.album_gain
+    }
+
+    /// Largest sample of the album, `1.0` being full scale
+    pub fn album_peak(&self) -> Option<f64> {
+        self.album_peak
+    }
+
+    /// Take the values missing here from `other`
+    fn fill(&mut self, other: &ReplayGain) {
+        self.track_gain = self.track_gain.or(other.track_gain);
+        self.track_peak = self.track_peak.or(other.track_peak);
+        self.album_gain = self.album_gain.or(other.album_gain);
+        self.album_peak = self.album_peak.or(other.album_peak);
+    }
+
+    /// Values like `-6.48 dB` or `0.988553`
+    fn parse(value: &Value) -> Option<f64> {
+        match value {
+            Value::String(v) => {
+                let v = v.trim();
+                let v = match v.len().checked_sub(2) {
+                    Some(unit) if v.get(unit..).is_some_and(|u| u.eq_ignore_ascii_case("dB")) => {
+                        &v[..unit]
+                    }
+                    _ => v,
+                };
+                v.trim().parse().ok()
+            }
+            Value::Float(v) => Some(*v),
+            Value::SignedInt(v) => Some(*v as f64),
+            Value::UnsignedInt(v) => Some(*v as f64),
+            _ => None,
+        }
+        .filter(|v: &f64| v.is_finite())
+    }
+
+    /// Gains in 1/256 dB, as in `-1280` for -5 dB
+    fn parse_r128(value: &Value) -> Option<f64> {
+        Self::parse(value).map(|gain| gain / 256.0 + Self::R128_OFFSET)
+    }
+}
+
+/// Technical details of the audio stream of a track
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamInfo {
+    codec: Option<&'static str>,
+    codec_name: Option<&'static str>,
+    container: Option<&'static str>,
+    sample_rate: Option<u32>,
+    bits_per_sample: Option<u32>,
+    channels: Option<Channels>,
+    average_bitrate: Option<u32>,
+    instant_bitrate: Option<u32>,
+    file_size: Option<u64>,
+    lossless: bool,
+}
+
+impl StreamInfo {
+    /// Codecs that decode to exactly what was encoded, besides PCM
+    const LOSSLESS: [CodecType; 5] = [
+        CODEC_TYPE_FLAC,
+        CODEC_TYPE_ALAC,
+        CODEC_TYPE_WAVPACK,
+        CODEC_TYPE_MONKEYS_AUDIO,
+        CODEC_TYPE_TTA,
+    ];
+
+    /// `audio_size` is the part of `file_size` the average bitrate is calculated from
+    pub(super) fn new(
+        codec_params: &CodecParameters,
+        container: Option<&'static str>,
+        file_size: Option<u64>,
+        audio_size: Option<u64>,
+        duration: Option<Duration>,
+    ) -> Self {
+        let codec = symphonia::default::get_codecs().get_codec(codec_params.codec);
+        // A-law and mu-law are PCM too, but compressed
+        let pcm = codec.is_some_and(|codec| codec.short_name.starts_with("pcm_"))
+            && ![CODEC_TYPE_PCM_ALAW, CODEC_TYPE_PCM_MULAW].contains(&codec_params.codec);
+        let average_bitrate = match (audio_size, duration) {
+            (Some(size), Some(duration)) if !duration.is_zero() => {
+                Some((size as f64 * 8.0 / duration.as_secs_f64()) as u32)
+            }
+            _ => None,
+        };
+        Self {
+            codec: codec.map(|codec| codec.short_name),
+            codec_name: codec.map(|codec| codec.long_name),
+            container,
+            sample_rate: codec_params.sample_rate,
+            bits_per_sample: codec_params.bits_per_sample,
+            channels: codec_params.channels.or(codec_params
+                .channel_layout
+                .map(|layout| layout.into_channels())),
+            average_bitrate,
+            instant_bitrate: None,
+            file_size,
+            lossless: pcm || Self::LOSSLESS.contains(&codec_params.codec),
+        }
+    }
+
+    pub(super) fn set_instant_bitrate(&mut self, bitrate: Option<u32>) {
+        self.instant_bitrate = bitrate;
+    }
+
+    /// Short name of the codec like `flac` or `pcm_s16le`
+    pub fn codec(&self) -> Option<&str> {
+        self.codec
+    }
+
+    /// Descriptive name of the codec like `Free Lossless Audio Codec`
+    pub fn codec_name(&self) -> Option<&str> {
+        self.codec_name
+    }
+
+    /// Name of the container format like `Ogg` or `MP4`
+    pub fn container(&self) -> Option<&str> {
+        self.container
+    }
+
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.sample_rate
+    }
+
+    /// Bits per sample before encoding, only known for lossless codecs
+    pub fn bits_per_sample(&self) -> Option<u32> {
+        self.bits_per_sample
+    }
+
+    pub fn channels(&self) -> Option<usize> {
+        self.channels.map(|channels| channels.count())
+    }
+
+    /// `mono`, `stereo`, or the number of channels with the low frequency channel like `5.1`
+    pub fn channel_layout(&self) -> Option<String> {
+        let channels = self.channels?;
+        let count = channels.count();
+        Some(match (count, channels.contains(Channels::LFE1)) {
+            (1, _) => "mono".to_string(),
+            (2, false) => "stereo".to_string(),
+            (count, true) => format!("{}.1", count - 1),
+            (count, false) => format!("{} channels", count),
+        })
+    }
+
+    /// Bits per second over the whole track, from the size of the file and the duration
+    pub fn average_bitrate(&self) -> Option<u32> {
+        self.average_bitrate
+    }
+
+    /// Bits per second of the last second or so that was decoded, only known while playing
+    pub fn instant_bitrate(&self) -> Option<u32> {
+        self.instant_bitrate
+    }
+
+    /// Size in bytes of the file the track was opened from
+    pub fn file_size(&self) -> Option<u64> {
+        self.file_size
+    }
+
+    /// The codec decodes to exactly the samples that were encoded
+    pub fn lossless(&self) -> bool {
+        self.lossless
+    }
+}
+
+/// Like `flac in FLAC, 44.1 kHz, 16 bit, stereo, 1411 kbps, lossless`
+impl Display for StreamInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let format = match (self.codec, self.container) {
+            (Some(codec), Some(container)) => Some(format!("{} in {}", codec, container)),
+            (codec, container) => codec.or(container).map(str::to_string),
+        };
+        let bitrate = self.instant_bitrate.or(self.average_bitrate);
+        let parts = [
+            format,
+            self.sample_rate
+                .map(|rate| format!("{} kHz", rate as f64 / 1000.0)),
+            self.bits_per_sample.map(|bits| format!("{} bit", bits)),
+            self.channel_layout(),
+            bitrate.map(|bitrate| format!("{} kbps", bitrate / 1000)),
+            self.lossless.then(|| "lossless".to_string()),
+        ];
+        write!(
+            f,
+            "{}",
+            parts.into_iter().flatten().collect::<Vec<_>>().join(", ")
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cover {
+    data: Box<[u8]>,
+}
+
+impl Cover {
+    pub fn data(&self) -> &Box<[u8]> {
+        &self.data
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use hound::{SampleFormat, WavSpec, WavWriter};
+    use symphonia::core::codecs::{CODEC_TYPE_MP3, CODEC_TYPE_PCM_S16LE, CODEC_TYPE_VORBIS};
+
+    use super::*;
+
+    /// A WAV file of 32 bit float `samples`, interleaved when there are several `channels`
+    pub(crate) fn wav(samples: &[f32], channels: u16, sample_rate: u32) -> Vec<u8> {
+        let spec = WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut bytes = Cursor::new(vec![]);
+        let mut writer = WavWriter::new(&mut bytes, spec).unwrap();
+        for &sample in samples {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        bytes.into_inner()
+    }
+
+    /// A short silent track
+    pub(crate) fn silence() -> Track {
+        Track::open_bytes(wav(&[0.0; 16], 1, 8000), Some("wav")).unwrap()
+    }
+
+    #[test]
+    fn replay_gain() {
+        let parse = |value: &str| ReplayGain::parse(&Value::from(value));
+        assert_eq!(parse("-6.48 dB"), Some(-6.48));
+        assert_eq!(parse(" +2.5DB "), Some(2.5));
+        assert_eq!(parse("0.988553"), Some(0.988553));
+        assert_eq!(parse("loud"), None);
+        assert_eq!(parse("inf"), None);
+        assert_eq!(ReplayGain::parse(&Value::Float(-1.5)), Some(-1.5));
+    }
+
+    #[test]
+    fn replay_gain_r128() {
+        // -5 dB relative to -23 LUFS is 0 dB relative to -18 LUFS
+        let parse = |value: &Value| ReplayGain::parse_r128(value);
+        assert_eq!(parse(&Value::from("-1280")), Some(0.0));
+        assert_eq!(parse(&Value::SignedInt(-1280)), Some(0.0));
+        assert_eq!(parse(&Value::from("512")), Some(7.0));
+        assert_eq!(parse(&Value::from("")), None);
+    }
+
+    /// Two channels of a second at 8 kHz, every sample is a frame counter
+    fn counter() -> Vec<u8> {
+        let samples = (0..16000)
+            .map(|i| (i / 2) as f32 / 8000.0)
+            .collect::<Vec<_>>();
+        wav(&samples, 2, 8000)
+    }
+
+    fn frames(track: &mut Track) -> usize {
+        let mut frames = 0;
+        loop {
+            match track.decoded.next() {
+                Ok(buffer) => frames += buffer.frames(),
+                Err(err) if err.end_of_stream() => return frames,
+                Err(err) => panic!("{}", err),
+            }
+        }
+    }
+
+    fn check(track: &mut Track, source: TrackSource, file_size: Option<u64>) {
+        assert_eq!(track.source(), &source);
+        assert_eq!(track.details().duration(), Some(&Duration::from_secs(1)));
+        let info = track.stream_info();
+        assert_eq!(info.container(), Some("WAVE"));
+        assert_eq!(info.codec(), Some("pcm_f32le"));
+        assert_eq!(info.sample_rate(), Some(8000));
+        assert_eq!(info.channels(), Some(2));
+        assert_eq!(info.file_size(), file_size);
+        assert!(info.lossless());
+        assert_eq!(frames(track), 8000);
+    }
+
+    #[test]
+    fn open_bytes() {
+        let bytes = counter();
+        let size = bytes.len() as u64;
+        let mut track = Track::open_bytes(bytes, Some("wav")).unwrap();
+        check(&mut track, TrackSource::Bytes, Some(size));
+        // without tags in front of the container the whole file counts
+        let bitrate = track.stream_info().average_bitrate();
+        assert_eq!(bitrate, Some(size as u32 * 8));
+        assert!(Track::open_bytes(vec![0; 100], None).is_err());
+    }
+
+    #[test]
+    fn open_reader() {
+        let bytes = counter();
+        let size = bytes.len() as u64;
+        let mut track = Track::open_reader(Cursor::new(bytes), None).unwrap();
+        check(&mut track, TrackSource::Reader, Some(size));
+        track.decoded.seek(Duration::from_millis(500)).unwrap();
+        // from the start of the packet the position is in
+        let frames = frames(&mut track);
+        assert!((4000..8000).contains(&frames), "{}", frames);
+    }
+
+    #[test]
+    fn open_stream() {
+        let bytes = counter();
+        let mut track = Track::open_stream(Cursor::new(bytes), Some("audio/wav")).unwrap();
+        check(&mut track, TrackSource::Reader, None);
+        assert!(track.decoded.seek(Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn hint() {
+        // the hint only chooses between formats that start the same, a wrong one is ignored
+        for hint in [
+            None,
+            Some("wav"),
+            Some("WAVE"),
+            Some("audio/x-wav"),
+            Some("mp3"),
+        ] {
+            let track = Track::open_bytes(counter(), hint).unwrap();
+            assert_eq!(track.stream_info().container(), Some("WAVE"), "{:?}", hint);
+            let track = Track::open_stream(Cursor::new(counter()), hint).unwrap();
+            assert_eq!(track.stream_info().container(), Some("WAVE"), "{:?}", hint);
+        }
+    }
+
+    #[test]
+    fn stream_info_display() {
+        let flac = StreamInfo {
+            codec: Some("flac"),
+            container: Some("FLAC"),
+            sample_rate: Some(44100),
+            bits_per_sample: Some(16),
+            channels: Some(Channels::FRONT_LEFT | Channels::FRONT_RIGHT),
+            average_bitrate: Some(1411200),
+            lossless: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            flac.to_string(),
+            "flac in FLAC, 44.1 kHz, 16 bit, stereo, 1411 kbps, lossless"
+        );
+        // the bitrate while playing is preferred
+        let surround = Channels::FRONT_LEFT
+            | Channels::FRONT_RIGHT
+            | Channels::FRONT_CENTRE
+            | Channels::LFE1
+            | Channels::REAR_LEFT
+            | Channels::REAR_RIGHT;
+        let mp3 = StreamInfo {
+            codec: Some("mp3"),
+            sample_rate: Some(48000),
+            channels: Some(surround),
+            average_bitrate: Some(256000),
+            instant_bitrate: Some(320000),
+            ..Default::default()
+        };
+        assert_eq!(mp3.to_string(), "mp3, 48 kHz, 5.1, 320 kbps");
+        let mono = StreamInfo {
+            container: Some("Ogg"),
+            channels: Some(Channels::FRONT_LEFT),
+            ..Default::default()
+        };
+        assert_eq!(mono.to_string(), "Ogg, mono");
+        assert_eq!(StreamInfo::default().to_string(), "");
+    }
+
+    #[test]
+    fn stream_info_lossless() {
+        let lossless = |codec| {
+            let mut params = CodecParameters::new();
+            params.for_codec(codec);
+            StreamInfo::new(&params, None, None, None, None).lossless()
+        };
+        assert!(lossless(CODEC_TYPE_FLAC));
+        assert!(lossless(CODEC_TYPE_ALAC));
+        assert!(lossless(CODEC_TYPE_PCM_S16LE));
+        assert!(!lossless(CODEC_TYPE_PCM_ALAW));
+        assert!(!lossless(CODEC_TYPE_PCM_MULAW));
+        assert!(!lossless(CODEC_TYPE_MP3));
+        assert!(!lossless(CODEC_TYPE_VORBIS));
+    }
+}
@@ -0,0 +1,420 @@
+use std::time::Duration;
+
+use crate::{buffer::SampleBuf, processor::AudioProcessor, track::ReplayGain, FadeOptions};
+
+/// Software volume settings, changed through the controller
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Volume {
+    /// Linear amplitude factor in `0.0..=1.0`
+    pub(super) volume: f32,
+    pub(super) muted: bool,
+    /// `-1.0` is fully left, `1.0` fully right
+    pub(super) balance: f32,
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            muted: false,
+            balance: 0.0,
+        }
+    }
+}
+
+impl Volume {
+    /// Gain of `channel`, balance only affects the front left and right channels
+    fn gain(&self, channel: usize, channels: usize) -> f64 {
+        if self.muted {
+            return 0.0;
+        }
+        let balance = match channel {
+            0 if channels > 1 => (1.0 - self.balance).min(1.0),
+            1 => (1.0 + self.balance).min(1.0),
+            _ => 1.0,
+        };
+        (self.volume * balance) as f64
+    }
+}
+
+/// Which ReplayGain value tracks are played at
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NormalizationMode {
+    #[default]
+    Off,
+    /// Play every track at the same loudness
+    Track,
+    /// Play every album at the same loudness, keeping the differences between its tracks
+    Album,
+    /// Album gain while the queue plays in order, track gain while it is shuffled
+    Auto,
+}
+
+/// Loudness normalization settings, changed through the controller
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Normalization {
+    pub(super) mode: NormalizationMode,
+    /// Added to the ReplayGain of every track, in dB
+    pub(super) preamp: f64,
+    /// Lower the gain of tracks whose peak would clip
+    pub(super) prevent_clipping: bool,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Self {
+            mode: NormalizationMode::default(),
+            preamp: 0.0,
+            prevent_clipping: true,
+        }
+    }
+}
+
+impl Normalization {
+    /// Linear factor for a track, tracks without ReplayGain are left as they are. The gain of
+    /// the album is used in place of a missing track gain and the other way around
+    pub(super) fn gain(&self, replay_gain: &ReplayGain, shuffled: bool) -> f64 {
+        let use_album = match self.mode {
+            NormalizationMode::Off => return 1.0,
+            NormalizationMode::Track => false,
+            NormalizationMode::Album => true,
+            NormalizationMode::Auto => !shuffled,
+        };
+        let track = (replay_gain.track_gain(), replay_gain.track_peak());
+        let album = (replay_gain.album_gain(), replay_gain.album_peak());
+        let (gain, peak) = match (use_album, track.0, album.0) {
+            (true, _, Some(_)) | (false, None, Some(_)) => album,
+            _ => track,
+        };
+        let Some(gain) = gain else {
+            return 1.0;
+        };
+        let gain = 10f64.powf((gain + self.preamp) / 20.0);
+        match peak {
+            Some(peak) if self.prevent_clipping && gain * peak > 1.0 => 1.0 / peak,
+            _ => gain,
+        }
+    }
+}
+
+/// Applies [Volume] and the normalization of the playing track to samples, ramping between
+/// gains so changes don't click. This is the [crate::ProcessorId::VOLUME] processor
+pub(super) struct Gain {
+    volume: Volume,
+    /// The linear factor of [Normalization::gain]
+    normalization: f64,
+    /// Gain per channel at the end of the last buffer
+    gains: Vec<f64>,
+}
+
+impl Gain {
+    const RAMP: Duration = Duration::from_millis(20);
+
+    pub(super) fn new() -> Self {
+        Self {
+            volume: Volume::default(),
+            normalization: 1.0,
+            gains: vec![],
+        }
+    }
+
+    /// Ramp to `volume` and `normalization` from the next buffer on
+    pub(super) fn set(&mut self, volume: Volume, normalization: f64) {
+        self.volume = volume;
+        self.normalization = normalization;
+    }
+}
+
+impl AudioProcessor for Gain {
+    fn process(&mut self, buffer: &mut SampleBuf, sample_rate: u32) {
+        let (volume, normalization) = (&self.volume, self.normalization);
+        // largest change of gain between two frames
+        let step = 1.0 / (Self::RAMP.as_secs_f64() * sample_rate as f64);
+        let channels = buffer.channels();
+        if self.gains.len() != channels {
+            // nothing to ramp from
+            self.gains = (0..channels)
+                .map(|c| volume.gain(c, channels) * normalization)
+                .collect();
+        }
+        for (c, samples) in buffer.channel_samples_mut().enumerate() {
+            let target = volume.gain(c, channels) * normalization;
+            let gain = &mut self.gains[c];
+            if *gain == target {
+                if target != 1.0 {
+                    samples.iter_mut().for_each(|s| *s *= target);
+                }
+                continue;
+            }
+            for sample in samples.iter_mut() {
+                *gain = if *gain < target {
+                    (*gain + step).min(target)
+                } else {
+                    (*gain - step).max(target)
+                };
+                *sample *= *gain;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.gains.clear();
+    }
+}
+
+/// Mixes the end of a track with the start of the next, with equal power curves so the
+/// loudness stays even
+pub(super) struct Crossfade {
+    /// Length of the crossfade
+    frames: usize,
+    /// Frames mixed so far
+    mixed: usize,
+}
+
+impl Crossfade {
+    pub(super) fn new(frames: usize) -> Self {
+        Self { frames, mixed: 0 }
+    }
+
+    /// The crossfade into the next track once `remaining` of the current track is left to play
+    /// at `speed`, `None` if it is too early or the tracks are not crossfaded. `albums` are the
+    /// albums of the current and the next track
+    pub(super) fn start(
+        fades: &FadeOptions,
+        remaining: Duration,
+        speed: f64,
+        sample_rate: u32,
+        albums: [Option<&str>; 2],
+    ) -> Option<Self> {
+        let same_album =
+            fades.crossfade_skip_same_album && albums[0].is_some() && albums[0] == albums[1];
+        if fades.crossfade.is_zero() || same_album || remaining > fades.crossfade {
+            return None;
+        }
+        // the rest of the track plays in fewer frames when faster
+        let frames = remaining.as_secs_f64() / speed * sample_rate as f64;
+        Some(Self::new(frames as usize))
+    }
+
+    /// Fade `outgoing` out and mix in `incoming` scaled by `incoming_gain`, which is silent where
+    /// it is shorter. Mono is mixed into every channel and channels `outgoing` doesn't have are
+    /// averaged into `channel % channels`
+    pub(super) fn apply(
+        &mut self,
+        outgoing: &mut SampleBuf,
+        incoming: &SampleBuf,
+        incoming_gain: f64,
+    ) {
+        let mix = incoming.channel_mix(outgoing.channels());
+        for (samples, incoming) in outgoing.channel_samples_mut().zip(mix) {
+            let incoming_gain = incoming_gain / incoming.len().max(1) as f64;
+            for (i, sample) in samples.iter_mut().enumerate() {
+                let progress = ((self.mixed + i) as f64 / self.frames as f64).min(1.0);
+                let angle = progress * std::f64::consts::FRAC_PI_2;
+                let mixed: f64 = incoming.iter().filter_map(|s| s.get(i)).sum();
+                *sample = *sample * angle.cos() + mixed * incoming_gain * angle.sin();
+            }
+        }
+        self.mixed += outgoing.frames();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_1_SQRT_2, FRAC_PI_2};
+
+    use super::*;
+
+    /// `frames` frames of a constant value per channel
+    fn buffer(channels: &[f64], frames: usize) -> SampleBuf {
+        SampleBuf::with_buffer(channels.iter().map(|&s| vec![s; frames]).collect())
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn balance() {
+        let volume = |volume, balance| Volume {
+            volume,
+            muted: false,
+            balance,
+        };
+        let gains = |volume: Volume, channels| {
+            (0..channels)
+                .map(|c| volume.gain(c, channels))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(gains(volume(1.0, 0.0), 2), [1.0, 1.0]);
+        assert_eq!(gains(volume(1.0, -1.0), 2), [1.0, 0.0]);
+        assert_eq!(gains(volume(1.0, 1.0), 2), [0.0, 1.0]);
+        assert_eq!(gains(volume(0.5, 0.5), 2), [0.25, 0.5]);
+        // only the front left and right channels are balanced
+        assert_eq!(gains(volume(0.5, 1.0), 1), [0.5]);
+        assert_eq!(gains(volume(1.0, -0.5), 4), [1.0, 0.5, 1.0, 1.0]);
+        let muted = Volume {
+            muted: true,
+            ..volume(1.0, 0.0)
+        };
+        assert_eq!(gains(muted, 2), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn ramp() {
+        let rate = 48000;
+        let ramp = (Gain::RAMP.as_secs_f64() * rate as f64) as usize;
+        let mut gain = Gain::new();
+        let mut samples = buffer(&[1.0, 1.0], 100);
+        gain.process(&mut samples, rate);
+        assert!(samples.channel_samples().flatten().all(|&s| s == 1.0));
+
+        let volume = Volume {
+            balance: 1.0,
+            ..Default::default()
+        };
+        gain.set(volume, 0.5);
+        let mut samples = buffer(&[1.0, 1.0], 2 * ramp);
+        gain.process(&mut samples, rate);
+        let left = samples.samples(0).unwrap();
+        let right = samples.samples(1).unwrap();
+        assert!(left.windows(2).all(|w| w[1] < w[0] || w[1] == 0.0));
+        assert!(close(left[ramp / 2 - 1], 0.5));
+        assert!(left[ramp - 1] < 1e-9);
+        assert!(left[ramp..].iter().all(|&s| s == 0.0));
+        assert!(close(right[ramp / 2 - 1], 0.5));
+        assert!(right[ramp / 2..].iter().all(|&s| s == 0.5));
+
+        // starts over at the new gain without ramping
+        gain.reset();
+        gain.set(Volume::default(), 1.0);
+        let mut samples = buffer(&[1.0], 10);
+        gain.process(&mut samples, rate);
+        assert!(samples.channel_samples().flatten().all(|&s| s == 1.0));
+    }
+
+    #[test]
+    fn normalization() {
+        let db = |db: f64| 10f64.powf(db / 20.0);
+        let normalization = |mode, preamp, prevent_clipping| Normalization {
+            mode,
+            preamp,
+            prevent_clipping,
+        };
+        let both = ReplayGain::new(Some(-6.0), Some(0.5), Some(-3.0), Some(0.9));
+        let gain = |normalization: Normalization, replay_gain, shuffled| {
+            normalization.gain(&replay_gain, shuffled)
+        };
+
+        let off = normalization(NormalizationMode::Off, 6.0, true);
+        assert_eq!(gain(off, both, false), 1.0);
+        let track = normalization(NormalizationMode::Track, 0.0, true);
+        assert!(close(gain(track, both, false), db(-6.0)));
+        let album = normalization(NormalizationMode::Album, 0.0, true);
+        assert!(close(gain(album, both, false), db(-3.0)));
+        let auto = normalization(NormalizationMode::Auto, 0.0, true);
+        assert!(close(gain(auto, both, false), db(-3.0)));
+        assert!(close(gain(auto, both, true), db(-6.0)));
+
+        // the other gain stands in for a missing one, no gain at all leaves the track as it is
+        let track_only = ReplayGain::new(Some(-6.0), None, None, None);
+        assert!(close(gain(album, track_only, false), db(-6.0)));
+        let album_only = ReplayGain::new(None, None, Some(-3.0), None);
+        assert!(close(gain(track, album_only, false), db(-3.0)));
+        assert_eq!(gain(track, ReplayGain::default(), false), 1.0);
+
+        // the preamp is added before limiting to the peak
+        let preamp = normalization(NormalizationMode::Track, 3.0, true);
+        assert!(close(gain(preamp, both, false), db(-3.0)));
+        let loud = normalization(NormalizationMode::Track, 18.0, true);
+        assert!(close(gain(loud, both, false), 2.0));
+        let clipping = normalization(NormalizationMode::Track, 18.0, false);
+        assert!(close(gain(clipping, both, false), db(12.0)));
+    }
+
+    #[test]
+    fn crossfade_equal_power() {
+        let frames = 1000;
+        let mut fade_out = Crossfade::new(frames);
+        let mut fade_in = Crossfade::new(frames);
+        // the second buffer goes past the end of the crossfade
+        for part in 0..2 {
+            let mut outgoing = buffer(&[1.0], frames * 3 / 4);
+            fade_out.apply(&mut outgoing, &buffer(&[0.0], frames), 1.0);
+            let mut incoming = buffer(&[0.0], frames * 3 / 4);
+            fade_in.apply(&mut incoming, &buffer(&[1.0], frames), 1.0);
+            let outgoing = outgoing.samples(0).unwrap();
+            let incoming = incoming.samples(0).unwrap();
+            for (i, (out, inc)) in outgoing.iter().zip(incoming).enumerate() {
+                let progress = ((part * frames * 3 / 4 + i) as f64 / frames as f64).min(1.0);
+                assert!(close(*out, (progress * FRAC_PI_2).cos()));
+                assert!(close(out * out + inc * inc, 1.0));
+            }
+        }
+
+        let mut outgoing = buffer(&[1.0], frames);
+        Crossfade::new(frames).apply(&mut outgoing, &buffer(&[1.0], frames), 0.5);
+        let samples = outgoing.samples(0).unwrap();
+        assert!(close(samples[0], 1.0));
+        assert!(close(samples[frames / 2], FRAC_1_SQRT_2 * 1.5));
+    }
+
+    #[test]
+    fn crossfade_incoming_shorter() {
+        let mut outgoing = buffer(&[1.0], 100);
+        Crossfade::new(100).apply(&mut outgoing, &buffer(&[1.0], 50), 1.0);
+        let samples = outgoing.samples(0).unwrap();
+        let angle = 60.0 / 100.0 * FRAC_PI_2;
+        assert!(close(samples[60], angle.cos()));
+    }
+
+    #[test]
+    fn crossfade_channels() {
+        // past the crossfade only the incoming track is left
+        let mix = |outgoing: usize, incoming: &[f64]| {
+            let mut outgoing = buffer(&vec![1.0; outgoing], 2);
+            Crossfade::new(1).apply(&mut outgoing, &buffer(incoming, 2), 1.0);
+            outgoing
+                .channel_samples()
+                .map(|samples| samples[1])
+                .collect::<Vec<_>>()
+        };
+        let close_all = |a: Vec<f64>, b: &[f64]| a.iter().zip(b).all(|(a, b)| close(*a, *b));
+        assert!(close_all(mix(2, &[0.5]), &[0.5, 0.5]));
+        assert!(close_all(mix(1, &[0.2, 0.6]), &[0.4]));
+        assert!(close_all(mix(2, &[0.2, 0.4, 0.6, 0.8]), &[0.4, 0.6]));
+        assert!(close_all(mix(3, &[0.2, 0.4]), &[0.2, 0.4, 0.0]));
+    }
+
+    #[test]
+    fn crossfade_start() {
+        let fades = FadeOptions {
+            crossfade: Duration::from_secs(4),
+            ..Default::default()
+        };
+        let second = Duration::from_secs(1);
+        let frames = |fades: &FadeOptions, remaining, speed, albums| {
+            Crossfade::start(fades, remaining, speed, 1000, albums).map(|c| c.frames)
+        };
+        let albums = [Some("a"), Some("b")];
+        assert_eq!(frames(&fades, 5 * second, 1.0, albums), None);
+        assert_eq!(frames(&fades, 4 * second, 1.0, albums), Some(4000));
+        // the rest of the track is played faster or slower
+        assert_eq!(frames(&fades, 2 * second, 2.0, albums), Some(1000));
+        assert_eq!(frames(&fades, 2 * second, 0.5, albums), Some(4000));
+
+        let same = [Some("a"), Some("a")];
+        assert_eq!(frames(&fades, second, 1.0, same), None);
+        assert_eq!(frames(&fades, second, 1.0, [None, None]), Some(1000));
+        let fades = FadeOptions {
+            crossfade_skip_same_album: false,
+            ..fades
+        };
+        assert_eq!(frames(&fades, second, 1.0, same), Some(1000));
+        let fades = FadeOptions {
+            crossfade: Duration::ZERO,
+            ..fades
+        };
+        assert_eq!(frames(&fades, Duration::ZERO, 1.0, albums), None);
+    }
+}
@@ -0,0 +1,127 @@
+use audio_player::Track;
+use clap::Args;
+use color_eyre::eyre::Result;
+use serde::Serialize;
+use std::path::PathBuf;
+
+#[derive(Debug, Args)]
+pub(super) struct InfoArgs {
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Info {
+    file: PathBuf,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    duration_seconds: Option<f64>,
+    codec: Option<String>,
+    codec_name: Option<String>,
+    container: Option<String>,
+    sample_rate: Option<u32>,
+    bits_per_sample: Option<u32>,
+    channels: Option<usize>,
+    channel_layout: Option<String>,
+    /// Bits per second
+    average_bitrate: Option<u32>,
+    file_size: Option<u64>,
+    lossless: bool,
+}
+
+impl Info {
+    fn new(file: PathBuf, track: &Track) -> Self {
+        let details = track.details();
+        let stream = track.stream_info();
+        Self {
+            file,
+            title: details.title().map(str::to_string),
+            artist: details.artist().map(str::to_string),
+            album: details.album().map(str::to_string),
+            duration_seconds: details.duration().map(|d| d.as_secs_f64()),
+            codec: stream.codec().map(str::to_string),
+            codec_name: stream.codec_name().map(str::to_string),
+            container: stream.container().map(str::to_string),
+            sample_rate: stream.sample_rate(),
+            bits_per_sample: stream.bits_per_sample(),
+            channels: stream.channels(),
+            channel_layout: stream.channel_layout(),
+            average_bitrate: stream.average_bitrate(),
+            file_size: stream.file_size(),
+            lossless: stream.lossless(),
+        }
+    }
+
+    fn print(&self) {
+        println!("File: {}", self.file.to_string_lossy());
+        let print = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                println!("    {}: {}", name, value);
+            }
+        };
+        print("Title", self.title.clone());
+        print("Artist", self.artist.clone());
+        print("Album", self.album.clone());
+        print(
+            "Duration",
+            self.duration_seconds.map(|d| {
+                let ms = (d * 1000.0) as u128;
+                format!(
+                    "{:02}:{:02}:{:06.3}",
+                    ms / 3_600_000,
+                    (ms % 3_600_000) / 60_000,
+                    (ms % 60_000) as f64 / 1000.0
+                )
+            }),
+        );
+        print(
+            "Codec",
+            match (&self.codec_name, &self.codec) {
+                (Some(name), Some(codec)) => Some(format!("{} ({})", name, codec)),
+                (name, codec) => name.clone().or(codec.clone()),
+            },
+        );
+        print("Container", self.container.clone());
+        print(
+            "Sample rate",
+            self.sample_rate.map(|rate| format!("{} Hz", rate)),
+        );
+        print(
+            "Bits per sample",
+            self.bits_per_sample.map(|bits| bits.to_string()),
+        );
+        print("Channels", self.channel_layout.clone());
+        print(
+            "Bitrate",
+            self.average_bitrate
+                .map(|bitrate| format!("{} kbps", bitrate / 1000)),
+        );
+        print(
+            "File size",
+            self.file_size
+                .map(|size| format!("{:.1} MiB", size as f64 / (1024.0 * 1024.0))),
+        );
+        println!("    Lossless: {}", if self.lossless { "yes" } else { "no" });
+    }
+}
+
+pub(super) fn info(args: InfoArgs) -> Result<()> {
+    let info = args
+        .files
+        .into_iter()
+        .map(|file| {
+            let track = Track::open(&file)?;
+            Ok(Info::new(file, &track))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+    } else {
+        info.iter().for_each(Info::print);
+    }
+    Ok(())
+}
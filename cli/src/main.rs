@@ -1,76 +1,350 @@
-use audio_player::AudioPlayer;
-use clap::{ArgAction, Parser};
+use audio_player::{
+    AudioPlayer, AudioPlayerEvent, AudioPlayerOptions, Equalizer, EqualizerPreset, FadeOptions,
+    NormalizationMode, QueueItem, RepeatMode, ReplayGain, SpeedMode, TrackDetails,
+};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use color_eyre::eyre::{eyre, Result};
 use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use info::InfoArgs;
+use scan::{LoudnessCache, ScanArgs};
 use std::{
     io::{stdin, stdout, Write},
     path::PathBuf,
     time::Duration,
 };
 
+mod info;
+mod scan;
+
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct CliArgs {
-    file: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Files or `http://` and `https://` URLs to play
+    #[arg(required_unless_present = "list_devices")]
+    files: Vec<PathBuf>,
     #[arg(short, long, default_value_t = true, action=ArgAction::SetFalse)]
     progress_bar: bool,
+    /// Output device name or id, the default device if not given
+    #[arg(short, long)]
+    device: Option<String>,
+    /// List the output devices and exit
+    #[arg(long)]
+    list_devices: bool,
+    /// Play the files again once they ended, `one` loops each file
+    #[arg(short, long, value_enum, default_value_t = Repeat::Off)]
+    repeat: Repeat,
+    /// Play the files in random order
+    #[arg(short, long)]
+    shuffle: bool,
+    /// Seconds the end of a file is mixed with the start of the next one
+    #[arg(long, default_value_t = 0.0)]
+    crossfade: f64,
+    /// Playback speed from 0.5 to 3
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+    /// Keep the pitch when playing faster or slower
+    #[arg(long)]
+    preserve_pitch: bool,
+    /// Play the files at the loudness of their ReplayGain tags, or of `ap scan --cache`
+    #[arg(long, value_enum, default_value_t = Normalize::Off)]
+    normalize: Normalize,
+    /// Gain in dB added to the ReplayGain of every file
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    preamp: f64,
+    /// Shape the sound with an equalizer preset
+    #[arg(long, value_enum, conflicts_with = "equalizer_gains")]
+    equalizer: Option<Preset>,
+    /// Gains in dB of the 10 bands of the equalizer from 31 Hz to 16 kHz, separated by commas
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "DB,...",
+        allow_negative_numbers = true
+    )]
+    equalizer_gains: Option<Vec<f64>>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Measure the loudness of files as in EBU R128
+    Scan(ScanArgs),
+    /// Show the codec, sample rate, bit depth and bitrate of files
+    Info(InfoArgs),
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Repeat {
+    Off,
+    One,
+    All,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Normalize {
+    Off,
+    Track,
+    Album,
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Preset {
+    Flat,
+    Rock,
+    Pop,
+    Jazz,
+    Classical,
+    Electronic,
+    Vocal,
+    BassBoost,
+    TrebleBoost,
+}
+
+impl From<Preset> for EqualizerPreset {
+    fn from(preset: Preset) -> Self {
+        match preset {
+            Preset::Flat => EqualizerPreset::Flat,
+            Preset::Rock => EqualizerPreset::Rock,
+            Preset::Pop => EqualizerPreset::Pop,
+            Preset::Jazz => EqualizerPreset::Jazz,
+            Preset::Classical => EqualizerPreset::Classical,
+            Preset::Electronic => EqualizerPreset::Electronic,
+            Preset::Vocal => EqualizerPreset::Vocal,
+            Preset::BassBoost => EqualizerPreset::BassBoost,
+            Preset::TrebleBoost => EqualizerPreset::TrebleBoost,
+        }
+    }
+}
+
+impl From<Normalize> for NormalizationMode {
+    fn from(normalize: Normalize) -> Self {
+        match normalize {
+            Normalize::Off => NormalizationMode::Off,
+            Normalize::Track => NormalizationMode::Track,
+            Normalize::Album => NormalizationMode::Album,
+            Normalize::Auto => NormalizationMode::Auto,
+        }
+    }
+}
+
+impl From<Repeat> for RepeatMode {
+    fn from(repeat: Repeat) -> Self {
+        match repeat {
+            Repeat::Off => RepeatMode::Off,
+            Repeat::One => RepeatMode::One,
+            Repeat::All => RepeatMode::All,
+        }
+    }
 }
 
 fn main() -> Result<()> {
     color_eyre::install()?;
     let args = CliArgs::parse();
 
-    let mut player = AudioPlayer::new();
-    let controller = player.controller().clone();
-    let track = player.open(&args.file)?;
-    let details = track.details().clone();
-    player.queue(track)?;
-
-    println!("File: {}", args.file.to_string_lossy());
-    if let Some(title) = details.title() {
-        println!("Title: {}", title);
+    match args.command {
+        Some(Command::Scan(args)) => return scan::scan(args),
+        Some(Command::Info(args)) => return info::info(args),
+        None => (),
     }
-    if let Some(artist) = details.artist() {
-        println!("Title: {}", artist);
+
+    if args.list_devices {
+        for device in AudioPlayer::output_devices()? {
+            println!(
+                "{}{}",
+                device.id(),
+                if device.is_default() {
+                    " (default)"
+                } else {
+                    ""
+                }
+            );
+            for config in device.configs() {
+                println!(
+                    "    {} channels, {}-{} Hz, {}",
+                    config.channels(),
+                    config.min_sample_rate(),
+                    config.max_sample_rate(),
+                    config.sample_format()
+                );
+            }
+        }
+        return Ok(());
     }
+
     const FPS: u64 = 15;
-    let duration = details.duration().ok_or(eyre!("no duration"))?.as_millis();
+    let mut player = AudioPlayer::with_options(AudioPlayerOptions {
+        device: args.device,
+        fades: FadeOptions {
+            crossfade: Duration::from_secs_f64(args.crossfade.max(0.0)),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let events = player
+        .controller()
+        .subscribe(Some(Duration::from_millis(1000 / FPS)));
+    let cache = match args.normalize {
+        Normalize::Off => LoudnessCache::default(),
+        _ => LoudnessCache::load().unwrap_or_default(),
+    };
+    for file in &args.files {
+        let mut track = player.open(file)?;
+        if *track.details().replay_gain() == ReplayGain::default() {
+            if let Some(replay_gain) = cache.get(file) {
+                track.set_replay_gain(replay_gain);
+            }
+        }
+        player.queue(track);
+    }
+    player.controller().set_repeat(args.repeat.into());
+    player.controller().set_shuffle(args.shuffle);
+    player.controller().set_speed(args.speed);
+    if args.preserve_pitch {
+        player.controller().set_speed_mode(SpeedMode::PreservePitch);
+    }
+    player.controller().set_normalization(args.normalize.into());
+    player.controller().set_preamp(args.preamp);
+    if let Some(preset) = args.equalizer {
+        player
+            .controller()
+            .set_equalizer(Equalizer::preset(preset.into()));
+    }
+    if let Some(gains) = args.equalizer_gains {
+        let gains = gains.try_into().map_err(|_| eyre!("expected 10 gains"))?;
+        player.controller().set_equalizer(Equalizer::graphic(gains));
+    }
+
+    fn describe(track: &QueueItem) -> Vec<String> {
+        let mut lines = vec![format!("File: {}", track.source())];
+        if let Some(title) = track.details().title() {
+            lines.push(format!("Title: {}", title));
+        }
+        if let Some(artist) = track.details().artist() {
+            lines.push(format!("Artist: {}", artist));
+        }
+        let tags = track.details().tags();
+        if let Some(album) = tags.album() {
+            match (tags.track_number(), tags.date()) {
+                (Some(number), Some(date)) => {
+                    lines.push(format!("Album: {} ({}), track {}", album, date, number))
+                }
+                (Some(number), None) => lines.push(format!("Album: {}, track {}", album, number)),
+                (None, Some(date)) => lines.push(format!("Album: {} ({})", album, date)),
+                (None, None) => lines.push(format!("Album: {}", album)),
+            }
+        }
+        if let Some(genre) = tags.genre() {
+            lines.push(format!("Genre: {}", genre));
+        }
+        lines
+    }
+    /// The song a radio stream moved on to
+    fn now_playing(details: &TrackDetails) -> Vec<String> {
+        let mut lines = vec![];
+        if let Some(title) = details.title() {
+            lines.push(format!("Title: {}", title));
+        }
+        if let Some(artist) = details.artist() {
+            lines.push(format!("Artist: {}", artist));
+        }
+        lines
+    }
+    /// `None` for live streams
+    fn duration_of(track: &QueueItem) -> Option<u128> {
+        track.details().duration().map(Duration::as_millis)
+    }
     if args.progress_bar {
-        let bar = ProgressBar::with_draw_target(
-            Some(duration as u64),
-            ProgressDrawTarget::stderr_with_hz(FPS as u8),
-        );
-        bar.set_style(ProgressStyle::with_template(&format!(
-            "[{{msg:>12}}] {{wide_bar}} [{:02}:{:02}:{:06.3}]",
-            duration / 3600_000,
-            (duration % 3600_000) / 60_000,
-            (duration % 60_000) as f64 / 1000.0
-        ))?);
-        std::thread::spawn(move || loop {
-            std::thread::sleep(Duration::from_millis(1000 / FPS));
-            let position = controller.position().map(|d| d.as_millis()).unwrap_or(0);
-            bar.set_position(position as u64);
-            bar.set_message(format!(
-                "{:02}:{:02}:{:06.3}",
-                position / 3600_000,
-                (position % 3600_000) / 60_000,
-                (position % 60_000) as f64 / 1000.0
-            ));
+        let bar =
+            ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr_with_hz(FPS as u8));
+        std::thread::spawn(move || -> Result<()> {
+            for event in events {
+                match event {
+                    AudioPlayerEvent::TrackStarted(track) => {
+                        describe(&track).iter().for_each(|l| bar.println(l));
+                        let template = match duration_of(&track) {
+                            Some(duration) => {
+                                bar.set_length(duration as u64);
+                                format!(
+                                    "[{{msg:>12}}] {{wide_bar}} [{:02}:{:02}:{:06.3}]",
+                                    duration / 3600_000,
+                                    (duration % 3600_000) / 60_000,
+                                    (duration % 60_000) as f64 / 1000.0
+                                )
+                            }
+                            None => "[{msg:>12}]".to_string(),
+                        };
+                        bar.set_style(ProgressStyle::with_template(&template)?);
+                    }
+                    AudioPlayerEvent::Position(position) => {
+                        let position = position.as_millis();
+                        bar.set_position(position as u64);
+                        bar.set_message(format!(
+                            "{:02}:{:02}:{:06.3}",
+                            position / 3600_000,
+                            (position % 3600_000) / 60_000,
+                            (position % 60_000) as f64 / 1000.0
+                        ));
+                    }
+                    AudioPlayerEvent::MetadataChanged(_, details) => {
+                        now_playing(&details).iter().for_each(|l| bar.println(l))
+                    }
+                    AudioPlayerEvent::Error(err) => bar.println(format!("Error: {}", err)),
+                    AudioPlayerEvent::DeviceLost(_) => {
+                        bar.println("Output device lost, continuing on the default device")
+                    }
+                    _ => (),
+                }
+            }
+            Ok(())
         });
     } else {
-        std::thread::spawn(move || loop {
-            std::thread::sleep(Duration::from_millis(1000 / FPS));
-            let position = controller.position().map(|d| d.as_millis()).unwrap_or(0);
-            print!("\x1b[2K\r");
-            print!(
-                "[{:02}:{:02}:{:06.3} / {:02}:{:02}:{:06.3}]",
-                position / 3600_000,
-                (position % 3600_000) / 60_000,
-                (position % 60_000) as f64 / 1000.0,
-                duration / 3600_000,
-                (duration % 3600_000) / 60_000,
-                (duration % 60_000) as f64 / 1000.0
-            );
-            stdout().flush().unwrap();
+        std::thread::spawn(move || -> Result<()> {
+            let mut duration = None;
+            for event in events {
+                match event {
+                    AudioPlayerEvent::TrackStarted(track) => {
+                        print!("\x1b[2K\r");
+                        describe(&track).iter().for_each(|l| println!("{}", l));
+                        duration = duration_of(&track);
+                    }
+                    AudioPlayerEvent::Position(position) => {
+                        let position = position.as_millis();
+                        print!("\x1b[2K\r");
+                        print!(
+                            "[{:02}:{:02}:{:06.3}",
+                            position / 3600_000,
+                            (position % 3600_000) / 60_000,
+                            (position % 60_000) as f64 / 1000.0
+                        );
+                        if let Some(duration) = duration {
+                            print!(
+                                " / {:02}:{:02}:{:06.3}",
+                                duration / 3600_000,
+                                (duration % 3600_000) / 60_000,
+                                (duration % 60_000) as f64 / 1000.0
+                            );
+                        }
+                        print!("]");
+                        stdout().flush()?;
+                    }
+                    AudioPlayerEvent::MetadataChanged(_, details) => {
+                        print!("\x1b[2K\r");
+                        now_playing(&details).iter().for_each(|l| println!("{}", l));
+                    }
+                    AudioPlayerEvent::Error(err) => {
+                        print!("\x1b[2K\r");
+                        eprintln!("Error: {}", err);
+                    }
+                    AudioPlayerEvent::DeviceLost(_) => {
+                        print!("\x1b[2K\r");
+                        println!("Output device lost, continuing on the default device");
+                    }
+                    _ => (),
+                }
+            }
+            Ok(())
         });
     }
 
@@ -0,0 +1,197 @@
+use audio_player::{AlbumLoudness, Loudness, ReplayGain, Track};
+use clap::Args;
+use color_eyre::eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+#[derive(Debug, Args)]
+pub(super) struct ScanArgs {
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+    /// Also measure the files together as one album
+    #[arg(short, long)]
+    album: bool,
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+    /// Remember the results for playing files without ReplayGain tags with `--normalize`
+    #[arg(long)]
+    cache: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ScanResult {
+    tracks: Vec<Measurement>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    album: Option<Measurement>,
+}
+
+#[derive(Debug, Serialize)]
+struct Measurement {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<PathBuf>,
+    /// `None` if everything is silent
+    integrated_lufs: Option<f64>,
+    range_lu: f64,
+    true_peak: f64,
+    true_peak_dbtp: f64,
+    replay_gain_db: Option<f64>,
+}
+
+impl Measurement {
+    fn new(file: Option<&Path>, loudness: &Loudness) -> Self {
+        Self {
+            file: file.map(Path::to_path_buf),
+            integrated_lufs: Some(loudness.integrated()).filter(|l| l.is_finite()),
+            range_lu: loudness.range(),
+            true_peak: loudness.true_peak(),
+            true_peak_dbtp: 20.0 * loudness.true_peak().log10(),
+            replay_gain_db: loudness.replay_gain().track_gain(),
+        }
+    }
+
+    fn print(&self) {
+        match &self.file {
+            Some(file) => println!("File: {}", file.to_string_lossy()),
+            None => println!("Album"),
+        }
+        match self.integrated_lufs {
+            Some(integrated) => println!("    Integrated loudness: {:.1} LUFS", integrated),
+            None => println!("    Integrated loudness: silent"),
+        }
+        println!("    Loudness range: {:.1} LU", self.range_lu);
+        println!(
+            "    True peak: {:.1} dBTP ({:.6})",
+            self.true_peak_dbtp, self.true_peak
+        );
+        if let Some(gain) = self.replay_gain_db {
+            println!("    ReplayGain: {:+.2} dB", gain);
+        }
+    }
+}
+
+pub(super) fn scan(args: ScanArgs) -> Result<()> {
+    let (loudness, album) = if args.album {
+        let mut tracks = args
+            .files
+            .iter()
+            .map(Track::open)
+            .collect::<Result<Vec<_>, _>>()?;
+        let album = AlbumLoudness::scan(&mut tracks)?;
+        (album.tracks().to_vec(), Some(album))
+    } else {
+        // only one file is open at a time
+        let loudness = args
+            .files
+            .iter()
+            .map(|file| Loudness::scan(&mut Track::open(file)?))
+            .collect::<Result<Vec<_>, _>>()?;
+        (loudness, None)
+    };
+
+    let result = ScanResult {
+        tracks: args
+            .files
+            .iter()
+            .zip(&loudness)
+            .map(|(file, loudness)| Measurement::new(Some(file), loudness))
+            .collect(),
+        album: album.as_ref().map(|a| Measurement::new(None, a.album())),
+    };
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&result)?);
+    } else {
+        result.tracks.iter().for_each(Measurement::print);
+        result.album.iter().for_each(Measurement::print);
+    }
+
+    if args.cache {
+        let mut cache = LoudnessCache::load()?;
+        for (i, file) in args.files.iter().enumerate() {
+            let replay_gain = match &album {
+                Some(album) => album.replay_gain(i),
+                None => Some(loudness[i].replay_gain()),
+            };
+            if let Some(replay_gain) = replay_gain {
+                cache.insert(file, &replay_gain)?;
+            }
+        }
+        cache.save()?;
+    }
+    Ok(())
+}
+
+/// Results of `ap scan --cache` by file, only valid as long as the file is not modified
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(super) struct LoudnessCache {
+    files: HashMap<PathBuf, CacheEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    modified: SystemTime,
+    track_gain: Option<f64>,
+    track_peak: Option<f64>,
+    album_gain: Option<f64>,
+    album_peak: Option<f64>,
+}
+
+impl LoudnessCache {
+    fn path() -> Result<PathBuf> {
+        let cache_dir = dirs::cache_dir().ok_or(eyre!("no cache directory"))?;
+        Ok(cache_dir.join("ap").join("loudness.json"))
+    }
+
+    pub(super) fn load() -> Result<Self> {
+        match fs::read(Self::path()?) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// URLs are left out as there is no telling whether they changed
+    fn insert(&mut self, file: &Path, replay_gain: &ReplayGain) -> Result<()> {
+        if !file.is_file() {
+            return Ok(());
+        }
+        let entry = CacheEntry {
+            modified: fs::metadata(file)?.modified()?,
+            track_gain: replay_gain.track_gain(),
+            track_peak: replay_gain.track_peak(),
+            album_gain: replay_gain.album_gain(),
+            album_peak: replay_gain.album_peak(),
+        };
+        self.files.insert(file.canonicalize()?, entry);
+        Ok(())
+    }
+
+    /// The cached values for `file` unless it was modified since it was scanned
+    pub(super) fn get(&self, file: &Path) -> Option<ReplayGain> {
+        let entry = self.files.get(&file.canonicalize().ok()?)?;
+        let modified = fs::metadata(file).and_then(|m| m.modified()).ok()?;
+        (modified == entry.modified).then(|| {
+            ReplayGain::new(
+                entry.track_gain,
+                entry.track_peak,
+                entry.album_gain,
+                entry.album_peak,
+            )
+        })
+    }
+}
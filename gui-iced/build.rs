@@ -1,13 +1,13 @@
-use std::{env, io};
-
-use winresource::WindowsResource;
-
-fn main() -> io::Result<()> {
-    if env::var_os("CARGO_CFG_WINDOWS").is_some() {
-        WindowsResource::new()
-            // This path can be absolute, or relative to your crate root.
-            .set_icon("assets/icon.ico")
-            .compile()?;
-    }
-    Ok(())
+use std::{env, io};
+
+use winresource::WindowsResource;
+
+fn main() -> io::Result<()> {
+    if env::var_os("CARGO_CFG_WINDOWS").is_some() {
+        WindowsResource::new()
+            // This path can be absolute, or relative to your crate root.
+            .set_icon("assets/icon.ico")
+            .compile()?;
+    }
+    Ok(())
 }
\ No newline at end of file
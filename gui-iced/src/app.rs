@@ -1,9 +1,13 @@
 use std::{path::PathBuf, time::Duration};
 
+use audio_player::{AudioPlayerEvent, OutputDevice};
 use iced::{
     alignment::Vertical,
-    font, time,
-    widget::{button, column, container, image, lazy, row, svg, text, Space},
+    font,
+    futures::{executor::block_on, SinkExt},
+    widget::{
+        button, column, container, image, lazy, row, scrollable, slider, svg, text, Column, Space,
+    },
     window, Alignment, Background, Border, Color, Element, Font, Length, Padding, Size,
     Subscription, Task, Theme,
 };
@@ -17,6 +21,8 @@ use crate::player::AudioPlayer;
 
 pub(super) struct AudioPlayerApplication {
     player: AudioPlayer,
+    /// Output devices listed in the device menu
+    devices: Vec<OutputDevice>,
     seeking: bool,
     playback_position: f64,
 }
@@ -25,10 +31,17 @@ pub(super) struct AudioPlayerApplication {
 pub(super) enum Message {
     Play,
     Pause,
-    SyncPosition,
+    Next,
+    Previous,
+    JumpTo(usize),
+    SelectDevice(Option<String>),
+    SetVolume(f32),
+    ToggleMute,
+    PlayerEvent(AudioPlayerEvent),
     BeginSeek(f64),
     ConfirmSeek,
     OpenFilePicker,
+    QueueFilePicker,
     Stop,
     Resize(Size),
 }
@@ -40,9 +53,11 @@ impl AudioPlayerApplication {
         if let Some(p) = flags.file_path {
             player.open(p).expect("failed to open");
         }
+        let devices = player.devices();
         (
             Self {
                 player,
+                devices,
                 seeking: false,
                 playback_position: 0.0,
             },
@@ -55,7 +70,7 @@ impl AudioPlayerApplication {
             if let Some(title) = track.details().title() {
                 return format!("Audio Player - {}", title);
             } else {
-                return format!("Audio Player - {}", track.file_path().to_string_lossy());
+                return format!("Audio Player - {}", track.source());
             }
         }
         "Audio Player".into()
@@ -65,12 +80,28 @@ impl AudioPlayerApplication {
         match message {
             Message::Play => self.player.play(),
             Message::Pause => self.player.pause(),
-            Message::SyncPosition => {
-                self.playback_position = self.player.position().as_micros() as f64;
-            }
+            Message::Next => self.player.next(),
+            Message::Previous => self.player.previous(),
+            Message::JumpTo(index) => self.player.jump_to(index),
+            Message::SelectDevice(device) => self.player.set_device(device),
+            Message::SetVolume(volume) => self.player.set_volume(volume),
+            Message::ToggleMute => self.player.set_muted(!self.player.muted()),
+            Message::PlayerEvent(event) => match event {
+                AudioPlayerEvent::Position(position) | AudioPlayerEvent::Seeked(position)
+                    if !self.seeking =>
+                {
+                    self.playback_position = position.as_micros() as f64;
+                }
+                AudioPlayerEvent::TrackStarted(_) if !self.seeking => {
+                    self.playback_position = 0.0;
+                }
+                _ => (),
+            },
             Message::BeginSeek(position) => {
                 self.seeking = true;
                 self.playback_position = position;
+                // follow the slider while dragging, the player skips seeks it cannot keep up with
+                self.player.seek(Duration::from_micros(position as u64));
             }
             Message::ConfirmSeek => {
                 self.player
@@ -84,6 +115,14 @@ impl AudioPlayerApplication {
                     self.player.open(file).expect("failed to open");
                 }
             }
+            Message::QueueFilePicker => {
+                if let Some(files) = FileDialog::new().pick_files() {
+                    for file in files {
+                        // TODO handle error
+                        self.player.open(file).expect("failed to open");
+                    }
+                }
+            }
             Message::Stop => self.player.stop(),
             Message::Resize(size) => {
                 return window::get_oldest().then(move |id| {
@@ -98,13 +137,22 @@ impl AudioPlayerApplication {
     }
 
     pub(super) fn subscription(&self) -> iced::Subscription<Message> {
-        if self.player.playing() && !self.seeking {
-            const FPS: u64 = 240;
-            // TODO use stream update or something to avoid smol dependency
-            return Subscription::batch([time::every(time::Duration::from_millis(1000u64 / FPS))
-                .map(|_| Message::SyncPosition)]);
-        }
-        Subscription::none()
+        const FPS: u64 = 60;
+        let controller = self.player.controller();
+        Subscription::run_with_id(
+            "player-events",
+            iced::stream::channel(100, move |mut output| async move {
+                let events = controller.subscribe(Some(Duration::from_millis(1000u64 / FPS)));
+                // the receiver blocks, so forward events from a thread of its own
+                std::thread::spawn(move || {
+                    for event in events {
+                        if block_on(output.send(Message::PlayerEvent(event))).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }),
+        )
     }
 
     pub(super) fn view(&self) -> Element<Message> {
@@ -121,21 +169,64 @@ impl AudioPlayerApplication {
         let menu = Menu::new(menu_items![(button("Open")
             .style(menu_button_style)
             .on_press(Message::OpenFilePicker))(
+            button("Add to Queue")
+                .style(menu_button_style)
+                .on_press(Message::QueueFilePicker)
+        )(
             button("Close")
                 .style(menu_button_style)
                 .on_press(Message::Stop)
         )]);
-        let menu_bar = menu_bar![(
-            button("File").style(menu_button_style),
-            menu.width(100).offset(5.0)
-        )]
+        let selected_device = self.player.device();
+        let device_menu = Menu::new(
+            std::iter::once((None, "Default".to_string()))
+                .chain(self.devices.iter().map(|d| (Some(d.id()), d.id())))
+                .map(|(device, name)| {
+                    let weight = if device == selected_device {
+                        font::Weight::Bold
+                    } else {
+                        font::Weight::Normal
+                    };
+                    Item::new(
+                        button(text(name).shaping(text::Shaping::Advanced).font(Font {
+                            weight,
+                            ..Default::default()
+                        }))
+                        .style(menu_button_style)
+                        .width(Length::Fill)
+                        .on_press(Message::SelectDevice(device)),
+                    )
+                })
+                .collect(),
+        );
+        let menu_bar = menu_bar![
+            (
+                button("File").style(menu_button_style),
+                menu.width(120).offset(5.0)
+            )(
+                button("Device").style(menu_button_style),
+                device_menu.width(300).offset(5.0)
+            )
+        ]
         .draw_path(menu::DrawPath::Backdrop);
 
         // TODO: requires https://github.com/iced-rs/iced/issues/36 to implement selectable text
-        let track_description = container(lazy(self.player.current(), |&current| {
+        // the id of a track stays the same when the title of a radio stream or the bitrate
+        // changes while it plays
+        let current = self.player.current().map(|track| {
+            let details = track.details();
+            let key = (
+                details.title().map(str::to_string),
+                details.artist().map(str::to_string),
+                details.album().map(str::to_string),
+                track.stream_info().to_string(),
+            );
+            (track, key)
+        });
+        let track_description = container(lazy(current, |current| {
             match current {
-                Some(track) => {
-                    let file_path = text(track.file_path().to_string_lossy().to_string())
+                Some((track, _)) => {
+                    let file_path = text(track.source().to_string())
                         .shaping(text::Shaping::Advanced)
                         .font(Font {
                             weight: font::Weight::Light,
@@ -160,6 +251,19 @@ impl AudioPlayerApplication {
                             .into(),
                         None => Element::from(Space::with_height(0)),
                     };
+                    let album = match track.details().album() {
+                        Some(album) => text(album.to_string())
+                            .size(18)
+                            .shaping(text::Shaping::Advanced)
+                            .into(),
+                        None => Element::from(Space::with_height(0)),
+                    };
+                    let stream = text(track.stream_info().to_string())
+                        .size(14)
+                        .font(Font {
+                            weight: font::Weight::Light,
+                            ..Default::default()
+                        });
                     let separator = Space::with_height(10);
                     let cover = match track.details().cover() {
                         Some(cover) => {
@@ -168,16 +272,46 @@ impl AudioPlayerApplication {
                         }
                         None => Element::from(Space::with_height(Length::Fill)),
                     };
-                    column![file_path, title, artist, separator, cover]
+                    column![file_path, title, artist, album, stream, separator, cover]
                         .align_x(Alignment::Center)
                         .into()
                 }
                 None => Element::from(Space::with_height(0)),
             }
         }))
+        .width(Length::Fill)
         .height(Length::Fill)
         .align_y(Vertical::Center);
 
+        let current_index = self.player.current_index();
+        let queue_list = container(scrollable(Column::with_children(
+            self.player
+                .queue()
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    let name = match item.details().title() {
+                        Some(title) => title.to_string(),
+                        None => item.source().to_string(),
+                    };
+                    let weight = if Some(i) == current_index {
+                        font::Weight::Bold
+                    } else {
+                        font::Weight::Normal
+                    };
+                    button(text(name).shaping(text::Shaping::Advanced).font(Font {
+                        weight,
+                        ..Default::default()
+                    }))
+                    .style(menu_button_style)
+                    .width(Length::Fill)
+                    .on_press(Message::JumpTo(i))
+                    .into()
+                }),
+        )))
+        .width(250)
+        .height(Length::Fill);
+
         let track_duration = match self.player.current() {
             Some(track) => match track.details().duration() {
                 Some(duration) => duration.as_micros() as f64,
@@ -247,11 +381,31 @@ impl AudioPlayerApplication {
             }
         });
         // let stop_button = button(text("Stop")).on_press(Message::Stop);
+        let previous_button = button(text("<<")).on_press(Message::Previous);
+        let next_button = button(text(">>")).on_press(Message::Next);
+        let mute_button = button(text(if self.player.muted() { "Unmute" } else { "Mute" }))
+            .style(menu_button_style)
+            .on_press(Message::ToggleMute);
+        let volume_slider = slider(0.0..=1.0, self.player.volume(), Message::SetVolume)
+            .step(0.01)
+            .width(100);
+        let buffering = match self.player.buffering() {
+            Some(buffering) if buffering.stalled => Element::from(text("Buffering...").size(14)),
+            _ => Element::from(Space::with_width(0)),
+        };
         let controls = container(
-            row![play_pause_button
-            // , stop_button
+            row![
+                previous_button,
+                play_pause_button,
+                next_button,
+                // stop_button,
+                row![mute_button, volume_slider]
+                    .spacing(5)
+                    .align_y(Alignment::Center),
+                buffering
             ]
-            .spacing(20),
+            .spacing(20)
+            .align_y(Alignment::Center),
         )
         .width(Length::Fill)
         .center_x(Length::Fill)
@@ -260,7 +414,7 @@ impl AudioPlayerApplication {
         column![
             menu_bar,
             container(
-                column![track_description, seek_progress, controls]
+                column![row![track_description, queue_list], seek_progress, controls]
                     .spacing(10)
                     .align_x(Alignment::Center),
             )
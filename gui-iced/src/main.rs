@@ -11,6 +11,7 @@ use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
 struct CliArgs {
+    /// File or `http://` or `https://` URL to play
     #[arg(index(1))]
     file: Option<PathBuf>,
 }
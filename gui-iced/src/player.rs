@@ -1,13 +1,8 @@
-use std::{
-    hash::Hash,
-    path::{Path, PathBuf},
-    time::Duration,
-};
+use std::{path::Path, time::Duration};
 
-use audio_player::{AudioPlayerError, TrackDetails};
+use audio_player::{AudioPlayerController, AudioPlayerError, Buffering, OutputDevice, QueueItem};
 
 pub(super) struct AudioPlayer {
-    track: Option<Track>,
     player: audio_player::AudioPlayer,
 }
 
@@ -15,26 +10,27 @@ impl AudioPlayer {
     pub(super) fn new() -> Self {
         let player = audio_player::AudioPlayer::new();
 
-        Self {
-            track: None,
-            player,
-        }
+        Self { player }
     }
 
     pub(super) fn open<P: AsRef<Path>>(&mut self, path: P) -> Result<(), AudioPlayerError> {
         let track = self.player.open(path.as_ref().to_path_buf())?;
-        self.track = Some(Track {
-            file_path: path.as_ref().to_path_buf(),
-            details: track.details().clone(),
-        });
-        self.player.queue(track)?;
+        self.player.queue(track);
 
         Ok(())
     }
 
     /// Get the current playing track
-    pub(super) fn current(&self) -> Option<&Track> {
-        self.track.as_ref()
+    pub(super) fn current(&self) -> Option<QueueItem> {
+        self.player.controller().current()
+    }
+
+    pub(super) fn queue(&self) -> Vec<QueueItem> {
+        self.player.controller().queue()
+    }
+
+    pub(super) fn current_index(&self) -> Option<usize> {
+        self.player.controller().current_index()
     }
 
     pub(super) fn play(&self) {
@@ -53,6 +49,47 @@ impl AudioPlayer {
         self.player.drain()
     }
 
+    pub(super) fn volume(&self) -> f32 {
+        self.player.controller().volume()
+    }
+
+    pub(super) fn set_volume(&self, volume: f32) {
+        self.player.controller().set_volume(volume);
+    }
+
+    pub(super) fn muted(&self) -> bool {
+        self.player.controller().muted()
+    }
+
+    pub(super) fn set_muted(&self, muted: bool) {
+        self.player.controller().set_muted(muted);
+    }
+
+    pub(super) fn devices(&self) -> Vec<OutputDevice> {
+        audio_player::AudioPlayer::output_devices().unwrap_or_default()
+    }
+
+    pub(super) fn device(&self) -> Option<String> {
+        self.player.controller().device()
+    }
+
+    pub(super) fn set_device(&self, device: Option<String>) {
+        self.player.controller().set_device(device);
+    }
+
+    pub(super) fn next(&self) {
+        self.player.controller().next();
+    }
+
+    pub(super) fn previous(&self) {
+        self.player.controller().previous();
+    }
+
+    pub(super) fn jump_to(&self, index: usize) {
+        // the index comes from `queue` so it can only be stale, which is safe to ignore
+        let _ = self.player.controller().jump_to(index);
+    }
+
     pub(super) fn position(&self) -> Duration {
         self.player
             .controller()
@@ -60,32 +97,20 @@ impl AudioPlayer {
             .unwrap_or(Duration::from_secs(0))
     }
 
-    pub(super) fn seek(&self, position: Duration) {
-        self.player.controller().seek(position);
+    /// `None` unless the current track is played from a URL
+    pub(super) fn buffering(&self) -> Option<Buffering> {
+        self.player.controller().buffering()
     }
 
-    pub(super) fn running(&self) -> bool {
-        self.player.running()
-    }
-}
-
-pub(super) struct Track {
-    file_path: PathBuf,
-    details: TrackDetails,
-}
-
-impl Track {
-    pub(super) fn file_path(&self) -> &PathBuf {
-        &self.file_path
+    pub(super) fn seek(&self, position: Duration) {
+        self.player.controller().seek(position);
     }
 
-    pub(super) fn details(&self) -> &TrackDetails {
-        &self.details
+    pub(super) fn controller(&self) -> AudioPlayerController {
+        self.player.controller().clone()
     }
-}
 
-impl Hash for Track {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.file_path.hash(state);
+    pub(super) fn running(&self) -> bool {
+        self.player.running()
     }
 }
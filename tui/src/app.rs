@@ -1,181 +1,332 @@
-use std::{
-    io::Stdout,
-    path::{Path, PathBuf},
-    time::Duration,
-};
-
-use audio_player::{AudioPlayer, TrackDetails};
-use color_eyre::eyre::{eyre, Error, Ok, Result};
-use ratatui::{
-    crossterm::{
-        event::{self, Event, KeyCode, KeyEventKind, MouseButton},
-        terminal,
-    },
-    layout::{Constraint, Layout, Position, Rect},
-    prelude::{Backend, CrosstermBackend},
-    style::{Color, Style, Stylize},
-    text::{Line, Span, Text},
-    widgets::{Block, Gauge, Paragraph, Widget},
-    Terminal,
-};
-
-pub(super) struct AudioPlayerApplication {
-    player: AudioPlayer,
-    track: Option<Track>,
-}
-
-impl AudioPlayerApplication {
-    pub(super) fn new() -> Self {
-        let player = AudioPlayer::new();
-        Self {
-            player,
-            track: None,
-        }
-    }
-
-    pub(super) fn open<P: AsRef<Path>>(&mut self, file_path: P) -> Result<()> {
-        let track = self.player.open(file_path.as_ref())?;
-        self.track = Some(Track {
-            file_path: file_path.as_ref().to_path_buf(),
-            details: track.details().clone(),
-        });
-        self.player.queue(track)?;
-        Ok(())
-    }
-
-    pub(super) fn run(self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<()> {
-        const FPS: u64 = 240;
-        self.player.controller().play();
-        let mut seekbar_rect = None;
-        let track = match self.track {
-            Some(track) => track,
-            None => panic!("track unavailable"),
-        };
-        let file_path = track.file_path().to_string_lossy();
-        let track_title = track.details().title().unwrap_or_default();
-        let track_artist = track.details().artist().unwrap_or_default();
-        let mut drag_progress = None;
-        loop {
-            let position = self
-                .player
-                .controller()
-                .position()
-                .unwrap_or(Duration::from_secs(0));
-            let duration = track
-                .details()
-                .duration()
-                .cloned()
-                .unwrap_or(Duration::from_secs(0));
-            terminal.draw(|frame| {
-                let position = match drag_progress {
-                    Some(position) => position,
-                    None => position,
-                };
-                let progress_bar = Gauge::default()
-                    .ratio(position.as_micros() as f64 / duration.as_micros() as f64)
-                    .use_unicode(true)
-                    // Need this to avoid percentage sign
-                    .label("")
-                    .gauge_style(Style {
-                        fg: Some(Color::Gray),
-                        bg: Some(Color::Black),
-                        ..Default::default()
-                    });
-
-                // TODO: finish
-                let track_info = Paragraph::new(Text::from(vec![
-                    Line::from(format!("Title: {}", track_title)),
-                    Line::from(format!("Artist: {}", track_artist)),
-                ]))
-                .block(Block::new().title(format!("Playing: {}", file_path)));
-
-                let progress_info = Text::raw(format!(
-                    "[{:02}:{:02}:{:06.3} / {:02}:{:02}:{:06.3}]",
-                    position.as_millis() / 3600_000,
-                    (position.as_millis() % 3600_000) / 60_000,
-                    (position.as_millis() % 60_000) as f64 / 1000.0,
-                    duration.as_millis() / 3600_000,
-                    (duration.as_millis() % 3600_000) / 60_000,
-                    (duration.as_millis() % 60_000) as f64 / 1000.0
-                ))
-                .centered();
-
-                let layout = Layout::vertical([
-                    Constraint::Fill(1),
-                    Constraint::Length(2),
-                    Constraint::Length(1),
-                ])
-                .split(frame.area());
-                seekbar_rect = Some(layout[1]);
-                frame.render_widget(track_info, layout[0]);
-                frame.render_widget(progress_bar, layout[1]);
-                frame.render_widget(progress_info, layout[2]);
-            })?;
-
-            if event::poll(Duration::from_millis(1000 / FPS))? {
-                match event::read()? {
-                    Event::Key(key) => match key.kind {
-                        KeyEventKind::Press => match key.code {
-                            KeyCode::Char('q') => break Ok(()),
-                            KeyCode::Char(' ') => {
-                                if self.player.controller().playing() {
-                                    self.player.controller().pause();
-                                } else {
-                                    self.player.controller().play();
-                                }
-                            }
-                            _ => (),
-                        },
-                        _ => (),
-                    },
-                    Event::Mouse(mouse) => match mouse.kind {
-                        event::MouseEventKind::Up(MouseButton::Left) => {
-                            if let Some(rect) = seekbar_rect {
-                                if rect.contains(Position {
-                                    x: mouse.column,
-                                    y: mouse.row,
-                                }) {
-                                    drag_progress = None;
-                                    let seek_position = mouse.column as f64 / rect.width as f64
-                                        * duration.as_secs_f64();
-                                    self.player
-                                        .controller()
-                                        .seek(Duration::from_secs_f64(seek_position))
-                                }
-                            }
-                        }
-                        event::MouseEventKind::Drag(MouseButton::Left) => {
-                            if let Some(rect) = seekbar_rect {
-                                if rect.contains(Position {
-                                    x: mouse.column,
-                                    y: mouse.row,
-                                }) {
-                                    let seek_position = mouse.column as f64 / rect.width as f64
-                                        * duration.as_secs_f64();
-                                    drag_progress = Some(Duration::from_secs_f64(seek_position));
-                                }
-                            }
-                        }
-                        _ => (),
-                    },
-                    _ => (),
-                };
-            };
-        }
-    }
-}
-
-pub(super) struct Track {
-    file_path: PathBuf,
-    details: TrackDetails,
-}
-
-impl Track {
-    pub(super) fn file_path(&self) -> &PathBuf {
-        &self.file_path
-    }
-
-    pub(super) fn details(&self) -> &TrackDetails {
-        &self.details
-    }
-}
+use std::{io::Stdout, path::Path, time::Duration};
+
+use audio_player::{
+    AudioPlayer, AudioPlayerEvent, AudioPlayerOptions, Equalizer, EqualizerPreset,
+    NormalizationMode, RepeatMode, SpeedMode,
+};
+use color_eyre::eyre::{eyre, Error, Ok, Result};
+use ratatui::{
+    crossterm::{
+        event::{self, Event, KeyCode, KeyEventKind, MouseButton},
+        terminal,
+    },
+    layout::{Constraint, Layout, Position, Rect},
+    prelude::{Backend, CrosstermBackend},
+    style::{Color, Style, Stylize},
+    text::{Line, Span, Text},
+    widgets::{Block, Gauge, Paragraph, Widget},
+    Terminal,
+};
+
+pub(super) struct AudioPlayerApplication {
+    player: AudioPlayer,
+}
+
+impl AudioPlayerApplication {
+    pub(super) fn new(device: Option<String>) -> Self {
+        let player = AudioPlayer::with_options(AudioPlayerOptions {
+            device,
+            ..Default::default()
+        });
+        Self { player }
+    }
+
+    pub(super) fn open<P: AsRef<Path>>(&mut self, file_path: P) -> Result<()> {
+        let track = self.player.open(file_path.as_ref())?;
+        self.player.queue(track);
+        Ok(())
+    }
+
+    pub(super) fn run(self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<()> {
+        const FPS: u64 = 60;
+        const VOLUME_STEP: f32 = 0.05;
+        const SPEED_STEP: f64 = 0.25;
+        let events = self
+            .player
+            .controller()
+            .subscribe(Some(Duration::from_millis(1000 / FPS)));
+        self.player.controller().play();
+        let mut redraw = true;
+        let mut seekbar_rect = None;
+        let mut drag_progress = None;
+        let mut error = None;
+        let mut preset = EqualizerPreset::Flat;
+        loop {
+            // only redraw when the player or the user changed something
+            for event in events.try_iter() {
+                if let AudioPlayerEvent::Error(err) = event {
+                    error = Some(err.to_string());
+                }
+                redraw = true;
+            }
+            let controller = self.player.controller();
+            let buffering = controller.buffering();
+            let queue = controller.queue();
+            let current_index = controller.current_index();
+            let track = current_index.map(|i| &queue[i]);
+            let position = controller.position().unwrap_or(Duration::from_secs(0));
+            let duration = track
+                .and_then(|t| t.details().duration().cloned())
+                .unwrap_or(Duration::from_secs(0));
+            let volume = match controller.muted() {
+                true => "Volume: muted".to_string(),
+                false => format!("Volume: {:.0}%", controller.volume() * 100.0),
+            };
+            let normalization = format!(
+                "ReplayGain: {}",
+                match controller.normalization() {
+                    NormalizationMode::Off => "off",
+                    NormalizationMode::Track => "track",
+                    NormalizationMode::Album => "album",
+                    NormalizationMode::Auto => "auto",
+                }
+            );
+            let mode = format!(
+                "Repeat: {}, Shuffle: {}",
+                match controller.repeat() {
+                    RepeatMode::Off => "off",
+                    RepeatMode::One => "one",
+                    RepeatMode::All => "all",
+                },
+                if controller.shuffle() { "on" } else { "off" }
+            );
+            let speed = format!(
+                "Speed: {:.2}x{}",
+                controller.speed(),
+                match controller.speed_mode() {
+                    SpeedMode::Varispeed => "",
+                    SpeedMode::PreservePitch => ", pitch kept",
+                }
+            );
+            let equalizer = format!("Equalizer: {}", preset);
+            if redraw {
+                redraw = false;
+                terminal.draw(|frame| {
+                    let position = match drag_progress {
+                        Some(position) => position,
+                        None => position,
+                    };
+                    // live streams have no duration
+                    let ratio = match duration.is_zero() {
+                        true => 0.0,
+                        false => position.as_micros() as f64 / duration.as_micros() as f64,
+                    };
+                    let progress_bar = Gauge::default()
+                        .ratio(ratio.clamp(0.0, 1.0))
+                        .use_unicode(true)
+                        // Need this to avoid percentage sign
+                        .label("")
+                        .gauge_style(Style {
+                            fg: Some(Color::Gray),
+                            bg: Some(Color::Black),
+                            ..Default::default()
+                        });
+
+                    // TODO: finish
+                    let error_line = error
+                        .as_ref()
+                        .map(|error| Line::from(format!("Error: {}", error)).red());
+                    let buffering_line = buffering.map(|buffering| {
+                        Line::from(match buffering.stalled {
+                            true => "Network: buffering".to_string(),
+                            false => format!(
+                                "Network: {:.1} MiB ahead",
+                                buffering.ahead as f64 / (1024.0 * 1024.0)
+                            ),
+                        })
+                    });
+                    let track_info = match track {
+                        Some(track) => Paragraph::new(Text::from_iter(
+                            [
+                                Line::from(format!(
+                                    "Title: {}",
+                                    track.details().title().unwrap_or_default()
+                                )),
+                                Line::from(format!(
+                                    "Artist: {}",
+                                    track.details().artist().unwrap_or_default()
+                                )),
+                                Line::from(format!(
+                                    "Album: {}",
+                                    track.details().album().unwrap_or_default()
+                                )),
+                                Line::from(format!(
+                                    "Format: {}",
+                                    track.stream_info()
+                                )),
+                                Line::from(volume),
+                                Line::from(normalization),
+                                Line::from(mode),
+                                Line::from(speed),
+                                Line::from(equalizer),
+                            ]
+                            .into_iter()
+                            .chain(buffering_line)
+                            .chain(error_line),
+                        ))
+                        .block(Block::new().title(format!("Playing: {}", track.source()))),
+                        None => Paragraph::new(Text::from_iter(error_line))
+                            .block(Block::new().title("Stopped")),
+                    };
+
+                    let queue_info = Paragraph::new(Text::from(
+                        queue
+                            .iter()
+                            .enumerate()
+                            .map(|(i, item)| {
+                                let name = match item.details().title() {
+                                    Some(title) => title.to_string(),
+                                    None => item.source().to_string(),
+                                };
+                                if Some(i) == current_index {
+                                    Line::from(format!("> {}", name)).bold()
+                                } else {
+                                    Line::from(format!("  {}", name))
+                                }
+                            })
+                            .collect::<Vec<_>>(),
+                    ))
+                    .block(Block::new().title("Queue"));
+
+                    let progress_info = Text::raw(format!(
+                        "[{:02}:{:02}:{:06.3} / {:02}:{:02}:{:06.3}]",
+                        position.as_millis() / 3600_000,
+                        (position.as_millis() % 3600_000) / 60_000,
+                        (position.as_millis() % 60_000) as f64 / 1000.0,
+                        duration.as_millis() / 3600_000,
+                        (duration.as_millis() % 3600_000) / 60_000,
+                        (duration.as_millis() % 60_000) as f64 / 1000.0
+                    ))
+                    .centered();
+
+                    let layout = Layout::vertical([
+                        Constraint::Fill(1),
+                        Constraint::Length(2),
+                        Constraint::Length(1),
+                    ])
+                    .split(frame.area());
+                    let info_layout =
+                        Layout::horizontal([Constraint::Fill(1), Constraint::Fill(1)])
+                            .split(layout[0]);
+                    seekbar_rect = Some(layout[1]);
+                    frame.render_widget(track_info, info_layout[0]);
+                    frame.render_widget(queue_info, info_layout[1]);
+                    frame.render_widget(progress_bar, layout[1]);
+                    frame.render_widget(progress_info, layout[2]);
+                })?;
+            }
+
+            if event::poll(Duration::from_millis(1000 / FPS))? {
+                redraw = true;
+                match event::read()? {
+                    Event::Key(key) => match key.kind {
+                        KeyEventKind::Press => match key.code {
+                            KeyCode::Char('q') => break Ok(()),
+                            KeyCode::Char('n') => self.player.controller().next(),
+                            KeyCode::Char('p') => self.player.controller().previous(),
+                            KeyCode::Char('+') | KeyCode::Char('=') => {
+                                let controller = self.player.controller();
+                                controller.set_volume(controller.volume() + VOLUME_STEP);
+                            }
+                            KeyCode::Char('-') => {
+                                let controller = self.player.controller();
+                                controller.set_volume(controller.volume() - VOLUME_STEP);
+                            }
+                            KeyCode::Char('m') => {
+                                let controller = self.player.controller();
+                                controller.set_muted(!controller.muted());
+                            }
+                            KeyCode::Char('r') => {
+                                let controller = self.player.controller();
+                                controller.set_repeat(match controller.repeat() {
+                                    RepeatMode::Off => RepeatMode::All,
+                                    RepeatMode::All => RepeatMode::One,
+                                    RepeatMode::One => RepeatMode::Off,
+                                });
+                            }
+                            KeyCode::Char('g') => {
+                                let controller = self.player.controller();
+                                controller.set_normalization(match controller.normalization() {
+                                    NormalizationMode::Off => NormalizationMode::Auto,
+                                    NormalizationMode::Auto => NormalizationMode::Track,
+                                    NormalizationMode::Track => NormalizationMode::Album,
+                                    NormalizationMode::Album => NormalizationMode::Off,
+                                });
+                            }
+                            KeyCode::Char('s') => {
+                                let controller = self.player.controller();
+                                controller.set_shuffle(!controller.shuffle());
+                            }
+                            KeyCode::Char(']') => {
+                                let controller = self.player.controller();
+                                controller.set_speed(controller.speed() + SPEED_STEP);
+                            }
+                            KeyCode::Char('[') => {
+                                let controller = self.player.controller();
+                                controller.set_speed(controller.speed() - SPEED_STEP);
+                            }
+                            KeyCode::Char('k') => {
+                                let controller = self.player.controller();
+                                controller.set_speed_mode(match controller.speed_mode() {
+                                    SpeedMode::Varispeed => SpeedMode::PreservePitch,
+                                    SpeedMode::PreservePitch => SpeedMode::Varispeed,
+                                });
+                            }
+                            KeyCode::Char('e') => {
+                                let index = EqualizerPreset::ALL
+                                    .iter()
+                                    .position(|p| *p == preset)
+                                    .unwrap_or_default();
+                                preset =
+                                    EqualizerPreset::ALL[(index + 1) % EqualizerPreset::ALL.len()];
+                                self.player
+                                    .controller()
+                                    .set_equalizer(Equalizer::preset(preset));
+                            }
+                            KeyCode::Char(' ') => {
+                                if self.player.controller().playing() {
+                                    self.player.controller().pause();
+                                } else {
+                                    self.player.controller().play();
+                                }
+                            }
+                            _ => (),
+                        },
+                        _ => (),
+                    },
+                    Event::Mouse(mouse) => match mouse.kind {
+                        event::MouseEventKind::Up(MouseButton::Left) => {
+                            if let Some(rect) = seekbar_rect {
+                                if rect.contains(Position {
+                                    x: mouse.column,
+                                    y: mouse.row,
+                                }) {
+                                    drag_progress = None;
+                                    let seek_position = mouse.column as f64 / rect.width as f64
+                                        * duration.as_secs_f64();
+                                    self.player
+                                        .controller()
+                                        .seek(Duration::from_secs_f64(seek_position))
+                                }
+                            }
+                        }
+                        event::MouseEventKind::Drag(MouseButton::Left) => {
+                            if let Some(rect) = seekbar_rect {
+                                if rect.contains(Position {
+                                    x: mouse.column,
+                                    y: mouse.row,
+                                }) {
+                                    let seek_position = mouse.column as f64 / rect.width as f64
+                                        * duration.as_secs_f64();
+                                    drag_progress = Some(Duration::from_secs_f64(seek_position));
+                                }
+                            }
+                        }
+                        _ => (),
+                    },
+                    _ => (),
+                };
+            };
+        }
+    }
+}
@@ -8,17 +8,24 @@ use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
 struct CliArgs {
-    file: PathBuf,
+    /// Files or `http://` and `https://` URLs to play
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
     #[arg(short, long, default_value_t = true, action=ArgAction::SetFalse)]
     progress_bar: bool,
+    /// Output device name or id, the default device if not given
+    #[arg(short, long)]
+    device: Option<String>,
 }
 
 fn main() -> Result<()> {
     color_eyre::install()?;
     let args = CliArgs::parse();
 
-    let mut app = AudioPlayerApplication::new();
-    app.open(args.file)?;
+    let mut app = AudioPlayerApplication::new(args.device);
+    for file in args.files {
+        app.open(file)?;
+    }
 
     let mut terminal = ratatui::init_with_options(ratatui::TerminalOptions {
         viewport: ratatui::Viewport::Fullscreen,